@@ -0,0 +1,61 @@
+//! Infers the schema of an existing collection from a random sample of its
+//! documents. Prints Rust source code of the corresponding `Doc` type,
+//! preceded by the inferred `$jsonSchema` in a comment.
+//!
+//! Usage:
+//! ```text
+//! avocado-schema <mongodb-uri> <database> <collection> [sample-size]
+//! ```
+
+extern crate avocado;
+extern crate serde_json;
+
+use std::env;
+use std::process;
+use std::error::Error;
+use avocado::prelude::*;
+use avocado::infer::InferredSchema;
+
+/// The number of documents sampled if not specified otherwise.
+const DEFAULT_SAMPLE_SIZE: usize = 1000;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("Usage: avocado-schema <mongodb-uri> <database> <collection> [sample-size]");
+        process::exit(2);
+    }
+
+    if let Err(error) = run(&args[0], &args[1], &args[2], args.get(3)) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+/// Samples the collection and prints the inferred schema.
+fn run(uri: &str, db_name: &str, coll_name: &str, size_arg: Option<&String>) -> Result<(), Box<dyn Error>> {
+    let sample_size = match size_arg {
+        Some(size) => size.parse()?,
+        None => DEFAULT_SAMPLE_SIZE,
+    };
+    let client = Client::with_uri(uri)?;
+    let db = client.db(db_name);
+    let schema = InferredSchema::sample(&db, coll_name, sample_size)?;
+    let json_schema = serde_json::to_string_pretty(&schema.to_json_schema())?;
+
+    println!("// Inferred from {} documents sampled from `{}.{}`.", schema.num_documents(), db_name, coll_name);
+    println!("//");
+    println!("// $jsonSchema:");
+
+    for line in json_schema.lines() {
+        println!("// {}", line);
+    }
+
+    println!();
+    println!("use avocado::prelude::*;");
+    println!();
+    print!("{}", schema.to_rust_source(coll_name));
+
+    Ok(())
+}
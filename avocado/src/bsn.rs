@@ -90,6 +90,162 @@ impl BsonExt for Bson {
     }
 }
 
+/// Looks up a potentially nested value in a document, based on a path in
+/// MongoDB's "dot notation", e.g. `"address.city"`. Returns `None` if any
+/// of the path segments is missing or refers into a non-document value.
+pub fn lookup_path<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut segments = path.split('.');
+    let first = doc.get(segments.next()?)?;
+
+    segments.try_fold(first, |value, segment| match *value {
+        Bson::Document(ref inner) => inner.get(segment),
+        _ => None,
+    })
+}
+
+/// Appends a key or an array index to a path in dot notation.
+pub fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Combines two filter documents so that both of them must match.
+/// Avoids a redundant `$and` if either of them is empty.
+pub fn and_filters(lhs: Document, rhs: Document) -> Document {
+    if lhs.is_empty() {
+        rhs
+    } else if rhs.is_empty() {
+        lhs
+    } else {
+        doc!{ "$and": [lhs, rhs] }
+    }
+}
+
+/// Removes `field` from the arguments of the given update operators
+/// (e.g. `$set`) in `update`, dropping operators which become empty.
+pub fn remove_from_operators(update: &mut Document, operators: &[&str], field: &str) {
+    for &op in operators {
+        let emptied = match update.get_mut(op) {
+            Some(&mut Bson::Document(ref mut fields)) => {
+                fields.remove(field);
+                fields.is_empty()
+            }
+            _ => false,
+        };
+
+        if emptied {
+            update.remove(op);
+        }
+    }
+}
+
+/// Adds `field` with `value` to the arguments of the update operator `op`
+/// (e.g. `$set`) in `update`, creating the operator if necessary.
+pub fn insert_into_operator(update: &mut Document, op: &str, field: &str, value: Bson) {
+    let mut fields = match update.remove(op) {
+        Some(Bson::Document(fields)) => fields,
+        _ => Document::new(),
+    };
+
+    fields.insert(field, value);
+    update.insert(op, fields);
+}
+
+/// Computes a minimal update document (consisting of `$set` and `$unset`
+/// operators) which, when applied to `old`, yields `new`. Changes inside
+/// embedded documents are expressed using dot-notation paths, so that only
+/// the modified leaves are overwritten. Arrays are always replaced as a whole.
+///
+/// Returns an empty document if `old` and `new` are equal.
+pub fn diff_documents(old: &Document, new: &Document) -> Document {
+    let mut set = Document::new();
+    let mut unset = Document::new();
+
+    diff_documents_at(None, old, new, &mut set, &mut unset);
+
+    let mut update = Document::new();
+
+    if !set.is_empty() {
+        update.insert("$set", set);
+    }
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+
+    update
+}
+
+/// Helper for `diff_documents()`. Records differences between the (possibly
+/// embedded) documents `old` and `new`, found at `prefix`, in `set`/`unset`.
+fn diff_documents_at(
+    prefix: Option<&str>,
+    old: &Document,
+    new: &Document,
+    set: &mut Document,
+    unset: &mut Document,
+) {
+    let path_to = |key: &str| match prefix {
+        Some(parent) => format!("{}.{}", parent, key),
+        None => key.to_owned(),
+    };
+
+    for (key, new_value) in new {
+        match (old.get(key), new_value) {
+            (Some(old_value), _) if old_value == new_value => {}
+            (Some(&Bson::Document(ref old_doc)), &Bson::Document(ref new_doc))
+                if is_path_safe(old_doc) && is_path_safe(new_doc) => {
+                diff_documents_at(Some(path_to(key).as_str()), old_doc, new_doc, set, unset);
+            }
+            _ => {
+                set.insert(path_to(key), new_value.clone());
+            }
+        }
+    }
+
+    for key in old.keys().filter(|key| !new.contains_key(key)) {
+        unset.insert(path_to(key), "");
+    }
+}
+
+/// Flattens the embedded documents of `doc` into dot-notation paths leading to
+/// their leaves, e.g. for building an equality filter which doesn't require
+/// the rest of an embedded document to match. `null` values are omitted.
+/// Arrays, and embedded documents with keys unsuitable for a path, are kept
+/// as a whole.
+pub fn flatten_document(doc: &Document) -> Document {
+    let mut flat = Document::new();
+
+    flatten_document_at("", doc, &mut flat);
+
+    flat
+}
+
+/// Helper for `flatten_document()`. Inserts the non-`null` leaves of the
+/// (possibly embedded) document `doc`, found at `prefix`, into `flat`.
+fn flatten_document_at(prefix: &str, doc: &Document, flat: &mut Document) {
+    for (key, value) in doc {
+        match *value {
+            Bson::Null => {}
+            Bson::Document(ref inner) if is_path_safe(inner) => {
+                flatten_document_at(&join_path(prefix, key), inner, flat);
+            }
+            _ => {
+                flat.insert(join_path(prefix, key), value.clone());
+            }
+        }
+    }
+}
+
+/// Returns `true` if none of the keys of `doc` would be misinterpreted if
+/// they were part of a dot-notation path, i.e. they are non-empty, don't
+/// contain dots, and don't start with a dollar sign.
+fn is_path_safe(doc: &Document) -> bool {
+    doc.keys().all(|key| !key.is_empty() && !key.contains('.') && !key.starts_with('$'))
+}
+
 /// Creates a BSON `Document` out of a serializable value.
 pub fn serialize_document<T: Serialize>(value: &T) -> Result<Document> {
     serde_json::to_value(value)
@@ -169,6 +325,91 @@ mod tests {
         assert_eq!(Bson::String("hello world".into()).try_as_bool(), None);
     }
 
+    #[test]
+    fn lookup_nested_path() {
+        let doc = doc!{
+            "name": "Alice",
+            "address": {
+                "city": "Budapest",
+                "geo": { "lat": 47.5 },
+            },
+        };
+
+        assert_eq!(lookup_path(&doc, "name"), Some(&Bson::from("Alice")));
+        assert_eq!(lookup_path(&doc, "address.city"), Some(&Bson::from("Budapest")));
+        assert_eq!(lookup_path(&doc, "address.geo.lat"), Some(&Bson::FloatingPoint(47.5)));
+        assert_eq!(lookup_path(&doc, "address.zip"), None);
+        assert_eq!(lookup_path(&doc, "name.first"), None);
+        assert_eq!(lookup_path(&doc, "bogus"), None);
+    }
+
+    #[test]
+    fn diff_nested_documents() {
+        let old = doc!{
+            "_id": 1,
+            "name": "Alice",
+            "nickname": "Al",
+            "tags": ["a", "b"],
+            "address": {
+                "city": "Budapest",
+                "geo": { "lat": 47.5, "lng": 19.0 },
+            },
+            "weird": { "a.b": 1 },
+        };
+
+        assert_eq!(diff_documents(&old, &old), Document::new());
+
+        let new = doc!{
+            "_id": 1,
+            "name": "Alice",
+            "tags": ["a", "c"],
+            "address": {
+                "city": "Budapest",
+                "geo": { "lat": 47.4, "lng": 19.0 },
+                "zip": "1011",
+            },
+            "weird": { "a.b": 2 },
+            "age": 42,
+        };
+
+        assert_eq!(diff_documents(&old, &new), doc!{
+            "$set": {
+                "tags": ["a", "c"],
+                "address.geo.lat": 47.4,
+                "address.zip": "1011",
+                "weird": { "a.b": 2 },
+                "age": 42,
+            },
+            "$unset": {
+                "nickname": "",
+            },
+        });
+    }
+
+    #[test]
+    fn flatten_nested_document() {
+        let doc = doc!{
+            "name": "Alice",
+            "nickname": null,
+            "tags": ["a", { "b": null }],
+            "address": {
+                "city": "Budapest",
+                "zip": null,
+                "geo": { "lat": 47.5 },
+            },
+            "contact": { "email": null },
+            "weird": { "a.b": 1 },
+        };
+
+        assert_eq!(flatten_document(&doc), doc!{
+            "name": "Alice",
+            "tags": ["a", { "b": null }],
+            "address.city": "Budapest",
+            "address.geo.lat": 47.5,
+            "weird": { "a.b": 1 },
+        });
+    }
+
     #[test]
     fn serialize_one_document() -> Result<()> {
         #[derive(Serialize)]
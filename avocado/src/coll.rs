@@ -11,13 +11,17 @@ use std::hash::{ Hash, Hasher };
 use std::fmt::{ Debug, Formatter, Result as FmtResult };
 use serde::Deserialize;
 use bson::{ Bson, Document, from_bson };
+use mongodb::{ CommandType, db::ThreadedDatabase };
 use mongodb::coll::options::{
+    IndexModel,
+    WriteModel,
     UpdateOptions,
     FindOneAndDeleteOptions,
     FindOneAndUpdateOptions,
     ReturnDocument,
 };
 use mongodb::coll::results::UpdateResult;
+use mongodb::coll::error::WriteException;
 use typemap::Key;
 use crate::{
     cursor::Cursor,
@@ -26,7 +30,14 @@ use crate::{
     ops::*,
     bsn::*,
     utils::*,
-    error::{ Error, ErrorKind::{ MissingId, BsonDecoding }, Result, ResultExt },
+    version::Version,
+    timestamp::{ stamp_insert, stamp_replacement, stamp_update, stamp_upsert },
+    error::{
+        Error, ErrorKind::{
+            MissingId, BsonDecoding, InvalidConfiguration, MongoDbError, ConcurrentModification,
+        },
+        Result, ResultExt,
+    },
 };
 
 /// A statically-typed (homogeneous) `MongoDB` collection.
@@ -38,17 +49,57 @@ pub struct Collection<T: Doc> {
 }
 
 impl<T: Doc> Collection<T> {
+    /// Returns the full name of the collection, including the database name,
+    /// e.g. `"my_db.my_collection"`.
+    pub fn namespace(&self) -> &str {
+        &self.inner.namespace
+    }
+
     /// Creates indexes on the underlying `MongoDB` collection
     /// according to the given index specifications.
     pub fn create_indexes(&self) -> Result<()> {
         let indexes = T::indexes();
+        let extended_options = T::extended_index_options();
+
         if indexes.is_empty() {
             Ok(())
-        } else {
+        } else if extended_options.iter().all(Document::is_empty) {
             self.inner
                 .create_indexes(indexes)
                 .map(drop)
                 .chain(|| format!("can't create indexes on {}", T::NAME))
+        } else {
+            self.create_extended_indexes(&indexes, extended_options)
+                .chain(|| format!("can't create indexes on {}", T::NAME))
+        }
+    }
+
+    /// Creates indexes with options unknown to the driver by running the
+    /// `createIndexes` command directly.
+    fn create_extended_indexes(&self, indexes: &[IndexModel], extended_options: Vec<Document>) -> Result<()> {
+        let mut options = extended_options.into_iter();
+        let mut specs = Vec::with_capacity(indexes.len());
+
+        for index in indexes {
+            let mut spec = index.to_bson()?;
+
+            for (key, value) in options.next().into_iter().flatten() {
+                spec.insert(key, value);
+            }
+
+            specs.push(Bson::from(spec));
+        }
+
+        let command = doc! {
+            "createIndexes": self.inner.name(),
+            "indexes": specs,
+        };
+        let reply = self.inner.db.command(command, CommandType::CreateIndexes, None)?;
+
+        if reply.get("ok").and_then(Bson::try_as_bool).unwrap_or(false) {
+            Ok(())
+        } else {
+            Err(Error::new(MongoDbError, format!("createIndexes failed: {}", reply)))
         }
     }
 
@@ -59,8 +110,10 @@ impl<T: Doc> Collection<T> {
 
     /// Returns the number of documents matching the query criteria.
     pub fn count<Q: Count<T>>(&self, query: Q) -> Result<usize> {
+        let filter = live_filter::<T>(query.filter(), query.include_deleted());
+
         self.inner
-            .count(query.filter().into(), query.options().into())
+            .count(filter.into(), query.options().into())
             .chain(|| format!("error in {}::count({:#?})", T::NAME, query))
             .and_then(|n| int_to_usize_with_msg(n, "# of counted documents"))
     }
@@ -70,8 +123,10 @@ impl<T: Doc> Collection<T> {
         where Q: Distinct<T>,
               C: FromIterator<Q::Output>,
     {
+        let filter = live_filter::<T>(query.filter(), query.include_deleted());
+
         self.inner
-            .distinct(Q::FIELD, query.filter().into(), query.options().into())
+            .distinct(Q::FIELD, filter.into(), query.options().into())
             .chain(|| format!("error in {}::distinct({:#?})", T::NAME, query))
             .and_then(|values| {
                 values
@@ -96,29 +151,41 @@ impl<T: Doc> Collection<T> {
         // This uses `impl Deserialize for Option<T> where T: Deserialize`
         // and the fact that in MongoDB, top-level documents are always
         // `Document`s and never `Null`.
+        let filter = live_filter::<T>(query.filter(), query.include_deleted());
+
         self.inner
-            .find_one(query.filter().into(), query.options().into())
+            .find_one(filter.into(), query.options().into())
             .chain(|| format!("error in {}::find_one({:#?})", T::NAME, query))
-            .and_then(|opt| opt.map_or(Ok(None), |doc| {
-                let transformed = Q::transform(doc)?;
-                from_bson(transformed).map_err(From::from)
-            }))
+            .and_then(|opt| load_found(opt, Q::transform, Q::after_load))
     }
 
     /// Retrieves all documents satisfying the query.
     pub fn find_many<Q: Query<T>>(&self, query: Q) -> Result<Cursor<Q::Output>> {
+        let filter = live_filter::<T>(query.filter(), query.include_deleted());
+
         self.inner
-            .find(query.filter().into(), query.options().into())
+            .find(filter.into(), query.options().into())
             .chain(|| format!("error in {}::find_many({:#?})", T::NAME, query))
-            .map(|crs| Cursor::from_cursor_and_transform(crs, Q::transform))
+            .map(|crs| {
+                Cursor::from_cursor_and_transform(crs, Q::transform).with_after_load(Q::after_load)
+            })
     }
 
     /// Inserts a single document.
+    ///
+    /// The entity is checked using `Doc::validate_entity()` first. Then the
+    /// `Doc::before_insert()` hook may modify the document or veto the
+    /// insertion, and `Doc::after_insert()` is invoked upon success.
     pub fn insert_one(&self, entity: &T) -> Result<Uid<T>> {
-        let doc = serialize_document(entity)?;
+        entity.validate_entity()?;
+
+        let mut doc = serialize_document(entity)?;
         let write_concern = T::insert_options().write_concern;
         let message = || format!("error in {}::insert_one()", T::NAME);
 
+        entity.before_insert(&mut doc)?;
+        stamp_insert::<T>(&mut doc);
+
         self.inner
             .insert_one(doc, write_concern)
             .chain(&message)
@@ -133,6 +200,7 @@ impl<T: Doc> Collection<T> {
                     Err(Error::new(MissingId, message() + ": missing `inserted_id`"))
                 }
             })
+            .and_then(|id| entity.after_insert().map(|()| id))
     }
 
     /// Inserts many documents.
@@ -144,6 +212,11 @@ impl<T: Doc> Collection<T> {
     /// for IDs which couldn't be deserialized.
     ///
     /// The context map can be accessed as: `error.context::<InsertManyErrorContext<T>>()`
+    ///
+    /// Every entity is validated, and the `Doc::before_insert()` and
+    /// `Doc::after_insert()` hooks are invoked on it, just like with
+    /// `insert_one()`. If any entity is invalid or any `before_insert()`
+    /// hook fails, none of the documents are inserted.
     pub fn insert_many<I>(&self, entities: I) -> Result<BTreeMap<u64, Uid<T>>>
         where I: IntoIterator,
               I::Item: Borrow<T>,
@@ -151,12 +224,19 @@ impl<T: Doc> Collection<T> {
               T::Id: Clone + Debug,
               T: 'static,
     {
-        let values = entities.into_iter();
+        let values: Vec<_> = entities.into_iter().collect();
         let n_docs = values.len();
-        let docs = serialize_documents(values)?;
+        let mut docs = serialize_documents::<T, _>(values.iter().map(|value| -> &T { value.borrow() }))?;
         let options = T::insert_options();
         let message = || format!("error in {}::insert_many()", T::NAME);
 
+        for (value, doc) in values.iter().zip(&mut docs) {
+            let entity: &T = value.borrow();
+            entity.validate_entity()?;
+            entity.before_insert(doc)?;
+            stamp_insert::<T>(doc);
+        }
+
         // MongoDB complains if you try to insert 0 documents, but that's silly.
         if n_docs == 0 {
             return Ok(BTreeMap::new());
@@ -207,12 +287,34 @@ impl<T: Doc> Collection<T> {
                         .with_context::<InsertManyErrorContext<T>>(ids))
                 }
             })
+            .and_then(|ids| {
+                for value in &values {
+                    let entity: &T = value.borrow();
+                    entity.after_insert()?;
+                }
+                Ok(ids)
+            })
     }
 
     /// Convenience method for updating a single document based on identity (its
     /// `_id` field), setting all fields to the values supplied by `entity`.
     ///
     /// This doesn't add a new document if none with the specified `_id` exists.
+    ///
+    /// If `T::CREATED_AT_FIELD` is set, the creation timestamp carried by
+    /// `entity` is written as-is, even if it is `None`. Therefore, an entity
+    /// which was loaded without its creation timestamp (e.g. through a
+    /// projection) should be written using `upsert_entity()` or
+    /// `update_entity_diff()` instead. The modification timestamp is always set.
+    ///
+    /// If `T::VERSION_FIELD` is set, the stored document is only replaced if
+    /// its version equals that of `entity`, and the stored version is then
+    /// incremented. (`entity` itself is not modified.) If no document with
+    /// the same `_id` and version exists, an error of kind
+    /// `ErrorKind::ConcurrentModification` is returned.
+    ///
+    /// The entity is checked using `Doc::validate_entity()`, and the
+    /// `Doc::before_update()` and `Doc::after_update()` hooks are invoked.
     pub fn replace_entity(&self, entity: &T) -> Result<UpdateOneResult> where T: Debug {
         self.update_entity_internal(entity, false)
             .and_then(UpdateOneResult::from_raw)
@@ -222,6 +324,21 @@ impl<T: Doc> Collection<T> {
     /// `_id` field), setting all fields to the values supplied by `entity`.
     ///
     /// This method adds a new document if none with the specified `_id` exists.
+    ///
+    /// Unlike `replace_entity()`, this is performed as an update which sets
+    /// every field serialized from `entity`, so fields missing from the
+    /// serialized entity are left unchanged in an existing document. If
+    /// `T::CREATED_AT_FIELD` is set, the creation timestamp is only written
+    /// when a new document is inserted, using the one carried by `entity`,
+    /// or the current date if it has none.
+    ///
+    /// If `T::VERSION_FIELD` is set, an existing document is only replaced if
+    /// its version equals that of `entity`, otherwise an error of kind
+    /// `ErrorKind::ConcurrentModification` is returned. The stored version
+    /// is incremented upon success.
+    ///
+    /// The entity is checked using `Doc::validate_entity()`, and the
+    /// `Doc::before_update()` and `Doc::after_update()` hooks are invoked.
     pub fn upsert_entity(&self, entity: &T) -> Result<UpsertOneResult<Uid<T>>> where T: Debug {
         self.update_entity_internal(entity, true)
             .and_then(UpsertOneResult::from_raw)
@@ -231,11 +348,17 @@ impl<T: Doc> Collection<T> {
     fn update_entity_internal(&self, entity: &T, upsert: bool) -> Result<UpdateResult>
         where T: Debug
     {
+        entity.validate_entity()?;
+
         let mut document = serialize_document(entity)?;
+
+        entity.before_update(&mut document)?;
+
         let id = document.remove("_id").ok_or_else(
             || Error::new(MissingId, format!("No `_id` in entity of type {}", T::NAME))
         )?;
-        let filter = doc!{ "_id": id };
+        let mut filter = doc!{ "_id": id.clone() };
+        let version = Version::of::<T>(&document)?;
         let options = UpdateOptions {
             upsert: upsert.into(),
             write_concern: T::update_options().into(),
@@ -245,24 +368,106 @@ impl<T: Doc> Collection<T> {
                                  if upsert { "upsert" } else { "replace" },
                                  entity);
 
-        self.inner
-            .replace_one(filter, document, options.into())
+        if let Some(ref version) = version {
+            version.restrict(&mut filter);
+            version.bump_document(&mut document);
+        }
+
+        let result = if upsert {
+            let mut update = doc!{ "$set": document };
+            stamp_upsert::<T>(&mut update);
+            self.inner.update_one(filter, update, options.into())
+        } else {
+            stamp_replacement::<T>(&mut document);
+            self.inner.replace_one(filter, document, options.into())
+        };
+
+        result
             .chain(&message)
+            .and_then(|mut result| match (result.write_exception.take(), version) {
+                // A stale upsert doesn't match the existing document,
+                // so it tries to insert one with a duplicate `_id`.
+                (Some(ref error), Some(ref version)) if upsert && is_duplicate_key(error) => {
+                    Err(version.conflict::<T>(&id))
+                }
+                (Some(error), _) => Err(Error::with_cause(message(), error)),
+                (None, Some(ref version)) if !upsert && result.matched_count == 0 => {
+                    Err(version.conflict::<T>(&id))
+                }
+                (None, _) => Ok(result),
+            })
             .and_then(|result| {
-                if let Some(error) = result.write_exception {
-                    Err(Error::with_cause(message(), error))
-                } else {
-                    Ok(result)
+                if result.matched_count > 0 || result.upserted_id.is_some() {
+                    entity.after_update()?;
                 }
+                Ok(result)
             })
     }
 
+    /// Convenience method for updating a single document based on identity (its
+    /// `_id` field), only modifying the fields which differ between `old` and
+    /// `new`, using `$set` and `$unset` operators. Unlike `replace_entity()`,
+    /// this doesn't overwrite fields changed concurrently by other writers,
+    /// as long as they don't touch the same fields.
+    ///
+    /// The document is looked up by the `_id` of `old`. If `old` and `new`
+    /// serialize to the same document, no request is sent to the database,
+    /// and the returned result has `matched` and `modified` both set to false.
+    ///
+    /// If `T::VERSION_FIELD` is set, the update only succeeds if the stored
+    /// version equals that of `old`, and it increments the stored version.
+    /// Otherwise, an error of kind `ErrorKind::ConcurrentModification`
+    /// is returned.
+    ///
+    /// If anything changed, `new` is checked using `Doc::validate_entity()`,
+    /// and the `Doc::before_update()` and `Doc::after_update()` hooks are
+    /// invoked. The former receives the serialized `new`, before it's compared to `old`.
+    pub fn update_entity_diff(&self, old: &T, new: &T) -> Result<UpdateOneResult> where T: Debug {
+        let old_doc = serialize_document(old)?;
+        let mut change = match entity_diff(&old_doc, new)? {
+            Some((_, change)) => change,
+            None => return Ok(UpdateOneResult::default()),
+        };
+
+        let id = old_doc.get("_id").cloned().ok_or_else(
+            || Error::new(MissingId, format!("No `_id` in entity of type {}", T::NAME))
+        )?;
+        let mut filter = doc!{ "_id": id.clone() };
+        let version = Version::of::<T>(&old_doc)?;
+        let options = UpdateOptions {
+            upsert: Some(false),
+            write_concern: T::update_options().into(),
+        };
+        let message = || format!("error in {}::update_entity_diff({:#?})", T::NAME, new);
+
+        if let Some(ref version) = version {
+            version.restrict(&mut filter);
+            version.bump_update(&mut change);
+        }
+
+        let result = self.update_one_internal(filter, change, options, &message)
+            .and_then(UpdateOneResult::from_raw)?;
+
+        match version {
+            Some(ref version) if !result.matched => Err(version.conflict::<T>(&id)),
+            _ => {
+                if result.matched {
+                    new.after_update()?;
+                }
+                Ok(result)
+            }
+        }
+    }
+
     /// Updates a single document.
     ///
     /// This method only works with update operators (with field names starting
     /// with `$`), i.e. it does **not** replace entire documents.
+    ///
+    /// Soft-deleted documents are not updated, unless `update.include_deleted()`
+    /// returns `true`.
     pub fn update_one<U: Update<T>>(&self, update: U) -> Result<UpdateOneResult> {
-        let filter = update.filter();
+        let filter = live_filter::<T>(update.filter(), update.include_deleted());
         let change = update.update();
         let options = UpdateOptions {
             upsert: Some(false),
@@ -298,10 +503,12 @@ impl<T: Doc> Collection<T> {
     fn update_one_internal<F: Copy + FnOnce() -> String>(
         &self,
         filter: Document,
-        change: Document,
+        mut change: Document,
         options: UpdateOptions,
         message: F,
     ) -> Result<UpdateResult> {
+        stamp_update::<T>(&mut change, options.upsert.unwrap_or(false));
+
         self.inner
             .update_one(filter, change, options.into())
             .chain(message)
@@ -318,8 +525,11 @@ impl<T: Doc> Collection<T> {
     ///
     /// This method only works with update operators (with field names starting
     /// with `$`), i.e. it does **not** replace entire documents.
+    ///
+    /// Soft-deleted documents are not updated, unless `update.include_deleted()`
+    /// returns `true`.
     pub fn update_many<U: Update<T>>(&self, update: U) -> Result<UpdateManyResult> {
-        let filter = update.filter();
+        let filter = live_filter::<T>(update.filter(), update.include_deleted());
         let change = update.update();
         let options = UpdateOptions {
             upsert: Some(false),
@@ -351,10 +561,12 @@ impl<T: Doc> Collection<T> {
     fn update_many_internal<F: Copy + FnOnce() -> String>(
         &self,
         filter: Document,
-        change: Document,
+        mut change: Document,
         options: UpdateOptions,
         message: F,
     ) -> Result<UpdateManyResult> {
+        stamp_update::<T>(&mut change, options.upsert.unwrap_or(false));
+
         self.inner
             .update_many(filter, change, options.into())
             .chain(message)
@@ -369,52 +581,160 @@ impl<T: Doc> Collection<T> {
             })
     }
 
+    /// Performs several single-document updates in a single bulk write
+    /// operation. The updates are applied in order, and the first failing
+    /// one prevents the rest of them from being applied.
+    ///
+    /// The write concern returned by the `options()` of the individual
+    /// updates is ignored; the bulk write uses that of the collection.
+    /// Soft-deleted documents are not updated, unless `include_deleted()`
+    /// returns `true` for the respective update.
+    pub fn bulk_update<I>(&self, updates: I) -> Result<UpdateManyResult>
+        where I: IntoIterator,
+              I::Item: Update<T>,
+    {
+        let models: Vec<_> = updates
+            .into_iter()
+            .map(|update| {
+                let mut change = update.update();
+                stamp_update::<T>(&mut change, false);
+
+                WriteModel::UpdateOne {
+                    filter: live_filter::<T>(update.filter(), update.include_deleted()),
+                    update: change,
+                    upsert: Some(false),
+                }
+            })
+            .collect();
+        let message = || format!("error in {}::bulk_update()", T::NAME);
+
+        // Just like with `insert_many()`, an empty batch is not an error.
+        if models.is_empty() {
+            return Ok(UpdateManyResult::default());
+        }
+
+        let result = self.inner.bulk_write(models, true);
+
+        if let Some(error) = result.bulk_write_exception {
+            Err(Error::with_cause(message(), error))
+        } else {
+            let num_matched = int_to_usize_with_msg(result.matched_count, "# of matched documents")?;
+            let num_modified = int_to_usize_with_msg(result.modified_count, "# of modified documents")?;
+            Ok(UpdateManyResult { num_matched, num_modified })
+        }
+    }
+
     /// Convenience method for deleting a single entity based on its identity
     /// (the `_id` field). Returns `true` if it was found and deleted.
+    ///
+    /// The `Doc::before_delete()` hook may veto the deletion, and
+    /// `Doc::after_delete()` is invoked if the entity was deleted.
+    ///
+    /// If `T::VERSION_FIELD` is set, the document is only deleted if its
+    /// version equals that of `entity`. Otherwise, an error of kind
+    /// `ErrorKind::ConcurrentModification` is returned.
     pub fn delete_entity(&self, entity: &T) -> Result<bool> where T: Debug {
-        let id = entity.id().ok_or_else(
+        let document = serialize_document(entity)?;
+        let id = document.get("_id").cloned().ok_or_else(
             || Error::new(MissingId, format!("No `_id` in entity of type {}", T::NAME))
         )?;
-        let id_bson = bson::to_bson(id)?;
+        let mut filter = doc!{ "_id": id.clone() };
+        let version = Version::of::<T>(&document)?;
+
+        if let Some(ref version) = version {
+            version.restrict(&mut filter);
+        }
+
+        entity.before_delete()?;
 
-        self.delete_one(doc!{ "_id": id_bson }).chain(
+        let deleted = self.delete_one(filter).chain(
             || format!("error in {}::delete_entity({:#?})", T::NAME, entity)
-        )
+        )?;
+
+        if let Some(ref version) = version {
+            if !deleted {
+                return Err(version.conflict::<T>(&id));
+            }
+        }
+
+        if deleted {
+            entity.after_delete()?;
+        }
+
+        Ok(deleted)
     }
 
     /// Convenience method for deleting entities based on their identity
     /// (the `_id` fields). Returns the number of deleted documents.
+    ///
+    /// If `T::VERSION_FIELD` is set, each document is only deleted if its
+    /// version equals that of the corresponding entity. If any of them didn't
+    /// match, an error of kind `ErrorKind::ConcurrentModification` is returned,
+    /// but the documents which did match are deleted nevertheless.
     pub fn delete_entities<I>(&self, entities: I) -> Result<usize>
         where I: IntoIterator,
               I::Item: Borrow<T>,
               T: Debug,
     {
-        let ids: Vec<_> = entities
+        let criteria: Vec<_> = entities
             .into_iter()
             .map(|item| {
                 let entity = item.borrow();
-                let id = entity.id().ok_or_else(|| Error::new(
+                let document = serialize_document(entity)?;
+                let id = document.get("_id").cloned().ok_or_else(|| Error::new(
                     MissingId,
                     format!("No `_id` in entity to delete: {:#?}", entity)
                 ))?;
-                bson::to_bson(id).map_err(From::from)
+                let mut criterion = doc!{ "_id": id };
+
+                if let Some(version) = Version::of::<T>(&document)? {
+                    version.restrict(&mut criterion);
+                }
+
+                Ok(Bson::Document(criterion))
             })
             .collect::<Result<_>>()?;
+        let num_entities = criteria.len();
 
-        let criterion = doc!{
-            "_id": {
-                "$in": ids
-            }
-        };
+        // Just like with `insert_many()`, an empty batch is not an error.
+        if criteria.is_empty() {
+            return Ok(0);
+        }
 
-        self.delete_many(criterion).chain(
+        let num_deleted = self.delete_many(doc!{ "$or": criteria }).chain(
             || format!("error in {}::delete_entities(...)", T::NAME)
-        )
+        )?;
+
+        match T::VERSION_FIELD {
+            Some(field) if num_deleted < num_entities => Err(Error::new(
+                ConcurrentModification,
+                format!("{} of {} {} entities were modified concurrently or don't exist \
+                         (compared by `{}`)",
+                        num_entities - num_deleted, num_entities, T::NAME, field)
+            )),
+            _ => Ok(num_deleted),
+        }
     }
 
     /// Deletes one document. Returns `true` if one was found and deleted.
+    ///
+    /// If `T::SOFT_DELETE_FIELD` is set, the document is not removed; instead,
+    /// the field is set to the current date. Already soft-deleted documents
+    /// are not affected.
     pub fn delete_one<Q: Delete<T>>(&self, query: Q) -> Result<bool> {
         let message = || format!("error in {}::delete_one({:#?})", T::NAME, query);
+
+        if let Some(field) = T::SOFT_DELETE_FIELD {
+            let filter = live_filter::<T>(query.filter(), false);
+            let options = UpdateOptions {
+                upsert: Some(false),
+                write_concern: query.options().into(),
+            };
+
+            return self.update_one_internal(filter, soft_delete_update(field), options, &message)
+                .map(|result| result.matched_count > 0);
+        }
+
         self.inner
             .delete_one(query.filter(), query.options().into())
             .chain(&message)
@@ -428,8 +748,24 @@ impl<T: Doc> Collection<T> {
     }
 
     /// Deletes many documents. Returns the number of deleted documents.
+    ///
+    /// If `T::SOFT_DELETE_FIELD` is set, the documents are not removed;
+    /// instead, the field is set to the current date. Already soft-deleted
+    /// documents are not affected.
     pub fn delete_many<Q: Delete<T>>(&self, query: Q) -> Result<usize> {
         let message = || format!("error in {}::delete_many({:#?})", T::NAME, query);
+
+        if let Some(field) = T::SOFT_DELETE_FIELD {
+            let filter = live_filter::<T>(query.filter(), false);
+            let options = UpdateOptions {
+                upsert: Some(false),
+                write_concern: query.options().into(),
+            };
+
+            return self.update_many_internal(filter, soft_delete_update(field), options, &message)
+                .map(|result| result.num_matched);
+        }
+
         self.inner
             .delete_many(query.filter(), query.options().into())
             .chain(&message)
@@ -442,10 +778,77 @@ impl<T: Doc> Collection<T> {
             })
     }
 
+    /// Undoes the soft deletion of the documents matching the query, by setting
+    /// `T::SOFT_DELETE_FIELD` to `null`. Returns the number of restored documents.
+    /// Returns an error if `T` doesn't support soft deletion.
+    pub fn restore<Q: Delete<T>>(&self, query: Q) -> Result<usize> {
+        let field = soft_delete_field::<T>("restore")?;
+        let filter = and_filters(query.filter(), deleted_filter(field));
+        let mut fields = Document::new();
+        let options = UpdateOptions {
+            upsert: Some(false),
+            write_concern: query.options().into(),
+        };
+        let message = || format!("error in {}::restore({:#?})", T::NAME, query);
+
+        fields.insert(field, Bson::Null);
+        let change = doc!{ "$set": fields };
+
+        self.update_many_internal(filter, change, options, &message)
+            .map(|result| result.num_matched)
+    }
+
+    /// Physically removes the soft-deleted documents matching the query.
+    /// Documents that are not soft-deleted are left alone. Returns the number
+    /// of removed documents. Returns an error if `T` doesn't support soft
+    /// deletion; use `delete_many()` for those types instead.
+    pub fn purge<Q: Delete<T>>(&self, query: Q) -> Result<usize> {
+        let field = soft_delete_field::<T>("purge")?;
+        let filter = and_filters(query.filter(), deleted_filter(field));
+        let message = || format!("error in {}::purge({:#?})", T::NAME, query);
+
+        self.inner
+            .delete_many(filter, query.options().into())
+            .chain(&message)
+            .and_then(|result| {
+                if let Some(error) = result.write_exception {
+                    Err(Error::with_cause(message(), error))
+                } else {
+                    int_to_usize_with_msg(result.deleted_count, "# of purged documents")
+                }
+            })
+    }
+
     /// Deletes a single document based on the query criteria,
     /// returning it if it was found.
+    ///
+    /// If `T::SOFT_DELETE_FIELD` is set, the document is soft-deleted instead,
+    /// and it is returned as it was before the deletion.
     pub fn find_one_and_delete<Q: Query<T>>(&self, query: Q) -> Result<Option<Q::Output>> {
         let query_options = query.options();
+
+        if let Some(field) = T::SOFT_DELETE_FIELD {
+            let find_update_options = FindOneAndUpdateOptions {
+                return_document: Some(ReturnDocument::Before),
+                max_time_ms: query_options.max_time_ms,
+                projection: query_options.projection,
+                sort: query_options.sort,
+                upsert: Some(false),
+                ..Default::default()
+            };
+            let filter = live_filter::<T>(query.filter(), false);
+            let mut change = soft_delete_update(field);
+
+            stamp_update::<T>(&mut change, false);
+
+            return self.inner
+                .find_one_and_update(filter, change, find_update_options.into())
+                .chain(|| format!(
+                    "error in {}::find_one_and_delete({:#?})", T::NAME, query
+                ))
+                .and_then(|opt| load_found(opt, Q::transform, Q::after_load));
+        }
+
         let find_delete_options = FindOneAndDeleteOptions {
             max_time_ms: query_options.max_time_ms,
             projection: query_options.projection,
@@ -458,13 +861,7 @@ impl<T: Doc> Collection<T> {
             .chain(|| format!(
                 "error in {}::find_one_and_delete({:#?})", T::NAME, query
             ))
-            .and_then(|opt| match opt {
-                Some(document) => {
-                    let transformed = Q::transform(document)?;
-                    from_bson(transformed).map_err(From::from)
-                }
-                None => Ok(None)
-            })
+            .and_then(|opt| load_found(opt, Q::transform, Q::after_load))
     }
 
     /// Replaces a single document based on the query criteria.
@@ -472,6 +869,9 @@ impl<T: Doc> Collection<T> {
     ///
     /// This method does **not** provide an option for returning the updated
     /// document, since it already **requires** the presence of a replacement.
+    ///
+    /// Like `replace_entity()`, this writes the creation timestamp carried by
+    /// `replacement` as-is, and sets the modification timestamp.
     pub fn find_one_and_replace<Q: Query<T>>(&self, query: Q, replacement: &T) -> Result<Option<Q::Output>>
         where T: Debug
     {
@@ -484,8 +884,10 @@ impl<T: Doc> Collection<T> {
             upsert: Some(false),
             ..Default::default()
         };
-        let filter = query.filter();
-        let doc = serialize_document(replacement)?;
+        let filter = live_filter::<T>(query.filter(), query.include_deleted());
+        let mut doc = serialize_document(replacement)?;
+
+        stamp_replacement::<T>(&mut doc);
 
         self.inner
             .find_one_and_replace(filter, doc, find_replace_options.into())
@@ -493,13 +895,7 @@ impl<T: Doc> Collection<T> {
                 "error in {}::find_one_and_replace({:#?}, {:#?})",
                 T::NAME, query, replacement
             ))
-            .and_then(|opt| match opt {
-                Some(document) => {
-                    let transformed = Q::transform(document)?;
-                    from_bson(transformed).map_err(From::from)
-                }
-                None => Ok(None)
-            })
+            .and_then(|opt| load_found(opt, Q::transform, Q::after_load))
     }
 
     /// Finds a single document based on query criteria and updates it.
@@ -508,28 +904,24 @@ impl<T: Doc> Collection<T> {
     /// separate update and upsert functions.** The options returned by the
     /// `update` argument decide whether an update or an upsert happens.
     pub fn find_one_and_update<U: FindAndUpdate<T>>(&self, update: U) -> Result<Option<U::Output>> {
-        let filter = update.filter();
-        let change = update.update();
+        let filter = live_filter::<T>(update.filter(), update.include_deleted());
+        let mut change = update.update();
         let options = update.options();
 
+        stamp_update::<T>(&mut change, options.upsert.unwrap_or(false));
+
         self.inner
             .find_one_and_update(filter, change, options.into())
             .chain(|| format!(
                 "error in {}::find_one_and_update({:#?})", T::NAME, update
             ))
-            .and_then(|opt| match opt {
-                Some(document) => {
-                    let transformed = U::transform(document)?;
-                    from_bson(transformed).map_err(From::from)
-                }
-                None => Ok(None)
-            })
+            .and_then(|opt| load_found(opt, U::transform, U::after_load))
     }
 }
 
 impl<T: Doc> Debug for Collection<T> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        write!(f, "Collection<{}>", T::NAME)
+        write!(f, "Collection<{}>", T::collection_name())
     }
 }
 
@@ -543,6 +935,95 @@ impl<T: Doc> From<mongodb::coll::Collection> for Collection<T> {
     }
 }
 
+/// Transforms and deserializes the document returned by `find_one()` or one
+/// of the `find_one_and_*()` operations, if any, then invokes `after_load`
+/// on it.
+fn load_found<O>(
+    document: Option<Document>,
+    transform: fn(Document) -> Result<Bson>,
+    after_load: fn(&mut O) -> Result<()>,
+) -> Result<Option<O>>
+    where O: for<'a> Deserialize<'a>
+{
+    let mut output: Option<O> = match document {
+        Some(doc) => from_bson(transform(doc)?)?,
+        None => None,
+    };
+
+    if let Some(ref mut value) = output {
+        after_load(value)?;
+    }
+
+    Ok(output)
+}
+
+/// Validates `entity`, and invokes its `before_update()` hook on its
+/// serialized form, which is then compared to `old`, the stored document.
+/// Returns the serialized entity and the `$set`/`$unset` update document
+/// describing the changes, or `None` if nothing changed. Nothing is validated
+/// and no hooks are invoked for unmodified entities.
+pub(crate) fn entity_diff<T: Doc>(old: &Document, entity: &T) -> Result<Option<(Document, Document)>> {
+    let mut document = serialize_document(entity)?;
+
+    if diff_documents(old, &document).is_empty() {
+        return Ok(None);
+    }
+
+    entity.validate_entity()?;
+    entity.before_update(&mut document)?;
+
+    let change = diff_documents(old, &document);
+
+    if change.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some((document, change)))
+    }
+}
+
+/// Restricts `filter` to documents which are not soft-deleted, unless
+/// `T` doesn't support soft deletion or deleted documents were requested.
+fn live_filter<T: Doc>(filter: Document, include_deleted: bool) -> Document {
+    match T::SOFT_DELETE_FIELD {
+        Some(field) if !include_deleted => {
+            let mut live = Document::new();
+            live.insert(field, Bson::Null);
+            and_filters(filter, live)
+        }
+        _ => filter,
+    }
+}
+
+/// Matches documents which are soft-deleted.
+fn deleted_filter(field: &str) -> Document {
+    let mut deleted = Document::new();
+    deleted.insert(field, doc!{ "$ne": Bson::Null });
+    deleted
+}
+
+/// The update operation marking documents as soft-deleted.
+fn soft_delete_update(field: &str) -> Document {
+    let mut fields = Document::new();
+    fields.insert(field, true);
+    doc!{ "$currentDate": fields }
+}
+
+/// Returns the soft-delete field of `T`, or an error if it has none.
+fn soft_delete_field<T: Doc>(operation: &str) -> Result<&'static str> {
+    T::SOFT_DELETE_FIELD.ok_or_else(|| Error::new(
+        InvalidConfiguration,
+        format!("{}::{}() requires a soft-delete field", T::NAME, operation)
+    ))
+}
+
+/// Returns `true` if a write failed because of a duplicate key in a unique index.
+fn is_duplicate_key(error: &WriteException) -> bool {
+    /// The MongoDB error code for duplicate key errors.
+    const DUPLICATE_KEY: i32 = 11000;
+
+    error.write_error.as_ref().map_or(false, |e| e.code == DUPLICATE_KEY)
+}
+
 /// The outcome of a successful `update_one()` operation.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UpdateOneResult {
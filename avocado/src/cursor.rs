@@ -7,12 +7,26 @@ use serde::Deserialize;
 use bson::{ Bson, Document, from_bson };
 use crate::error::{ Error, ErrorKind, Result, ResultExt };
 
+#[cfg(feature = "rayon")]
+use std::{
+    error::Error as StdError,
+    sync::mpsc::sync_channel,
+    thread,
+    panic,
+};
+#[cfg(feature = "rayon")]
+use rayon::iter::{ IntoParallelIterator, ParallelIterator };
+#[cfg(feature = "rayon")]
+use crate::error::ErrorExt;
+
 /// A typed wrapper around the MongoDB `Cursor` type.
 pub struct Cursor<T> {
     /// The underlying MongoDB cursor.
     inner: mongodb::cursor::Cursor,
     /// The function applied to each returned `Document` before deserialization.
     transform: fn(Document) -> Result<Bson>,
+    /// The function applied to each value after deserialization.
+    after_load: fn(&mut T) -> Result<()>,
     /// Just here so that the type parameter is used.
     _marker: PhantomData<T>,
 }
@@ -28,10 +42,17 @@ impl<T> Cursor<T> where T: for<'a> Deserialize<'a> {
         Cursor {
             inner,
             transform,
+            after_load: |_| Ok(()),
             _marker: PhantomData,
         }
     }
 
+    /// Sets the function invoked on each value after deserialization.
+    #[doc(hidden)]
+    pub fn with_after_load(self, after_load: fn(&mut T) -> Result<()>) -> Self {
+        Cursor { after_load, ..self }
+    }
+
     /// Reads the remaining documents available in the current batch.
     pub fn next_batch<C: FromIterator<T>>(&mut self) -> Result<C> {
         self.inner
@@ -53,21 +74,60 @@ impl<T> Cursor<T> where T: for<'a> Deserialize<'a> {
         self.inner.has_next().chain("cursor error")
     }
 
-    /// Transforms and tries to deserialize a single document.
-    fn transform_and_deserialize_one(&self, mut doc: Document) -> Result<T> {
-        // For some reason, the driver hands us back an `Ok(Document)` even if
-        // the document itself represents an error. We catch this here.
-        if let Some(Bson::String(mut errmsg)) = doc.remove("$err") {
-            if let Ok(code) = doc.get_i32("code") {
-                write!(errmsg, " (code: {})", code).ok();
-            } else if let Ok(code) = doc.get_i64("code") {
-                write!(errmsg, " (code: {})", code).ok();
+    /// Consumes the cursor and invokes `callback` on every document it
+    /// yields, processing each batch in parallel on the `rayon` thread pool.
+    ///
+    /// While the documents of the current batch are being processed, the
+    /// next batch is already being prefetched on a background thread.
+    /// The first error, be it a database error, a deserialization error,
+    /// or one returned by `callback`, stops processing and is returned;
+    /// documents of the failing batch that were already handed over to
+    /// worker threads may still be processed before that happens.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each_batch<F>(self, callback: F) -> Result<()>
+        where F: Fn(T) -> Result<()> + Sync + Send
+    {
+        let Cursor { mut inner, transform, after_load, .. } = self;
+        let (sender, receiver) = sync_channel(1);
+
+        let prefetcher = thread::spawn(move || loop {
+            let batch = match inner.drain_current_batch() {
+                Ok(ref docs) if docs.is_empty() => break,
+                Ok(docs) => Ok(docs),
+                Err(error) => Err(ThreadError::from_error(
+                    &Error::with_cause("couldn't retrieve next batch", error)
+                )),
+            };
+            let is_err = batch.is_err();
+
+            // The receiver hangs up as soon as processing fails.
+            if sender.send(batch).is_err() || is_err {
+                break;
             }
+        });
+
+        let result = receiver
+            .iter()
+            .try_for_each(|batch| batch?.into_par_iter().try_for_each(|doc| {
+                deserialize_with(transform, after_load, doc)
+                    .and_then(&callback)
+                    .map_err(|error| ThreadError::from_error(&error))
+            }))
+            .map_err(|error| Error::with_cause("parallel batch processing failed", error));
 
-            return Err(Error::new(ErrorKind::MongoDbError, errmsg));
+        // Unblock the prefetcher if it's waiting to send yet another batch.
+        drop(receiver);
+
+        if let Err(payload) = prefetcher.join() {
+            panic::resume_unwind(payload);
         }
 
-        (self.transform)(doc).and_then(|b| from_bson(b).map_err(From::from))
+        result
+    }
+
+    /// Transforms and tries to deserialize a single document.
+    fn transform_and_deserialize_one(&self, doc: Document) -> Result<T> {
+        deserialize_with(self.transform, self.after_load, doc)
     }
 
     /// Transforms and tries to deserialize a vector of documents.
@@ -99,3 +159,83 @@ impl<T> fmt::Debug for Cursor<T> where T: for<'a> Deserialize<'a> {
         f.debug_struct("Cursor").finish()
     }
 }
+
+/// Checks a raw document for server-side errors, then transforms it,
+/// tries to deserialize the result, and invokes `after_load` on it.
+fn deserialize_with<T>(
+    transform: fn(Document) -> Result<Bson>,
+    after_load: fn(&mut T) -> Result<()>,
+    mut doc: Document,
+) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+{
+    // For some reason, the driver hands us back an `Ok(Document)` even if
+    // the document itself represents an error. We catch this here.
+    if let Some(Bson::String(mut errmsg)) = doc.remove("$err") {
+        if let Ok(code) = doc.get_i32("code") {
+            write!(errmsg, " (code: {})", code).ok();
+        } else if let Ok(code) = doc.get_i64("code") {
+            write!(errmsg, " (code: {})", code).ok();
+        }
+
+        return Err(Error::new(ErrorKind::MongoDbError, errmsg));
+    }
+
+    let mut value = from_bson(transform(doc)?)?;
+    after_load(&mut value)?;
+    Ok(value)
+}
+
+/// A thread-safe snapshot of an `Error`, which itself is not `Send`.
+#[cfg(feature = "rayon")]
+#[derive(Debug, Clone)]
+struct ThreadError {
+    /// The kind of the original error.
+    kind: ErrorKind,
+    /// The original error message along with its causes.
+    message: String,
+}
+
+#[cfg(feature = "rayon")]
+impl ThreadError {
+    /// Captures the kind and the chain of messages of an `Error`.
+    fn from_error(error: &Error) -> Self {
+        let mut message = error.description().to_owned();
+        let mut cause = error.source();
+
+        while let Some(reason) = cause {
+            write!(message, ", caused by: {}", reason.description()).ok();
+            cause = reason.source();
+        }
+
+        ThreadError {
+            kind: error.kind(),
+            message,
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl fmt::Display for ThreadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl StdError for ThreadError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl ErrorExt for ThreadError {
+    fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    fn as_std_error(&self) -> &(dyn StdError + 'static) {
+        self
+    }
+}
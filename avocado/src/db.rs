@@ -1,83 +1,45 @@
 //! Represents a MongoDB database.
 
-use mongodb::db::ThreadedDatabase;
+use bson::{ Bson, Document };
+use mongodb::{ CommandType, db::ThreadedDatabase };
 use crate::{
     coll::Collection,
-    doc::Doc,
-    ext::DocumentExt,
-    error::{ ErrorKind, Result, ResultExt },
+    doc::{ Doc, CappedOptions },
+    bsn::BsonExt,
+    utils::usize_to_i64_with_msg,
+    error::{ Error, ErrorKind, Result, ResultExt },
 };
 
 #[cfg(feature = "schema_validation")]
 use magnet_schema::BsonSchema;
 #[cfg(feature = "schema_validation")]
-use crate::uid::Uid;
+use crate::{ uid::Uid, schema::collection_schema };
 
 /// Methods augmenting MongoDB `ThreadedDatabase` types.
 pub trait DatabaseExt: ThreadedDatabase {
     /// Returns an existing collection without dropping/recreating it.
     fn existing_collection<T: Doc>(&self) -> Collection<T> {
-        self.collection(T::NAME).into()
+        self.collection(&T::collection_name()).into()
     }
 
     /// Creates a fresh, empty collection. **Drops any existing collection
     /// with the same name.** Recreates the collection with the `$jsonSchema`
-    /// validator based on the `BsonSchema` impl of the document type. Also
-    /// creates indexes specified via the `T::indexes()` method.
+    /// validator based on the `BsonSchema` impl of the document type, as
+    /// returned by `schema::collection_schema()`. Also creates indexes
+    /// specified via the `T::indexes()` method.
     #[cfg(feature = "schema_validation")]
     fn empty_collection<T>(&self) -> Result<Collection<T>>
         where T: Doc + BsonSchema,
               Uid<T>: BsonSchema,
     {
-        use bson::Bson;
-        use mongodb::CommandType;
-        use crate::bsn::BsonExt;
-        use crate::error::Error;
+        self.drop_collection(&T::collection_name()).chain("error dropping collection")?;
 
-        self.drop_collection(T::NAME).chain("error dropping collection")?;
-
-        // Add the `_id` field's spec to the top-level document's BSON schema.
-        let schema = {
-            let mut schema = T::bson_schema();
-            let mut properties = schema
-                .remove_document("properties")
-                .and_then(Bson::try_into_doc)?;
-
-            if properties.contains_key("_id") {
-                let id_schema = properties.get_document("_id")?;
-
-                if
-                    *id_schema != Uid::<T>::bson_schema()
-                    &&
-                    *id_schema != Option::<Uid<T>>::bson_schema()
-                {
-                    return Err(Error::new(ErrorKind::BsonSchema, "BSON schema mismatch for _id"));
-                }
-            } else {
-                properties.insert("_id", Uid::<T>::bson_schema());
-            }
-
-            schema.insert("properties", properties);
-            schema
-        };
+        let schema = collection_schema::<T>()?;
         let command = doc! {
-            "create": T::NAME,
+            "create": T::collection_name().into_owned(),
             "validator": { "$jsonSchema": schema },
         };
-        let reply = self.command(command, CommandType::CreateCollection, None)?;
-        let err = || Error::new(
-            ErrorKind::MongoDbError,
-            format!("couldn't create {}: {}", T::NAME, reply)
-        );
-        let success = reply.get("ok").and_then(Bson::try_as_bool).ok_or_else(&err)?;
-
-        if success {
-            let coll = self.existing_collection();
-            coll.create_indexes()?;
-            Ok(coll)
-        } else {
-            Err(err())
-        }
+        create_collection(self, command)
     }
 
     /// Creates a fresh, empty collection. **Drops any existing collection
@@ -85,11 +47,66 @@ pub trait DatabaseExt: ThreadedDatabase {
     /// schema validator. Also creates indexes specified via the `T::indexes()`
     /// method.
     fn empty_collection_novalidate<T: Doc>(&self) -> Result<Collection<T>> {
-        self.drop_collection(T::NAME).chain("error dropping collection")?;
+        self.drop_collection(&T::collection_name()).chain("error dropping collection")?;
         let coll = self.existing_collection();
         coll.create_indexes()?;
         Ok(coll)
     }
+
+    /// Creates a fresh, empty capped collection, with the size limits
+    /// specified by `T::capped_options()`. **Drops any existing collection
+    /// with the same name.** Recreates the collection **without** the BSON
+    /// schema validator. Also creates indexes specified via the `T::indexes()`
+    /// method.
+    fn empty_capped_collection<T: Doc>(&self) -> Result<Collection<T>> {
+        let options = T::capped_options().ok_or_else(|| Error::new(
+            ErrorKind::InvalidConfiguration,
+            format!("no capped collection options specified for {}", T::NAME)
+        ))?;
+
+        self.empty_capped_collection_with_options(options)
+    }
+
+    /// Same as `empty_capped_collection()`, but with explicitly specified
+    /// size limits instead of the ones provided by `T::capped_options()`.
+    fn empty_capped_collection_with_options<T: Doc>(&self, options: CappedOptions) -> Result<Collection<T>> {
+        let size = usize_to_i64_with_msg(options.size, "capped collection size")?;
+        let mut command = doc! {
+            "create": T::collection_name().into_owned(),
+            "capped": true,
+            "size": size,
+        };
+
+        if let Some(max) = options.max {
+            let max_i64 = usize_to_i64_with_msg(max, "capped collection max")?;
+            command.insert("max", max_i64);
+        }
+
+        self.drop_collection(&T::collection_name()).chain("error dropping collection")?;
+        create_collection(self, command)
+    }
 }
 
 impl<T: ThreadedDatabase> DatabaseExt for T {}
+
+/// Runs a `create` command, then creates the indexes specified
+/// via the `T::indexes()` method on the new collection.
+fn create_collection<D, T>(db: &D, command: Document) -> Result<Collection<T>>
+    where D: ThreadedDatabase + ?Sized,
+          T: Doc,
+{
+    let reply = db.command(command, CommandType::CreateCollection, None)?;
+    let err = || Error::new(
+        ErrorKind::MongoDbError,
+        format!("couldn't create {}: {}", T::NAME, reply)
+    );
+    let success = reply.get("ok").and_then(Bson::try_as_bool).ok_or_else(&err)?;
+
+    if success {
+        let coll: Collection<T> = db.collection(&T::collection_name()).into();
+        coll.create_indexes()?;
+        Ok(coll)
+    } else {
+        Err(err())
+    }
+}
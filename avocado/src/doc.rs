@@ -1,5 +1,6 @@
 //! A document is a direct member of a collection.
 
+use std::borrow::Cow;
 use serde::{ Serialize, Deserialize };
 use mongodb::{
     common::WriteConcern,
@@ -13,7 +14,8 @@ use mongodb::{
         FindOneAndUpdateOptions,
     },
 };
-use crate::uid::Uid;
+use bson::Document;
+use crate::{ uid::Uid, literal::DateTimeType, error::Result };
 
 /// Implemented by top-level (direct collection member) documents only.
 /// These types always have an associated top-level name and an `_id` field.
@@ -22,15 +24,49 @@ pub trait Doc: Serialize + for<'a> Deserialize<'a> {
     /// is `ObjectId`. TODO(H2CO3): make it default to `ObjectId` (#29661).
     type Id: Eq + Serialize + for <'a> Deserialize<'a>;
 
-    /// The name of the collection within the database.
+    /// The name of the collection within the database. For types whose
+    /// collection name depends on their type arguments, this is a template
+    /// describing the name; see `collection_name()`.
     const NAME: &'static str;
 
+    /// The name of the integer field used for optimistic concurrency control,
+    /// if any. When present, entity-level writes (e.g. `replace_entity()`)
+    /// only succeed if the stored version equals that of the entity, and they
+    /// increment the stored version. Otherwise, they fail with the error kind
+    /// `ErrorKind::ConcurrentModification`.
+    const VERSION_FIELD: Option<&'static str> = None;
+
+    /// The name of the field marking soft-deleted documents, if any. When
+    /// present, deleting a document sets this field to the current date
+    /// instead of removing the document, and queries exclude documents with
+    /// a non-`null` value in this field, unless wrapped in `IncludeDeleted`.
+    const SOFT_DELETE_FIELD: Option<&'static str> = None;
+
+    /// The name of the field holding the creation time of the document, if
+    /// any. It is set upon insertion, including inserts performed by upserts.
+    const CREATED_AT_FIELD: Option<&'static str> = None;
+
+    /// The name of the field holding the time of the last modification of the
+    /// document, if any. It is set upon insertion and by every update, upsert
+    /// and replacement performed through a `Collection`.
+    const UPDATED_AT_FIELD: Option<&'static str> = None;
+
+    /// The BSON type of the creation and modification timestamps.
+    const TIMESTAMP_TYPE: DateTimeType = DateTimeType::Date;
+
     /// Get the unique ID of this document if it exists.
     fn id(&self) -> Option<&Uid<Self>>;
 
     /// Set or change the unique ID of this document.
     fn set_id(&mut self, id: Uid<Self>);
 
+    /// The actual name of the collection within the database. Defaults to
+    /// `NAME`. Generic types override it in order to give every instantiation
+    /// a distinct collection, e.g. based on the `DocName` of type arguments.
+    fn collection_name() -> Cow<'static, str> {
+        Cow::Borrowed(Self::NAME)
+    }
+
     /// Returns the specifications of the indexes created on the collection.
     /// If not provided, returns an empty vector, leading to the collection not
     /// bearing any user-defined indexes. (The `_id` field will still be
@@ -39,6 +75,15 @@ pub trait Doc: Serialize + for<'a> Deserialize<'a> {
         Vec::new()
     }
 
+    /// Index options which the `IndexOptions` type of the `mongodb` driver
+    /// can't express, e.g. `partialFilterExpression`, `collation` or
+    /// `hidden`. The `i`-th document is merged into the specification of the
+    /// `i`-th index returned by `indexes()`; missing or empty documents leave
+    /// the corresponding index unchanged. Defaults to an empty vector.
+    fn extended_index_options() -> Vec<Document> {
+        Vec::new()
+    }
+
     /// Options for a count-only query.
     fn count_options() -> CountOptions {
         Default::default()
@@ -83,4 +128,157 @@ pub trait Doc: Serialize + for<'a> Deserialize<'a> {
     fn find_and_update_options() -> FindOneAndUpdateOptions {
         Default::default()
     }
+
+    /// If the collection is to be created as a capped collection, returns
+    /// its size limits. Used by `DatabaseExt::empty_capped_collection()`.
+    /// Defaults to `None`, i.e. a regular, uncapped collection.
+    fn capped_options() -> Option<CappedOptions> {
+        None
+    }
+
+    /// Checks the entity before it is inserted, replaced or upserted by
+    /// `Collection`, before any other hook is invoked. Returning an error,
+    /// ideally of kind `ErrorKind::Validation`, vetoes the write. Does nothing
+    /// by default; see the `validation` module for a derivable implementation.
+    fn validate_entity(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked by `Collection::insert_one()` and `Collection::insert_many()`
+    /// with the serialized entity, right before it is inserted. The document
+    /// may be modified, e.g. in order to normalize values or to add derived
+    /// fields. Returning an error vetoes the insertion. Does nothing by default.
+    fn before_insert(&self, _document: &mut Document) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked after the entity was inserted successfully. An error returned
+    /// from here is reported to the caller, but the insertion isn't undone.
+    fn after_insert(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked by `Collection::replace_entity()` and `Collection::upsert_entity()`
+    /// with the serialized entity, right before it is written. The document
+    /// may be modified, and returning an error vetoes the write, just like
+    /// with `before_insert()`. Does nothing by default.
+    fn before_update(&self, _document: &mut Document) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked after the entity was replaced or upserted successfully. An error
+    /// returned from here is reported to the caller, but the write isn't undone.
+    fn after_update(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked by `Collection::delete_entity()` right before the entity is
+    /// deleted. Returning an error vetoes the deletion. Does nothing by default.
+    fn before_delete(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked after the entity was found and deleted by `delete_entity()`.
+    /// An error returned from here is reported to the caller.
+    fn after_delete(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked on every entity deserialized from the results of queries, i.e.
+    /// by `Collection::find_one()`, the `find_one_and_*()` methods and by
+    /// cursors, via `Query::after_load()` or `FindAndUpdate::after_load()`.
+    /// Those forward here for `Document` filters and `#[derive]`d queries,
+    /// but a hand-written query has to forward explicitly. Returning an error
+    /// makes loading the entity fail. Does nothing by default.
+    fn after_load(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The lifecycle hooks of a `Doc` type, for types with a `#[derive]`d `Doc`
+/// impl. Since the hook methods of a derived `Doc` impl can't be overridden,
+/// implement this trait instead and add the `#[avocado(hooks)]` attribute to
+/// the type, which makes the derived hook methods of `Doc` forward to the
+/// methods of this trait, e.g. `Doc::before_insert()` to `on_insert()`. The
+/// methods are named differently from those of `Doc`, so that calling the
+/// latter with method syntax isn't ambiguous. Every method does nothing by
+/// default.
+pub trait DocHooks: Doc {
+    /// See `Doc::before_insert()`.
+    fn on_insert(&self, _document: &mut Document) -> Result<()> {
+        Ok(())
+    }
+
+    /// See `Doc::after_insert()`.
+    fn on_inserted(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// See `Doc::before_update()`.
+    fn on_update(&self, _document: &mut Document) -> Result<()> {
+        Ok(())
+    }
+
+    /// See `Doc::after_update()`.
+    fn on_updated(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// See `Doc::before_delete()`.
+    fn on_delete(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// See `Doc::after_delete()`.
+    fn on_deleted(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// See `Doc::after_load()`.
+    fn on_load(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The name of a type used as a type argument of a generic `Doc` type, from
+/// which the collection names of instantiations of the generic type are
+/// computed. `#[derive(Doc)]` implements it by returning the collection name.
+pub trait DocName {
+    /// Returns the name of the type.
+    fn doc_name() -> Cow<'static, str>;
+}
+
+/// The payload of a newtype variant of an enum `Doc` type. Enum `Doc`s are
+/// internally tagged: all variants are stored in the same collection, and
+/// they are told apart by the value of a discriminator field. Implemented by
+/// `#[derive(Doc)]` for the payload of every newtype variant of the enum, so
+/// that a single variant can be queried via `ops::OnlyVariant`.
+pub trait DocVariant<T: Doc>: for<'a> Deserialize<'a> {
+    /// The name of the discriminator field, i.e. the `tag` of the enum.
+    const TAG_FIELD: &'static str;
+
+    /// The value of the discriminator field identifying this variant.
+    const TAG: &'static str;
+}
+
+/// Field metadata of a struct `Doc` type, implemented by `#[derive(Doc)]`.
+/// It makes it possible for other macros to check at compile time that the
+/// fields they refer to exist, e.g. the fields of a type with a `#[derive]`d
+/// `ops::Projection` impl, or the fields in a `filter!()`. Not meant to be
+/// implemented by hand.
+pub trait DocFields: Doc {
+    /// A type whose associated constants hold the serialized names of the
+    /// fields. They are named by `#[derive(Doc)]` after the Rust identifiers
+    /// of the fields, as well as after the serialized names themselves.
+    type Fields;
+}
+
+/// Size limits of a capped collection. Once either of the limits is reached,
+/// MongoDB makes room for newly-inserted documents by removing the oldest ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CappedOptions {
+    /// The maximal size of the collection, in bytes.
+    pub size: u64,
+    /// The maximal number of documents in the collection, if any.
+    pub max: Option<u64>,
 }
@@ -0,0 +1,391 @@
+//! Comparing the declared schema of a `Doc` type against the actual documents
+//! of its collection.
+//!
+//! `Collection::schema_drift()` reports the discrepancies between the schema
+//! of the collection, as returned by `schema::collection_schema()`, and the
+//! stored documents: fields which aren't declared by the schema, required
+//! fields which are missing, and values of the wrong type. This is useful
+//! e.g. for checking whether existing data is ready for a strict validator.
+//!
+//! Discrepancies are grouped by the path of the field in dot notation. The
+//! items of arrays are represented by a `$` path segment, e.g. `tags.$`.
+
+use std::fmt;
+use std::collections::BTreeMap;
+use bson::{ Bson, Document };
+use magnet_schema::BsonSchema;
+use crate::{
+    coll::Collection,
+    doc::Doc,
+    uid::Uid,
+    ops::Pipeline,
+    schema::{ collection_schema, matches_type, type_alias },
+    bsn::join_path,
+    utils::usize_to_i64_with_msg,
+    error::Result,
+};
+
+/// The maximal number of example `_id`s recorded for each discrepancy.
+const MAX_EXAMPLE_IDS: usize = 5;
+
+impl<T: Doc> Collection<T> {
+    /// Compares the documents of the collection against the schema of `T`.
+    /// If `sample_size` is `Some`, only a random sample of (at most) that many
+    /// documents is checked, otherwise the whole collection is scanned.
+    /// Soft-deleted documents are checked too.
+    pub fn schema_drift(&self, sample_size: Option<usize>) -> Result<DriftReport>
+        where T: BsonSchema,
+              Uid<T>: BsonSchema,
+    {
+        let schema = collection_schema::<T>()?;
+        let size = match sample_size {
+            Some(n) => Some(usize_to_i64_with_msg(n, "sample size")?),
+            None => None,
+        };
+        let mut report = DriftReport::default();
+
+        for document in self.aggregate(DriftPipeline { sample_size: size })? {
+            report.add_document(&schema, &document?)?;
+        }
+
+        Ok(report)
+    }
+}
+
+/// A kind of discrepancy between a schema and a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Drift {
+    /// The field is present in the document, but it isn't declared
+    /// by the schema.
+    Undeclared,
+    /// The field is required by the schema, but it is missing
+    /// from the document.
+    Missing,
+    /// The type of the value doesn't match the type declared by the schema.
+    TypeMismatch,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Drift::Undeclared => "undeclared field",
+            Drift::Missing => "missing required field",
+            Drift::TypeMismatch => "type mismatch",
+        })
+    }
+}
+
+/// Statistics about a single kind of discrepancy at a single path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DriftStats {
+    /// The number of occurrences.
+    count: usize,
+    /// The types of the offending values, keyed by their `bsonType` alias.
+    found_types: BTreeMap<&'static str, usize>,
+    /// The `_id`s of some of the offending documents.
+    example_ids: Vec<Bson>,
+}
+
+impl DriftStats {
+    /// The number of occurrences. This is usually the number of offending
+    /// documents, but the items of an array are counted individually.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The number of offending values of each type, keyed by `bsonType` alias.
+    /// Empty in the case of missing fields.
+    pub fn found_types(&self) -> &BTreeMap<&'static str, usize> {
+        &self.found_types
+    }
+
+    /// The `_id`s of the first few offending documents.
+    pub fn example_ids(&self) -> &[Bson] {
+        &self.example_ids
+    }
+}
+
+/// The result of `Collection::schema_drift()`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DriftReport {
+    /// The number of documents checked.
+    num_documents: usize,
+    /// The discrepancies found, grouped by path, then by kind.
+    fields: BTreeMap<String, BTreeMap<Drift, DriftStats>>,
+}
+
+impl DriftReport {
+    /// The number of documents checked.
+    pub fn num_documents(&self) -> usize {
+        self.num_documents
+    }
+
+    /// Returns `true` if all checked documents conform to the schema.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// The discrepancies found, grouped by path, then by kind.
+    pub fn fields(&self) -> &BTreeMap<String, BTreeMap<Drift, DriftStats>> {
+        &self.fields
+    }
+
+    /// Returns the statistics of the given kind of discrepancy at `path`.
+    pub fn get(&self, path: &str, drift: Drift) -> Option<&DriftStats> {
+        self.fields.get(path).and_then(|kinds| kinds.get(&drift))
+    }
+
+    /// Checks a top-level document against the schema of the collection.
+    fn add_document(&mut self, schema: &Document, document: &Document) -> Result<()> {
+        let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+
+        self.num_documents += 1;
+
+        DriftWalker { report: self, id: &id }.walk_object(schema, document, "")
+    }
+}
+
+impl fmt::Display for DriftReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "checked {} documents", self.num_documents)?;
+
+        for (path, kinds) in &self.fields {
+            for (drift, stats) in kinds {
+                write!(f, "`{}`: {} in {} cases", path, drift, stats.count)?;
+
+                if !stats.found_types.is_empty() {
+                    let types: Vec<_> = stats.found_types
+                        .iter()
+                        .map(|(alias, count)| format!("{} ({})", alias, count))
+                        .collect();
+
+                    write!(f, ", found {}", types.join(", "))?;
+                }
+
+                let ids: Vec<_> = stats.example_ids.iter().map(ToString::to_string).collect();
+                writeln!(f, "; e.g. _id: {}", ids.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks a document along with its schema, recording discrepancies.
+#[derive(Debug)]
+struct DriftWalker<'a> {
+    /// The report in which discrepancies are recorded.
+    report: &'a mut DriftReport,
+    /// The `_id` of the top-level document being walked.
+    id: &'a Bson,
+}
+
+impl<'a> DriftWalker<'a> {
+    /// Checks `value`, found at `path`, against `schema`.
+    fn walk(&mut self, schema: &Document, value: &Bson, path: &str) -> Result<()> {
+        if !type_matches(schema, value)? {
+            self.record(path, Drift::TypeMismatch, Some(value));
+            return Ok(());
+        }
+
+        // Descend into the first alternative with a matching type, if any.
+        for &keyword in &["anyOf", "oneOf"] {
+            if let Some(&Bson::Array(ref alternatives)) = schema.get(keyword) {
+                let mut matching = None;
+
+                for alternative in alternatives {
+                    if let Bson::Document(ref subschema) = *alternative {
+                        if type_matches(subschema, value)? {
+                            matching = Some(subschema);
+                            break;
+                        }
+                    }
+                }
+
+                match matching {
+                    Some(subschema) => self.walk(subschema, value, path)?,
+                    None => {
+                        self.record(path, Drift::TypeMismatch, Some(value));
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        match *value {
+            Bson::Document(ref document) => self.walk_object(schema, document, path),
+            Bson::Array(ref array) => self.walk_array(schema, array, path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the fields of a document against the `required`, `properties`
+    /// and `additionalProperties` keywords of `schema`. Undeclared fields are
+    /// only reported if the schema declares any properties.
+    fn walk_object(&mut self, schema: &Document, document: &Document, path: &str) -> Result<()> {
+        let properties = match schema.get("properties") {
+            Some(&Bson::Document(ref properties)) => Some(properties),
+            _ => None,
+        };
+
+        if let Some(&Bson::Array(ref required)) = schema.get("required") {
+            for name in required {
+                if let Bson::String(ref name_str) = *name {
+                    if !document.contains_key(name_str) {
+                        self.record(&join_path(path, name_str), Drift::Missing, None);
+                    }
+                }
+            }
+        }
+
+        for (key, field) in document {
+            let field_path = join_path(path, key);
+
+            match properties.and_then(|props| props.get(key)) {
+                Some(&Bson::Document(ref property)) => self.walk(property, field, &field_path)?,
+                Some(_) => {}
+                None => match schema.get("additionalProperties") {
+                    Some(&Bson::Document(ref additional)) => {
+                        self.walk(additional, field, &field_path)?;
+                    }
+                    Some(&Bson::Boolean(true)) => {}
+                    _ => if properties.is_some() {
+                        self.record(&field_path, Drift::Undeclared, Some(field));
+                    },
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the items of an array against the `items` keyword of `schema`.
+    fn walk_array(&mut self, schema: &Document, array: &[Bson], path: &str) -> Result<()> {
+        match schema.get("items") {
+            Some(&Bson::Document(ref items)) => {
+                let items_path = join_path(path, "$");
+
+                for item in array {
+                    self.walk(items, item, &items_path)?;
+                }
+            }
+            Some(&Bson::Array(ref items)) => {
+                for (index, (item_schema, item)) in items.iter().zip(array).enumerate() {
+                    if let Bson::Document(ref subschema) = *item_schema {
+                        self.walk(subschema, item, &join_path(path, &index.to_string()))?;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Records a discrepancy at `path`, caused by `value` if it's present.
+    fn record(&mut self, path: &str, drift: Drift, value: Option<&Bson>) {
+        let stats = self.report.fields
+            .entry(path.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(drift)
+            .or_insert_with(DriftStats::default);
+
+        stats.count += 1;
+
+        if let Some(found) = value {
+            *stats.found_types.entry(type_alias(found)).or_insert(0) += 1;
+        }
+
+        if stats.example_ids.len() < MAX_EXAMPLE_IDS && !stats.example_ids.contains(self.id) {
+            stats.example_ids.push(self.id.clone());
+        }
+    }
+}
+
+/// Returns `true` if `schema` doesn't restrict the type of values,
+/// or if `value` is of one of the types it allows.
+fn type_matches(schema: &Document, value: &Bson) -> Result<bool> {
+    schema.get("bsonType").map_or(Ok(true), |argument| matches_type(argument, value))
+}
+
+/// Returns the documents to be checked by `Collection::schema_drift()`.
+#[derive(Debug, Clone, Copy)]
+struct DriftPipeline {
+    /// The number of documents to sample, or `None` for all documents.
+    sample_size: Option<i64>,
+}
+
+impl<T: Doc> Pipeline<T> for DriftPipeline {
+    type Output = Document;
+
+    fn stages(&self) -> Vec<Document> {
+        match self.sample_size {
+            Some(size) => vec![doc!{ "$sample": { "size": size } }],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_report() -> Result<()> {
+        let schema = doc!{
+            "bsonType": "object",
+            "required": ["_id", "name", "age"],
+            "additionalProperties": false,
+            "properties": {
+                "_id": { "bsonType": "int" },
+                "name": { "bsonType": "string" },
+                "age": { "bsonType": ["int", "long"] },
+                "tags": { "bsonType": "array", "items": { "bsonType": "string" } },
+                "meta": { "bsonType": "object", "additionalProperties": { "bsonType": "string" } },
+                "contact": {
+                    "anyOf": [
+                        { "bsonType": "null" },
+                        {
+                            "bsonType": "object",
+                            "properties": { "email": { "bsonType": "string" } },
+                        },
+                    ],
+                },
+            },
+        };
+        let documents = vec![
+            doc!{ "_id": 1, "name": "foo", "age": 1, "tags": ["a"], "contact": Bson::Null },
+            doc!{ "_id": 2, "name": 42, "age": 2, "nick": "x", "tags": ["b", 1, 2] },
+            doc!{ "_id": 3, "name": 43, "meta": { "a": "b", "c": 1 } },
+            doc!{ "_id": 4, "name": "bar", "age": 4, "contact": { "email": "a@b", "phone": 1 } },
+            doc!{ "_id": 5, "name": "qux", "age": 5, "contact": "a@b" },
+        ];
+        let mut report = DriftReport::default();
+
+        for document in &documents {
+            report.add_document(&schema, document)?;
+        }
+
+        let stats = |path, drift| report.get(path, drift).cloned().unwrap_or_default();
+
+        assert_eq!(report.num_documents(), 5);
+        assert_eq!(report.fields().len(), 7);
+
+        assert_eq!(stats("name", Drift::TypeMismatch).count(), 2);
+        assert_eq!(stats("name", Drift::TypeMismatch).found_types().get("int"), Some(&2));
+        assert_eq!(stats("name", Drift::TypeMismatch).example_ids(), &[Bson::I32(2), Bson::I32(3)]);
+        assert_eq!(stats("age", Drift::Missing).example_ids(), &[Bson::I32(3)]);
+        assert_eq!(stats("nick", Drift::Undeclared).count(), 1);
+        assert_eq!(stats("tags.$", Drift::TypeMismatch).count(), 2);
+        assert_eq!(stats("tags.$", Drift::TypeMismatch).example_ids(), &[Bson::I32(2)]);
+        assert_eq!(stats("meta.c", Drift::TypeMismatch).count(), 1);
+        assert_eq!(stats("contact.phone", Drift::Undeclared).count(), 1);
+        assert_eq!(stats("contact", Drift::TypeMismatch).found_types().get("string"), Some(&1));
+
+        assert!(report.to_string().contains(
+            "`name`: type mismatch in 2 cases, found int (2); e.g. _id: 2, 3\n"
+        ));
+
+        Ok(())
+    }
+}
@@ -123,6 +123,18 @@ pub enum ErrorKind {
     IntConversionOverflow,
     /// There was an error in the BSON schema for a type.
     BsonSchema,
+    /// An I/O error, e.g. while accessing a local file.
+    Io,
+    /// Some required configuration (e.g. options of a `Doc` type)
+    /// was missing or invalid.
+    InvalidConfiguration,
+    /// The entity was modified by someone else since it was read, or it
+    /// was deleted, as indicated by a mismatch of its version field.
+    ConcurrentModification,
+    /// An entity violated the rules of its `Validate` impl, or a document
+    /// violated a `$jsonSchema` checked on the client side. The individual
+    /// violations are available as the `ValidationErrors` context.
+    Validation,
 }
 
 impl ErrorKind {
@@ -145,6 +157,10 @@ impl ErrorKind {
             IntConversionUnderflow    => "integer conversion underflowed",
             IntConversionOverflow     => "integer conversion overflowed",
             BsonSchema                => "error in BSON schema",
+            Io                        => "I/O error",
+            InvalidConfiguration      => "missing or invalid configuration",
+            ConcurrentModification    => "entity was modified concurrently",
+            Validation                => "entity failed validation",
         }
     }
 }
@@ -360,6 +376,8 @@ impl_error_type! { bson::EncoderError, BsonEncoding,       "BSON encoding error"
 impl_error_type! { bson::DecoderError, BsonDecoding,       "BSON decoding error" }
 impl_error_type! { bson::oid::Error,   ObjectIdGeneration, "ObjectId generation error" }
 impl_error_type! { mongodb::Error,     MongoDbError,       "MongoDB error" }
+impl_error_type! { std::io::Error,     Io,                 "I/O error" }
+impl_error_type! { regex::Error,       InvalidConfiguration, "invalid regular expression" }
 impl_error_type! {
     mongodb::coll::error::WriteException,
     MongoDbWriteException,
@@ -0,0 +1,217 @@
+//! Compile-time-checked filter documents, built by the `filter!()` macro.
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # #[macro_use]
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Serialize, Deserialize, Doc)]
+//! #[serde(rename_all = "camelCase")]
+//! struct User {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<User>,
+//!     legal_name: String,
+//!     age: u32,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! let min_age = 18;
+//! let filter = filter!(User { age >= min_age && legal_name =~ "^A" })?;
+//!
+//! assert_eq!(filter, doc!{
+//!     "age": { "$gte": 18_i64 },
+//!     "legalName": { "$regex": "^A" },
+//! });
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The filter consists of one or more conditions joined by `&&`. Each of them
+//! compares a field of the `Doc` type, named by its Rust identifier, to an
+//! arbitrary expression, using one of the following operators:
+//!
+//! * `==`, `!=`, `<`, `<=`, `>` and `>=` translate to `$eq`, `$ne`, `$lt`,
+//!   `$lte`, `$gt` and `$gte`, respectively. The operand must be comparable
+//!   to the field using the same operator in Rust.
+//! * `=~` translates to `$regex`. Both the field and the pattern must be
+//!   string-like (`AsRef<str>`). Options can be specified inline in the
+//!   pattern, e.g. `"(?i)^a"`.
+//!
+//! The `Doc` type must have a `#[derive]`d `Doc` impl, because the fields are
+//! looked up in the `DocFields` metadata generated by the derive macro, so a
+//! misspelled field is a compile-time error. The field is replaced by its
+//! serialized name, i.e. Serde renaming is respected. The fields must also be
+//! accessible from where the macro is invoked. Every operand is evaluated
+//! (and moved) exactly once, then serialized in the same manner as documents
+//! are. This may fail, e.g. if a `u64` doesn't fit in an `i64`, so the macro
+//! evaluates to a `Result<Document>`.
+//!
+//! Conditions on the same field are merged, e.g. `age > 18 && age < 65` yields
+//! `{ "age": { "$gt": 18, "$lt": 65 } }`. If the same operator is applied to
+//! a field more than once, the additional conditions go into an `$and` array,
+//! so that all of them must hold.
+
+use serde::Serialize;
+use bson::{ Bson, Document };
+use crate::{
+    error::{ Error, Result },
+    bsn::JsonExt,
+};
+
+/// Builds a filter `Document` out of conditions on the fields of a `Doc` type,
+/// checked at compile time. Evaluates to a `Result<Document>`, which is an
+/// error if an operand can't be converted to BSON. See the [module-level documentation](filter/index.html)
+/// for the syntax.
+#[macro_export]
+macro_rules! filter {
+    (@split $filter:ident, $doc:path, [$($condition:tt)+] && $($rest:tt)+) => {
+        $crate::filter!(@condition $filter, $doc, $($condition)+);
+        $crate::filter!(@split $filter, $doc, [] $($rest)+);
+    };
+    (@split $filter:ident, $doc:path, [$($condition:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::filter!(@split $filter, $doc, [$($condition)* $next] $($rest)*);
+    };
+    (@split $filter:ident, $doc:path, [$($condition:tt)+]) => {
+        $crate::filter!(@condition $filter, $doc, $($condition)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident == $($value:tt)+) => {
+        $crate::filter!(@compare $filter, $doc, $field, ==, "$eq", $($value)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident != $($value:tt)+) => {
+        $crate::filter!(@compare $filter, $doc, $field, !=, "$ne", $($value)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident <= $($value:tt)+) => {
+        $crate::filter!(@compare $filter, $doc, $field, <=, "$lte", $($value)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident >= $($value:tt)+) => {
+        $crate::filter!(@compare $filter, $doc, $field, >=, "$gte", $($value)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident < $($value:tt)+) => {
+        $crate::filter!(@compare $filter, $doc, $field, <, "$lt", $($value)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident > $($value:tt)+) => {
+        $crate::filter!(@compare $filter, $doc, $field, >, "$gt", $($value)+);
+    };
+    (@condition $filter:ident, $doc:path, $field:ident =~ $($value:tt)+) => {
+        let value = $($value)+;
+        // Never called, only type checked.
+        let _ = |doc: &$doc| {
+            let _: &str = ::std::convert::AsRef::<str>::as_ref(&doc.$field);
+            let _: &str = ::std::convert::AsRef::<str>::as_ref(&value);
+        };
+        $crate::filter!(@insert $filter, $doc, $field, "$regex", value);
+    };
+    (@condition $filter:ident, $doc:path, $($condition:tt)+) => {
+        compile_error!(concat!(
+            "expected `field OP value` with OP being one of ",
+            "`==`, `!=`, `<`, `<=`, `>`, `>=` or `=~`, found `",
+            stringify!($($condition)+), "`"
+        ));
+    };
+    (@compare $filter:ident, $doc:path, $field:ident, $op:tt, $operator:expr, $($value:tt)+) => {
+        let value = $($value)+;
+        // Never called, only type checked.
+        let _ = |doc: &$doc| doc.$field $op value;
+        $crate::filter!(@insert $filter, $doc, $field, $operator, value);
+    };
+    (@insert $filter:ident, $doc:path, $field:ident, $operator:expr, $value:ident) => {
+        $filter = $crate::filter::add_operand(
+            $filter,
+            stringify!($field),
+            <$doc as $crate::doc::DocFields>::Fields::$field,
+            $operator,
+            &$value,
+        );
+    };
+    ($doc:path { $($conditions:tt)+ }) => {{
+        let mut filter = $crate::error::Result::Ok($crate::prelude::Document::new());
+        $crate::filter!(@split filter, $doc, [] $($conditions)+);
+        filter
+    }};
+}
+
+/// Serializes the operand of a condition on `field` in the same manner as
+/// documents are, then adds it to a filter built by `filter!()`, unless
+/// building the filter has already failed. Not meant to be called directly.
+#[doc(hidden)]
+pub fn add_operand<T: Serialize + ?Sized>(
+    result: Result<Document>,
+    field: &str,
+    key: &str,
+    operator: &str,
+    operand: &T,
+) -> Result<Document> {
+    let mut filter = result?;
+    let bson = serde_json::to_value(operand)
+        .map_err(Error::from)
+        .and_then(JsonExt::try_into_bson)
+        .map_err(|error| Error::with_cause(
+            format!("can't convert operand of field `{}` to BSON", field),
+            error,
+        ))?;
+
+    add_condition(&mut filter, key, operator, bson);
+
+    Ok(filter)
+}
+
+/// Adds the condition `{ key: { operator: operand } }` to a filter built by
+/// `filter!()`, merging it with the other conditions on the same key. Not
+/// meant to be called directly.
+#[doc(hidden)]
+pub fn add_condition(filter: &mut Document, key: &str, operator: &str, operand: Bson) {
+    let mut operators = Document::new();
+
+    if let Some(&mut Bson::Document(ref mut existing)) = filter.get_mut(key) {
+        if !existing.contains_key(operator) {
+            existing.insert(operator, operand);
+            return;
+        }
+    } else {
+        operators.insert(operator, operand);
+        filter.insert(key, operators);
+        return;
+    }
+
+    // The operator is already applied to the key: both conditions must hold.
+    let mut condition = Document::new();
+    operators.insert(operator, operand);
+    condition.insert(key, operators);
+
+    if let Some(&mut Bson::Array(ref mut conditions)) = filter.get_mut("$and") {
+        conditions.push(Bson::Document(condition));
+        return;
+    }
+
+    filter.insert("$and", vec![Bson::Document(condition)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_condition_merges_operators() {
+        let mut filter = Document::new();
+
+        add_condition(&mut filter, "age", "$gt", Bson::I64(18));
+        add_condition(&mut filter, "age", "$lt", Bson::I64(65));
+        add_condition(&mut filter, "name", "$regex", Bson::from("^A"));
+        add_condition(&mut filter, "age", "$gt", Bson::I64(21));
+        add_condition(&mut filter, "age", "$gt", Bson::I64(30));
+
+        assert_eq!(filter, doc!{
+            "age": { "$gt": 18_i64, "$lt": 65_i64 },
+            "name": { "$regex": "^A" },
+            "$and": [
+                { "age": { "$gt": 21_i64 } },
+                { "age": { "$gt": 30_i64 } },
+            ],
+        });
+    }
+}
@@ -0,0 +1,34 @@
+//! A `Doc` type shared by the unit tests, which can't `#[derive]` it.
+
+use bson::oid::ObjectId;
+use crate::{
+    doc::Doc,
+    uid::Uid,
+    literal::DateTimeType,
+};
+
+/// A minimal entity type which has a version field and both timestamps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entity {
+    /// The unique ID of the entity.
+    #[serde(rename = "_id")]
+    pub id: Uid<Entity>,
+}
+
+impl Doc for Entity {
+    type Id = ObjectId;
+
+    const NAME: &'static str = "Entity";
+    const VERSION_FIELD: Option<&'static str> = Some("rev");
+    const CREATED_AT_FIELD: Option<&'static str> = Some("created");
+    const UPDATED_AT_FIELD: Option<&'static str> = Some("updated");
+    const TIMESTAMP_TYPE: DateTimeType = DateTimeType::Timestamp;
+
+    fn id(&self) -> Option<&Uid<Self>> {
+        Some(&self.id)
+    }
+
+    fn set_id(&mut self, id: Uid<Self>) {
+        self.id = id;
+    }
+}
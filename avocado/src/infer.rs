@@ -0,0 +1,534 @@
+//! Inferring the schema of existing collections from their documents.
+//!
+//! An `InferredSchema` records the types of the values found at every path,
+//! how often each field is present, and the structure of nested documents and
+//! arrays. It can be rendered as a `$jsonSchema` document, as well as Rust
+//! source code of a corresponding `Doc` type, which is a good starting point
+//! when adopting Avocado on collections with pre-existing data. The same is
+//! available from the command line as the `avocado-schema` binary.
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate bson;
+//! # extern crate avocado;
+//! #
+//! # use avocado::infer::InferredSchema;
+//! #
+//! # fn main() {
+//! let schema = InferredSchema::from_documents(&[
+//!     doc!{ "_id": 1, "userName": "alice", "age": 31 },
+//!     doc!{ "_id": 2, "userName": "bob", "age": 42_i64 },
+//!     doc!{ "_id": 3, "userName": "eve" },
+//! ]);
+//!
+//! assert_eq!(schema.fields()["age"].presence(), 2);
+//! assert_eq!(schema.to_json_schema().get_array("required").unwrap().len(), 2);
+//!
+//! let source = schema.to_rust_source("users");
+//! assert!(source.contains("pub struct Users {"));
+//! assert!(source.contains("pub user_name: String,"));
+//! assert!(source.contains("pub age: Option<i64>,"));
+//! # }
+//! ```
+
+use std::borrow::Borrow;
+use std::collections::{ BTreeMap, BTreeSet };
+use bson::{ Bson, Document };
+use mongodb::db::ThreadedDatabase;
+use crate::{
+    schema::type_alias,
+    utils::usize_to_i64_with_msg,
+    error::{ Result, ResultExt },
+};
+
+/// Reserved words which can't be used as field names in Rust source.
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const",
+    "continue", "crate", "do", "dyn", "else", "enum", "extern", "false",
+    "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match",
+    "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "self",
+    "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// The schema of the values found at a given path of a set of documents.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InferredSchema {
+    /// The number of values observed.
+    count: usize,
+    /// The number of values of each type, keyed by their `bsonType` alias.
+    types: BTreeMap<&'static str, usize>,
+    /// The schemas of the fields of document values.
+    fields: BTreeMap<String, InferredSchema>,
+    /// The schema of the items of array values, if any arrays were observed.
+    items: Option<Box<InferredSchema>>,
+}
+
+impl InferredSchema {
+    /// Creates a schema which hasn't observed any values yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Infers the schema of the given documents.
+    pub fn from_documents<I>(documents: I) -> Self
+        where I: IntoIterator,
+              I::Item: Borrow<Document>,
+    {
+        let mut schema = Self::new();
+
+        for document in documents {
+            schema.add_document(document.borrow());
+        }
+
+        schema
+    }
+
+    /// Infers the schema of a collection from a random sample of at most
+    /// `sample_size` of its documents, obtained using the `$sample` stage.
+    pub fn sample<D>(db: &D, collection: &str, sample_size: usize) -> Result<Self>
+        where D: ThreadedDatabase + ?Sized
+    {
+        let size = usize_to_i64_with_msg(sample_size, "sample size")?;
+        let pipeline = vec![doc!{ "$sample": { "size": size } }];
+        let message = || format!("error sampling collection {}", collection);
+        let cursor = db
+            .collection(collection)
+            .aggregate(pipeline, None)
+            .chain(&message)?;
+        let mut schema = Self::new();
+
+        for document in cursor {
+            schema.add_document(&document.chain(&message)?);
+        }
+
+        Ok(schema)
+    }
+
+    /// Records a top-level document.
+    pub fn add_document(&mut self, document: &Document) {
+        self.count += 1;
+        *self.types.entry("object").or_insert(0) += 1;
+        self.add_fields(document);
+    }
+
+    /// Records an arbitrary value.
+    pub fn add_value(&mut self, value: &Bson) {
+        self.count += 1;
+        *self.types.entry(type_alias(value)).or_insert(0) += 1;
+
+        match *value {
+            Bson::Document(ref document) => self.add_fields(document),
+            Bson::Array(ref array) => {
+                let items = self.items.get_or_insert_with(Default::default);
+
+                for item in array {
+                    items.add_value(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Records the fields of a document value.
+    fn add_fields(&mut self, document: &Document) {
+        for (key, value) in document {
+            self.fields.entry(key.clone()).or_insert_with(Self::new).add_value(value);
+        }
+    }
+
+    /// The number of values observed, i.e. in the case of a field, the number
+    /// of documents in which it was present.
+    pub fn presence(&self) -> usize {
+        self.count
+    }
+
+    /// The number of values observed of each type, keyed by `bsonType` alias.
+    /// There is more than one entry if the values were of a union type.
+    pub fn types(&self) -> &BTreeMap<&'static str, usize> {
+        &self.types
+    }
+
+    /// The schemas of the fields of the observed documents, keyed by name.
+    pub fn fields(&self) -> &BTreeMap<String, InferredSchema> {
+        &self.fields
+    }
+
+    /// The schema of the items of the observed arrays, if there were any.
+    pub fn items(&self) -> Option<&InferredSchema> {
+        self.items.as_ref().map(|items| &**items)
+    }
+
+    /// The number of documents observed, i.e. the number of values of
+    /// type `object`. This is what field presence is relative to.
+    pub fn num_documents(&self) -> usize {
+        self.types.get("object").cloned().unwrap_or(0)
+    }
+
+    /// The ratio of the observed documents containing the given field, between
+    /// 0 and 1. Returns 0 if no documents were observed.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn presence_ratio(&self, field: &str) -> f64 {
+        let present = self.fields.get(field).map_or(0, InferredSchema::presence);
+
+        match self.num_documents() {
+            0 => 0.0,
+            total => present as f64 / total as f64,
+        }
+    }
+
+    /// Returns `true` if the field was present in every observed document.
+    fn is_required(&self, field: &InferredSchema) -> bool {
+        field.count == self.num_documents()
+    }
+
+    /// Renders the schema as a `$jsonSchema` document. Fields present in all
+    /// documents are `required`, and union types are expressed by multiple
+    /// `bsonType`s. The presence ratio and the type counts of every field are
+    /// recorded in its `description`.
+    pub fn to_json_schema(&self) -> Document {
+        let mut schema = Document::new();
+        let mut aliases: Vec<Bson> = self.types.keys().map(|&alias| alias.into()).collect();
+
+        if aliases.len() == 1 {
+            schema.insert("bsonType", aliases.remove(0));
+        } else if !aliases.is_empty() {
+            schema.insert("bsonType", aliases);
+        }
+
+        if self.num_documents() > 0 {
+            let mut properties = Document::new();
+            let mut required = Vec::new();
+
+            for (name, field) in &self.fields {
+                let mut property = field.to_json_schema();
+                property.insert("description", format!(
+                    "present in {:.1}% of documents; {}",
+                    self.presence_ratio(name) * 100.0,
+                    field.describe_types(),
+                ));
+                properties.insert(name.as_str(), property);
+
+                if self.is_required(field) {
+                    required.push(Bson::from(name.as_str()));
+                }
+            }
+
+            if !required.is_empty() {
+                schema.insert("required", required);
+            }
+
+            schema.insert("properties", properties);
+        }
+
+        if let Some(items) = self.items() {
+            if items.count > 0 {
+                schema.insert("items", items.to_json_schema());
+            }
+        }
+
+        schema
+    }
+
+    /// Lists the observed types along with their counts, for documentation.
+    fn describe_types(&self) -> String {
+        let types: Vec<_> = self.types
+            .iter()
+            .map(|(alias, count)| format!("{} ({})", alias, count))
+            .collect();
+
+        format!("types: {}", types.join(", "))
+    }
+
+    /// Renders the schema of top-level documents as the source code of a Rust
+    /// struct implementing `Doc`, along with the structs of nested documents.
+    /// The name of the type is derived from the name of the collection.
+    ///
+    /// The generated code assumes that `avocado::prelude::*` and the derive
+    /// macros of Serde and Avocado are in scope. Fields which aren't always
+    /// present or are sometimes `null` become `Option`s, and union types
+    /// which don't have a natural Rust equivalent become `Bson`.
+    pub fn to_rust_source(&self, collection: &str) -> String {
+        let mut generator = SourceGenerator::default();
+        generator.add_struct(collection, self, true);
+        generator.structs.join("\n")
+    }
+}
+
+/// Accumulates the Rust source of the structs generated from a schema.
+#[derive(Debug, Default)]
+struct SourceGenerator {
+    /// The source code of the structs generated so far.
+    structs: Vec<String>,
+    /// The names of the structs generated so far.
+    names: BTreeSet<String>,
+}
+
+impl SourceGenerator {
+    /// Generates a struct for the documents described by `schema`, named after
+    /// `key`, as well as the structs of its nested documents. If `is_doc` is
+    /// `true`, the struct is a `Doc` for the collection named `key`. Returns
+    /// the name of the struct.
+    fn add_struct(&mut self, key: &str, schema: &InferredSchema, is_doc: bool) -> String {
+        let name = self.unique_name(key);
+        let index = self.structs.len();
+        let mut source = String::new();
+        let mut field_names = BTreeSet::new();
+
+        // Reserve a slot so that this struct precedes its nested structs.
+        self.structs.push(String::new());
+
+        if is_doc {
+            source.push_str("#[derive(Debug, Clone, Serialize, Deserialize, Doc)]\n");
+
+            if name != key {
+                source.push_str(&format!("#[serde(rename = {:?})]\n", key));
+            }
+
+            if let Some(id_type) = schema.fields.get("_id").and_then(raw_id_type) {
+                source.push_str(&format!("#[id_type = {:?}]\n", id_type));
+            }
+        } else {
+            source.push_str("#[derive(Debug, Clone, Serialize, Deserialize)]\n");
+        }
+
+        source.push_str(&format!("pub struct {} {{\n", name));
+
+        for (field_key, field) in &schema.fields {
+            let is_id = is_doc && field_key == "_id";
+            let is_required = schema.is_required(field);
+            let ident = unique_ident(&mut field_names, if is_id { "id" } else { field_key }, false);
+            let ty = if is_id {
+                format!("Uid<{}>", name)
+            } else {
+                self.field_type(field_key, field, is_required)
+            };
+
+            source.push_str(&format!(
+                "    /// Present in {:.1}% of documents; {}.\n",
+                schema.presence_ratio(field_key) * 100.0,
+                field.describe_types(),
+            ));
+
+            if ident != *field_key {
+                source.push_str(&format!("    #[serde(rename = {:?})]\n", field_key));
+            }
+
+            if !is_required && ty.starts_with("Option<") {
+                source.push_str("    #[serde(skip_serializing_if = \"Option::is_none\")]\n");
+            }
+
+            source.push_str(&format!("    pub {}: {},\n", ident, ty));
+        }
+
+        source.push_str("}\n");
+        self.structs[index] = source;
+
+        name
+    }
+
+    /// Returns the Rust type of a field described by `schema`, generating
+    /// structs for nested documents as necessary.
+    fn field_type(&mut self, key: &str, schema: &InferredSchema, is_required: bool) -> String {
+        let is_nullable = schema.types.contains_key("null");
+        let non_null: Vec<&str> = schema.types
+            .keys()
+            .cloned()
+            .filter(|&alias| alias != "null")
+            .collect();
+        let ty = match non_null.as_slice() {
+            ["object"] => self.add_struct(key, schema, false),
+            ["array"] => match schema.items() {
+                Some(items) if items.count > 0 => {
+                    format!("Vec<{}>", self.field_type(key, items, true))
+                }
+                _ => String::from("Vec<Bson>"),
+            },
+            aliases => String::from(scalar_type(aliases)),
+        };
+
+        if ty == "Bson" && is_required {
+            // `Bson` can represent `null` by itself.
+            ty
+        } else if is_nullable || !is_required {
+            format!("Option<{}>", ty)
+        } else {
+            ty
+        }
+    }
+
+    /// Returns a struct name derived from `key` that hasn't been used yet.
+    fn unique_name(&mut self, key: &str) -> String {
+        unique_ident(&mut self.names, &pascal_case(key), true)
+    }
+}
+
+/// Returns the Rust type corresponding to values of the given non-null,
+/// non-document, non-array `bsonType`s, falling back to `Bson`.
+fn scalar_type(aliases: &[&str]) -> &'static str {
+    let is_numeric = |alias: &&str| ["int", "long", "double"].contains(alias);
+
+    match aliases {
+        ["bool"] => "bool",
+        ["int"] => "i32",
+        ["long"] | ["int", "long"] => "i64",
+        ["string"] => "String",
+        ["objectId"] => "ObjectId",
+        ["date"] => "UtcDateTime",
+        _ if !aliases.is_empty() && aliases.iter().all(is_numeric) => "f64",
+        _ => "Bson",
+    }
+}
+
+/// Returns the value of the `#[id_type]` attribute for the observed `_id`s,
+/// or `None` if they are `ObjectId`s, which is the default.
+fn raw_id_type(schema: &InferredSchema) -> Option<&'static str> {
+    let types: Vec<&str> = schema.types.keys().cloned().collect();
+
+    match types.as_slice() {
+        ["objectId"] => None,
+        aliases => Some(scalar_type(aliases)),
+    }
+}
+
+/// Converts a field name to PascalCase, for use as the name of a struct.
+fn pascal_case(key: &str) -> String {
+    key.split(|c: char| !c.is_ascii_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
+/// Converts a field name to snake_case, for use as a Rust identifier.
+fn snake_case(key: &str) -> String {
+    let mut ident = String::with_capacity(key.len());
+    let mut after_lowercase = false;
+
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            if after_lowercase {
+                ident.push('_');
+            }
+            ident.push(c.to_ascii_lowercase());
+            after_lowercase = false;
+        } else if c.is_ascii_alphanumeric() {
+            ident.push(c);
+            after_lowercase = true;
+        } else {
+            ident.push('_');
+            after_lowercase = false;
+        }
+    }
+
+    ident
+}
+
+/// Derives a valid Rust identifier from `key` that isn't contained in `used`,
+/// then adds it to `used`. Type names are expected to be PascalCase already;
+/// everything else is converted to snake_case.
+fn unique_ident(used: &mut BTreeSet<String>, key: &str, is_type_name: bool) -> String {
+    let mut base = if is_type_name { key.to_owned() } else { snake_case(key) };
+
+    if base.is_empty() || base.starts_with(|c: char| c.is_ascii_digit()) {
+        base.insert(0, if is_type_name { 'T' } else { '_' });
+    }
+
+    if RUST_KEYWORDS.contains(&base.as_str()) {
+        base.push('_');
+    }
+
+    let mut ident = base.clone();
+    let mut suffix = 2;
+
+    while !used.insert(ident.clone()) {
+        ident = format!("{}{}", base, suffix);
+        suffix += 1;
+    }
+
+    ident
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infer_schema() {
+        let schema = InferredSchema::from_documents(vec![
+            doc!{
+                "_id": 1,
+                "type": "admin",
+                "score": 1,
+                "address": { "city": "Paris", "zip": 75001 },
+                "tags": ["a", "b"],
+                "nickname": Bson::Null,
+            },
+            doc!{
+                "_id": 2,
+                "type": "user",
+                "score": 2.5,
+                "address": { "city": "Rome" },
+                "tags": [],
+                "nickname": "bob",
+                "extra": { "x": 1 },
+            },
+        ]);
+
+        assert_eq!(schema.num_documents(), 2);
+        assert!((schema.presence_ratio("extra") - 0.5).abs() < 1e-9);
+        assert_eq!(schema.fields()["score"].types().len(), 2);
+        assert_eq!(schema.fields()["address"].fields()["zip"].presence(), 1);
+        assert_eq!(schema.fields()["tags"].items().map(InferredSchema::presence), Some(2));
+
+        let json_schema = schema.to_json_schema();
+        let properties = json_schema.get_document("properties").unwrap();
+        assert_eq!(json_schema.get_str("bsonType").unwrap(), "object");
+        assert_eq!(json_schema.get_array("required").unwrap().len(), 6);
+        assert_eq!(properties.get_document("score").unwrap().get_array("bsonType").unwrap(),
+                   &vec![Bson::from("double"), Bson::from("int")]);
+        assert_eq!(properties.get_document("extra").unwrap().get_str("description").unwrap(),
+                   "present in 50.0% of documents; types: object (1)");
+        assert_eq!(properties.get_document("address").unwrap().get_array("required").unwrap(),
+                   &vec![Bson::from("city")]);
+
+        let source = schema.to_rust_source("user accounts");
+        assert!(source.starts_with("#[derive(Debug, Clone, Serialize, Deserialize, Doc)]\n\
+                                    #[serde(rename = \"user accounts\")]\n\
+                                    #[id_type = \"i32\"]\n\
+                                    pub struct UserAccounts {\n"));
+        assert!(source.contains("    #[serde(rename = \"_id\")]\n    pub id: Uid<UserAccounts>,\n"));
+        assert!(source.contains("    #[serde(rename = \"type\")]\n    pub type_: String,\n"));
+        assert!(source.contains("    pub score: f64,\n"));
+        assert!(source.contains("    pub address: Address,\n"));
+        assert!(source.contains("pub struct Address {\n"));
+        assert!(source.contains("    pub zip: Option<i32>,\n"));
+        assert!(source.contains("    pub tags: Vec<String>,\n"));
+        assert!(source.contains("    pub nickname: Option<String>,\n"));
+        assert!(source.contains("    pub extra: Option<Extra>,\n"));
+
+        // Every emitted type is reachable through the prelude.
+        assert_eq!(scalar_type(&["date"]), "UtcDateTime");
+    }
+
+    #[test]
+    fn rust_identifiers() {
+        let mut used = BTreeSet::new();
+
+        assert_eq!(pascal_case("order_items"), "OrderItems");
+        assert_eq!(pascal_case("user-profile.v2"), "UserProfileV2");
+        assert_eq!(unique_ident(&mut used, "userName", false), "user_name");
+        assert_eq!(unique_ident(&mut used, "user_name", false), "user_name2");
+        assert_eq!(unique_ident(&mut used, "HTTPStatus", false), "httpstatus");
+        assert_eq!(unique_ident(&mut used, "2fa", false), "_2fa");
+        assert_eq!(unique_ident(&mut used, "match", false), "match_");
+        assert_eq!(unique_ident(&mut used, "first name", false), "first_name");
+        assert_eq!(unique_ident(&mut used, "Self", true), "Self_");
+        assert_eq!(unique_ident(&mut used, "2", true), "T2");
+    }
+}
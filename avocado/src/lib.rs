@@ -143,12 +143,121 @@
 //! of whichever field serializes as `_id`. If there's 0 or more than 1 such
 //! fields, you will get a compile-time error. The `NAME` constant will
 //! be set to the name of the type, respecting the `#[serde(rename = "...")]`
-//! attribute at all times.
+//! attribute, unless the collection name is specified otherwise:
+//!
+//!   * `#[avocado(collection = "people")]` sets it verbatim.
+//!   * `#[avocado(collection_case = "snake_case")]` converts the name of the
+//!     Rust type (ignoring `#[serde(rename)]`) according to one of the rules
+//!     accepted by `#[serde(rename_all = "...")]`.
+//!   * `#[avocado(pluralize)]` appends a (naïve) English plural suffix, and
+//!     it can be combined with `collection_case`, e.g. `struct UserProfile`
+//!     becomes `user_profiles`.
+//!
+//! Two `Doc` types in the same module (or block) claiming the same collection
+//! name are rejected at compile time, since that is most likely a copy-paste
+//! mistake.
+//! If several types are intentionally stored in the same collection (e.g.
+//! a type which only deserializes a subset of the fields), mark all but
+//! one of them with `#[avocado(shared_collection)]`.
 //!
 //! A `#[derive]`d `Doc` trait will only implement those `..._options()` methods
 //! which are specified in the `#[options(fn_name = "path", ...)]` attribute.
 //! The implementation of the other methods will be left in the default state.
 //!
+//! The `#[avocado(capped(size = ..., max = ...))]` attribute implements the
+//! `capped_options()` method, declaring the size limit (in bytes) and the
+//! optional maximal document count used by
+//! `DatabaseExt::empty_capped_collection()`. Capped collections can then be
+//! followed using [`Collection::tail()`](tail/index.html).
+//!
+//! The `#[avocado(version_field = "...")]` attribute sets the `VERSION_FIELD`
+//! associated constant, enabling optimistic concurrency control based on the
+//! integer field with the given (serialized) name.
+//!
+//! The `#[avocado(soft_delete = "...")]` attribute sets the `SOFT_DELETE_FIELD`
+//! associated constant. Deleting documents of such a type only sets the field
+//! with the given name to the current date, and queries and updates skip these
+//! documents unless they are wrapped in `IncludeDeleted`. `Collection::restore()` and
+//! `Collection::purge()` undo and finalize soft deletion, respectively.
+//!
+//! The `#[avocado(created_at = "...", updated_at = "...")]` attributes set the
+//! `CREATED_AT_FIELD` and `UPDATED_AT_FIELD` associated constants. The given
+//! fields are then maintained automatically by inserts, updates, upserts and
+//! replacements. Timestamps are stored as BSON `Date`s by default; this can be
+//! changed using `#[avocado(timestamp_type = "timestamp")]`.
+//!
+//! The `Doc` trait also has lifecycle hooks, e.g. `before_insert()` and
+//! `after_load()`, which are invoked by `Collection` around writing and reading
+//! entities, and which can veto a write by returning an error. A derived `Doc`
+//! impl doesn't override them by default; with the `#[avocado(hooks)]`
+//! attribute, it forwards them to the type's impl of the `DocHooks` trait,
+//! whose methods are named after the events, e.g. `on_insert()` and `on_load()`.
+//!
+//! Similarly, the `#[avocado(validate)]` attribute makes
+//! `Doc::validate_entity()` forward to the type's impl of the `Validate`
+//! trait, which `Collection` then checks before inserting, replacing or
+//! upserting entities. `Validate` itself can be `#[derive]`d as well, using
+//! the `#[validate(...)]` field attributes described in the
+//! [`validation`](validation/index.html) module.
+//!
+//! `Doc` can also be derived for an internally tagged enum, i.e. one with a
+//! `#[serde(tag = "...")]` attribute, in order to store several kinds of
+//! entities in the same collection. Every variant must carry the `_id`:
+//! struct variants in one of their fields, newtype variants in the `_id`
+//! field of their payload, or in the field named by the
+//! `#[avocado(id_field = "...")]` attribute of the variant. The payload type
+//! of each newtype variant then implements `DocVariant`, so that wrapping a
+//! query in `OnlyVariant` restricts it to that variant and returns payloads.
+//! The field-based `#[avocado(...)]` attributes above aren't supported for
+//! enums.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[serde(tag = "kind")]
+//! enum Event {
+//!     Login(Login),
+//!     Logout { _id: Uid<Event> },
+//! }
+//!
+//! #[derive(Debug, Clone, Serialize, Deserialize)]
+//! struct Login {
+//!     _id: Uid<Event>,
+//!     ip_address: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let events: Collection<Event> = db.empty_collection_novalidate()?;
+//! let logins: Vec<Login> = events
+//!     .find_many(OnlyVariant::new(doc!{ "ip_address": "127.0.0.1" }))?
+//!     .collect::<AvocadoResult<_>>()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Types with type parameters get a separate collection for each
+//! instantiation. `NAME` is then a template, e.g. `"Envelope_{P}"` for a
+//! struct `Envelope<P>`, and `Doc::collection_name()` replaces each `{P}`
+//! placeholder with `P::doc_name()`. Thus, every type parameter must
+//! implement the `DocName` trait, which derived `Doc` impls also implement. A
+//! custom template can be given by `#[avocado(name_template = "...")]`, but it
+//! must mention all type parameters. The derived impl requires each type
+//! parameter to implement `DocName`, `Serialize` and `DeserializeOwned`.
+//! Since the `_id` field of type `Uid<Self>` requires `Self: Doc`, the struct
+//! must repeat these bounds, e.g.
+//! `struct Envelope<P: DocName + Serialize + DeserializeOwned>`, along with
+//! `#[serde(bound = "")]`, which stops Serde from adding its own, conflicting
+//! bounds on `P` to the derived `Deserialize` impl.
+//!
 //! ### Deriving `Doc` with indexes
 //!
 //! The `#[index(...)]` attribute can be applied to a type several times in
@@ -302,6 +411,9 @@
 //!     * `2d`
 //!     * `2dsphere`
 //!     * `geoHaystack`
+//!     * `wildcard` &mdash; indexes all fields of the embedded document at
+//!       the given path, i.e. it generates the key `"path.$**"`. A bare
+//!       `wildcard` word instead of `keys(...)` indexes all fields (`"$**"`).
 //! * Additional, optional configuration attributes can be specified, such as
 //!   `unique`, `sparse` or `name`. The `name` attribute must be string-valued.
 //!   The `unique` and `sparse` switches are either boolean-valued key-value
@@ -319,6 +431,88 @@
 //!   * `default_language = "french"` &mdash; default language of a text index.
 //!   * `language_override = "lang"` &mdash; field name that indicates the
 //!     language of a document.
+//!   * `weights(title = 10, body = 2)` &mdash; weights of the fields of a text
+//!     index, in the range `[1, 99999]`.
+//!   * `expire_after_seconds = 3600` &mdash; makes this a TTL index, which
+//!     removes documents once the indexed date is older than the given age.
+//!   * `partial_filter_expression = "..."` &mdash; only index documents that
+//!     match a filter. The string is a Rust expression evaluating to a
+//!     `Document`, e.g. `"doc!{ \"active\": true }"` or `"self::my_filter()"`.
+//!   * `collation(locale = "en", strength = 2)` &mdash; language-specific
+//!     rules for comparing strings. The `strength` is optional.
+//!   * `hidden` &mdash; hides the index from the query planner. Like `unique`,
+//!     it can be a bare word or a boolean-valued key-value pair.
+//!
+//!   The last three options aren't supported by the `IndexOptions` type of
+//!   the `mongodb` driver, so they are returned by the
+//!   `Doc::extended_index_options()` method instead, and
+//!   `Collection::create_indexes()` merges them into the index specifications.
+//!
+//! For the common case of an index on a single field, the `#[index]`
+//! attribute can also be applied to the field itself. The key is then the
+//! name the field is serialized as, respecting `#[serde(rename)]` and
+//! `#[serde(rename_all)]`. Such an index is ascending by default; the type
+//! can be changed with one of the `descending`, `text`, `hashed` or `wildcard`
+//! words, and all the options of struct-level indexes are available too. Text
+//! indexes additionally accept a `weight`. Since MongoDB only allows a single
+//! text index per collection, the text indexes of all fields are merged into
+//! one; an option specified on several of them must have the same value.
+//! Field-level indexes follow the struct-level ones in `Doc::indexes()`:
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Serialize, Deserialize, Doc)]
+//! #[serde(rename_all = "camelCase")]
+//! struct Post {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<Post>,
+//!     #[index(unique)]
+//!     url_slug: String,
+//!     #[index(descending, sparse)]
+//!     published_at: Option<u64>,
+//!     #[index(text, weight = 5)]
+//!     title: String,
+//!     #[index(text)]
+//!     body: String,
+//! }
+//! #
+//! # fn main() {
+//!
+//! assert_eq!(Post::indexes(), &[
+//!     IndexModel {
+//!         keys: doc!{ "urlSlug": IndexType::Ordered(Order::Ascending) },
+//!         options: IndexOptions {
+//!             unique: Some(true),
+//!             ..Default::default()
+//!         },
+//!     },
+//!     IndexModel {
+//!         keys: doc!{ "publishedAt": IndexType::Ordered(Order::Descending) },
+//!         options: IndexOptions {
+//!             sparse: Some(true),
+//!             ..Default::default()
+//!         },
+//!     },
+//!     IndexModel {
+//!         keys: doc!{
+//!             "title": IndexType::Text,
+//!             "body": IndexType::Text,
+//!         },
+//!         options: IndexOptions {
+//!             weights: Some(doc!{ "title": 5 }),
+//!             ..Default::default()
+//!         },
+//!     },
+//! ]);
+//! # }
+//! ```
 //!
 //! ### Collections and Databases
 //!
@@ -481,6 +675,27 @@
 //! For this more advanced (and recommended) use case, see the traits in the
 //! [`ops` module](ops/index.html) and the corresponding
 //! [methods on `Collection`](coll/struct.Collection.html#methods).
+//! `Query`, `Update` and `Delete` can also be `#[derive]`d for parameter
+//! structs whose fields make up the filter, based on `#[filter(...)]` and
+//! `#[update(...)]` field attributes. These are described in the
+//! documentation of the [`ops` module](ops/index.html) itself. For search
+//! screens and the like, [`Example`](ops/struct.Example.html) builds a query
+//! from the non-`null` fields of a partially filled-in "probe" value.
+//!
+//! When only some of the fields of a document are needed, define a read model
+//! struct with `#[derive(Projection)]` and `#[projection(of = "...")]`, and
+//! wrap the query in [`Project`](ops/struct.Project.html) in order to fetch
+//! only those fields. The fields of the read model are checked against the
+//! `Doc` type at compile time; see [`Projection`](ops/trait.Projection.html).
+//! Similarly, the [`filter!()`](macro.filter.html) macro turns conditions such
+//! as `filter!(User { age > 18 && legal_name =~ "^A" })` into a filter
+//! document, checking the fields and the types of the operands against the
+//! `Doc` type at compile time; see the [`filter` module](filter/index.html).
+//!
+//! Instead of a loose sort `Document`, a typed [`Sort`](sort/struct.Sort.html)
+//! specification can be built, e.g. `Sort::by_desc("score").then_asc("name")?`.
+//! It rejects duplicate keys, and converts into `FindOptions` or a `$sort`
+//! stage of an aggregation pipeline.
 //!
 //! For using more descriptive names for some constants in filter or update
 //! specification documents, and also for preventing certain classes of typos
@@ -660,9 +875,15 @@
 //! ### Crate Features
 //!
 //! * `schema_validation` (default): enables MongoDB-flavored JSON schema
-//!   validation via the `magnet_schema` crate.
+//!   validation via the `magnet_schema` crate, both on the server and on the
+//!   client side, via the [`schema`](schema/index.html) module. It also
+//!   enables reporting drift between a `Doc` type's schema and the documents
+//!   actually stored in its collection, via the [`drift`](drift/index.html)
+//!   module.
 //! * `raw_uuid` (default): augments the [`Uid`](uid/struct.Uid.html) type
 //!   with convenience methods for working with UUID-based entity/document IDs.
+//! * `rayon`: enables parallel processing of query results via
+//!   [`Cursor::par_for_each_batch`](cursor/struct.Cursor.html#method.par_for_each_batch).
 
 #![doc(html_root_url = "https://docs.rs/avocado/0.6.0")]
 #![deny(missing_debug_implementations, missing_copy_implementations,
@@ -699,11 +920,19 @@ extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 extern crate backtrace;
+extern crate chrono;
+extern crate regex;
 
 #[cfg(feature = "schema_validation")]
 extern crate magnet_schema;
 #[cfg(feature = "raw_uuid")]
 extern crate uuid;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
+/// Re-exported for the bounds generated by `#[derive(Doc)]`.
+#[doc(hidden)]
+pub use serde;
 
 pub mod db;
 pub mod coll;
@@ -711,6 +940,17 @@ pub mod cursor;
 pub mod doc;
 pub mod uid;
 pub mod ops;
+pub mod filter;
+pub mod pagination;
+pub mod sort;
+pub mod scan;
+pub mod tail;
+pub mod tracked;
+pub mod validation;
+pub mod schema;
+pub mod infer;
+#[cfg(feature = "schema_validation")]
+pub mod drift;
 pub mod literal;
 pub mod error;
 pub mod ext;
@@ -718,3 +958,7 @@ pub mod prelude;
 
 mod bsn;
 mod utils;
+mod version;
+mod timestamp;
+#[cfg(test)]
+mod fixture;
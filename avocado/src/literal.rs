@@ -36,6 +36,17 @@ pub enum Order {
     Descending = -1,
 }
 
+impl Order {
+    /// Returns the opposite ordering, e.g. for walking a sorted sequence of
+    /// documents backwards.
+    pub fn reversed(self) -> Self {
+        match self {
+            Order::Ascending => Order::Descending,
+            Order::Descending => Order::Ascending,
+        }
+    }
+}
+
 /// The default ordering is `Ascending`.
 impl Default for Order {
     fn default() -> Self {
@@ -184,6 +195,13 @@ impl<'a> Visitor<'a> for OrderVisitor {
 ///     "birth_date.year": -1,
 ///     "address_gps_coords": "2dsphere",
 /// });
+///
+/// let attribute_index = doc!{
+///     "attributes.$**": IndexType::Wildcard,
+/// };
+/// assert_eq!(attribute_index, doc!{
+///     "attributes.$**": 1,
+/// });
 /// #
 /// # }
 /// ```
@@ -201,6 +219,10 @@ pub enum IndexType {
     Geo2DSphere,
     /// 2D geospatial index optimized for very small areas.
     GeoHaystack,
+    /// Wildcard index on all fields (key `$**`), or on all fields of an
+    /// embedded document (key `path.$**`). It is represented by `1` in BSON,
+    /// which therefore deserializes as `Ordered(Order::Ascending)`.
+    Wildcard,
 }
 
 impl From<IndexType> for Bson {
@@ -212,6 +234,7 @@ impl From<IndexType> for Bson {
             IndexType::Geo2D          => Bson::from("2d"),
             IndexType::Geo2DSphere    => Bson::from("2dsphere"),
             IndexType::GeoHaystack    => Bson::from("geoHaystack"),
+            IndexType::Wildcard       => Bson::I32(1),
         }
     }
 }
@@ -232,6 +255,7 @@ impl From<IndexType> for Bson {
 /// let text = IndexType::Text;
 /// let planar_2d = IndexType::Geo2D;
 /// let spherical_2d = IndexType::Geo2DSphere;
+/// let wildcard = IndexType::Wildcard;
 ///
 /// assert_eq!(to_bson(&asc)?, Bson::from(asc));
 /// assert_eq!(to_bson(&desc)?, Bson::from(desc));
@@ -239,6 +263,7 @@ impl From<IndexType> for Bson {
 /// assert_eq!(to_bson(&text)?, Bson::from(text));
 /// assert_eq!(to_bson(&planar_2d)?, Bson::from(planar_2d));
 /// assert_eq!(to_bson(&spherical_2d)?, Bson::from(spherical_2d));
+/// assert_eq!(to_bson(&wildcard)?, Bson::from(wildcard));
 /// #
 /// # Ok(())
 /// # }
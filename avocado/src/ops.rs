@@ -1,7 +1,101 @@
 //! High-level database operations: query, update, delete, etc.
-
-use std::fmt::Debug;
-use serde::Deserialize;
+//!
+//! For the common case of a parameter struct whose fields are compared with
+//! fields of the document, `Query`, `Update` and `Delete` can be `#[derive]`d.
+//! The `Doc` type is specified by the type-level attribute `#[query(doc =
+//! "...")]`, `#[update(doc = "...")]` or `#[delete(doc = "...")]`, and each
+//! field of the parameter struct becomes a clause of the filter:
+//!
+//! * Without any attributes, the field is compared for equality with the
+//!   document field of the same (serialized) name.
+//! * `#[filter(op = "key")]` compares the field with the document field
+//!   `key`, which may be a dot-notation path, using the operator `op`, one of
+//!   `eq`, `ne`, `gt`, `gte`, `lt`, `lte`, `in` and `nin`. The short form
+//!   `#[filter(op)]` uses the name of the field itself as the key.
+//! * `#[filter(skip)]` excludes the field from the filter.
+//! * If the type of a field is `Option<_>`, its clause is omitted when it is
+//!   `None`. (Type aliases of `Option` aren't recognized.)
+//! * The type of a field may not mention `u64`, `usize`, `u128` or `i128`,
+//!   since not all of their values fit in a BSON integer, and `filter()` and
+//!   `update()` can't report a failed conversion.
+//!
+//! Clauses referring to the same key are combined, so a lower and an upper
+//! bound of a range can be two separate fields. In an `Update`, fields with an
+//! `#[update(op = "key")]` or `#[update(op)]` attribute make up the update
+//! document instead of the filter, where `op` is one of `set`, `inc`, `mul`,
+//! `min`, `max`, `push`, `add_to_set` and `pull`.
+//!
+//! Further type-level options override the defaults of the traits:
+//!
+//! * `output = "Type"` sets `Query::Output`, which otherwise is the `Doc`
+//!   type itself, in which case `Query::after_load()` forwards to
+//!   `Doc::after_load()`.
+//! * `transform = "path"` makes `Query::transform()` call the function
+//!   `path(raw)`.
+//! * `options = "path"` makes `options()` call the function `path(&self)`.
+//! * `include_deleted` makes a `Query` include soft-deleted documents.
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! struct User {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<User>,
+//!     legal_name: String,
+//!     age: u32,
+//!     last_login: Option<String>,
+//! }
+//!
+//! #[derive(Debug, Clone, Query)]
+//! #[query(doc = "User")]
+//! struct UsersByName {
+//!     #[filter(eq = "legal_name")]
+//!     name: String,
+//!     #[filter(gte = "age")]
+//!     min_age: Option<u32>,
+//! }
+//!
+//! #[derive(Debug, Clone, Update)]
+//! #[update(doc = "User")]
+//! struct RecordLogin {
+//!     #[filter(eq = "_id")]
+//!     id: Uid<User>,
+//!     #[update(set)]
+//!     last_login: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! let query = UsersByName { name: String::from("Alice"), min_age: Some(18) };
+//! assert_eq!(Query::<User>::filter(&query), doc!{
+//!     "legal_name": "Alice",
+//!     "age": { "$gte": 18_i64 },
+//! });
+//!
+//! let query = UsersByName { name: String::from("Bob"), min_age: None };
+//! assert_eq!(Query::<User>::filter(&query), doc!{ "legal_name": "Bob" });
+//!
+//! let update = RecordLogin {
+//!     id: Uid::new_oid()?,
+//!     last_login: String::from("2019-01-01"),
+//! };
+//! assert_eq!(Update::<User>::update(&update), doc!{
+//!     "$set": { "last_login": "2019-01-01" },
+//! });
+//! #
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::{ self, Debug, Formatter };
+use std::marker::PhantomData;
+use serde::{ Serialize, Deserialize };
 use bson::{ Bson, Document };
 use mongodb::common::WriteConcern;
 use mongodb::coll::options::{
@@ -12,8 +106,10 @@ use mongodb::coll::options::{
     FindOneAndUpdateOptions,
 };
 use crate::{
-    doc::Doc,
-    error::Result,
+    doc::{ Doc, DocVariant },
+    error::{ Error, ErrorKind, Result },
+    literal::RegexOpts,
+    bsn::{ JsonExt, serialize_document, flatten_document },
 };
 
 /// A counting-only query.
@@ -28,6 +124,12 @@ pub trait Count<T: Doc>: Debug {
     fn options(&self) -> CountOptions {
         T::count_options()
     }
+
+    /// Whether soft-deleted documents (see `Doc::SOFT_DELETE_FIELD`) should
+    /// be taken into account. Defaults to `false`, i.e. they are excluded.
+    fn include_deleted(&self) -> bool {
+        false
+    }
 }
 
 /// A query for returning the distinct values of a field.
@@ -57,6 +159,12 @@ pub trait Distinct<T: Doc>: Debug {
     fn options(&self) -> DistinctOptions {
         T::distinct_options()
     }
+
+    /// Whether soft-deleted documents (see `Doc::SOFT_DELETE_FIELD`) should
+    /// be taken into account. Defaults to `false`, i.e. they are excluded.
+    fn include_deleted(&self) -> bool {
+        false
+    }
 }
 
 /// An aggregation pipeline.
@@ -103,10 +211,26 @@ pub trait Query<T: Doc>: Debug {
         Ok(raw.into())
     }
 
+    /// Invoked on each result after deserialization.
+    ///
+    /// **The default implementation does nothing, so a hand-written query
+    /// whose `Output` is `T` must override it and forward to
+    /// `Doc::after_load()`,** as the impl for `Document` and `#[derive]`d
+    /// impls do. Otherwise, the hook is silently skipped for its results.
+    fn after_load(_output: &mut Self::Output) -> Result<()> {
+        Ok(())
+    }
+
     /// Options for this query.
     fn options(&self) -> FindOptions {
         T::query_options()
     }
+
+    /// Whether soft-deleted documents (see `Doc::SOFT_DELETE_FIELD`) should
+    /// be taken into account. Defaults to `false`, i.e. they are excluded.
+    fn include_deleted(&self) -> bool {
+        false
+    }
 }
 
 /// An update (but not an upsert) operation.
@@ -121,6 +245,12 @@ pub trait Update<T: Doc>: Debug {
     fn options(&self) -> WriteConcern {
         T::update_options()
     }
+
+    /// Whether soft-deleted documents (see `Doc::SOFT_DELETE_FIELD`) should
+    /// be updated as well. Defaults to `false`, i.e. they are left alone.
+    fn include_deleted(&self) -> bool {
+        false
+    }
 }
 
 /// An upsert (update or insert) operation.
@@ -170,10 +300,40 @@ pub trait FindAndUpdate<T: Doc>: Debug {
         Ok(raw.into())
     }
 
+    /// Invoked on the returned document after deserialization.
+    ///
+    /// **The default implementation does nothing, so an operation whose
+    /// `Output` is `T` must override it and forward to `Doc::after_load()`.**
+    fn after_load(_output: &mut Self::Output) -> Result<()> {
+        Ok(())
+    }
+
     /// Options for this query-and-update operation.
     fn options(&self) -> FindOneAndUpdateOptions {
         T::find_and_update_options()
     }
+
+    /// Whether soft-deleted documents (see `Doc::SOFT_DELETE_FIELD`) should
+    /// be taken into account. Defaults to `false`, i.e. they are excluded.
+    fn include_deleted(&self) -> bool {
+        false
+    }
+}
+
+/// A read model: a type which only holds a subset of the fields of the
+/// documents of type `T`, so that it's enough to fetch those fields.
+///
+/// It can be `#[derive]`d for a struct with the `#[projection(of = "T")]`
+/// attribute. The projection document then consists of the (serialized)
+/// names of the fields of the struct, and `_id` is excluded unless it is
+/// one of the fields. The fields are checked against the `#[derive]`d `Doc`
+/// impl of `T`, so that a field which doesn't exist in `T` is a compile-time
+/// error. Such a type can be used as the output of a query by wrapping the
+/// query in `Project`.
+pub trait Projection<T: Doc>: for<'a> Deserialize<'a> {
+    /// The projection document specifying which fields to return, e.g.
+    /// `{ "name": true, "_id": false }`.
+    fn projection() -> Document;
 }
 
 /////////////////////////////////////////////
@@ -192,6 +352,10 @@ impl<T: Doc> Query<T> for Document {
     fn filter(&self) -> Document {
         self.clone()
     }
+
+    fn after_load(output: &mut T) -> Result<()> {
+        output.after_load()
+    }
 }
 
 impl<T: Doc> Delete<T> for Document {
@@ -208,6 +372,10 @@ impl<T: Doc, Q: Count<T>> Count<T> for &Q {
     fn options(&self) -> CountOptions {
         (**self).options()
     }
+
+    fn include_deleted(&self) -> bool {
+        (**self).include_deleted()
+    }
 }
 
 impl<T: Doc, Q: Distinct<T>> Distinct<T> for &Q {
@@ -226,6 +394,10 @@ impl<T: Doc, Q: Distinct<T>> Distinct<T> for &Q {
     fn options(&self) -> DistinctOptions {
         (**self).options()
     }
+
+    fn include_deleted(&self) -> bool {
+        (**self).include_deleted()
+    }
 }
 
 impl<T: Doc, P: Pipeline<T>> Pipeline<T> for &P {
@@ -255,9 +427,17 @@ impl<T: Doc, Q: Query<T>> Query<T> for &Q {
         Q::transform(doc)
     }
 
+    fn after_load(output: &mut Self::Output) -> Result<()> {
+        Q::after_load(output)
+    }
+
     fn options(&self) -> FindOptions {
         (**self).options()
     }
+
+    fn include_deleted(&self) -> bool {
+        (**self).include_deleted()
+    }
 }
 
 impl<T: Doc, U: Update<T>> Update<T> for &U {
@@ -272,6 +452,10 @@ impl<T: Doc, U: Update<T>> Update<T> for &U {
     fn options(&self) -> WriteConcern {
         (**self).options()
     }
+
+    fn include_deleted(&self) -> bool {
+        (**self).include_deleted()
+    }
 }
 
 impl<T: Doc, U: Upsert<T>> Upsert<T> for &U {
@@ -313,7 +497,492 @@ impl<T: Doc, U: FindAndUpdate<T>> FindAndUpdate<T> for &U {
         U::transform(raw)
     }
 
+    fn after_load(output: &mut Self::Output) -> Result<()> {
+        U::after_load(output)
+    }
+
     fn options(&self) -> FindOneAndUpdateOptions {
         (**self).options()
     }
+
+    fn include_deleted(&self) -> bool {
+        (**self).include_deleted()
+    }
+}
+
+/// Wraps a query so that it also takes soft-deleted documents into account.
+/// Has no effect on collections without soft deletion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct IncludeDeleted<Q>(pub Q);
+
+impl<T: Doc, Q: Count<T>> Count<T> for IncludeDeleted<Q> {
+    fn filter(&self) -> Document {
+        self.0.filter()
+    }
+
+    fn options(&self) -> CountOptions {
+        self.0.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Doc, Q: Distinct<T>> Distinct<T> for IncludeDeleted<Q> {
+    type Output = Q::Output;
+
+    const FIELD: &'static str = Q::FIELD;
+
+    fn filter(&self) -> Document {
+        self.0.filter()
+    }
+
+    fn transform(bson: Bson) -> Result<Bson> {
+        Q::transform(bson)
+    }
+
+    fn options(&self) -> DistinctOptions {
+        self.0.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Doc, Q: Query<T>> Query<T> for IncludeDeleted<Q> {
+    type Output = Q::Output;
+
+    fn filter(&self) -> Document {
+        self.0.filter()
+    }
+
+    fn transform(doc: Document) -> Result<Bson> {
+        Q::transform(doc)
+    }
+
+    fn after_load(output: &mut Self::Output) -> Result<()> {
+        Q::after_load(output)
+    }
+
+    fn options(&self) -> FindOptions {
+        self.0.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Doc, U: Update<T>> Update<T> for IncludeDeleted<U> {
+    fn filter(&self) -> Document {
+        self.0.filter()
+    }
+
+    fn update(&self) -> Document {
+        self.0.update()
+    }
+
+    fn options(&self) -> WriteConcern {
+        self.0.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Doc, U: FindAndUpdate<T>> FindAndUpdate<T> for IncludeDeleted<U> {
+    type Output = U::Output;
+
+    fn filter(&self) -> Document {
+        self.0.filter()
+    }
+
+    fn update(&self) -> Document {
+        self.0.update()
+    }
+
+    fn transform(raw: Document) -> Result<Bson> {
+        U::transform(raw)
+    }
+
+    fn after_load(output: &mut Self::Output) -> Result<()> {
+        U::after_load(output)
+    }
+
+    fn options(&self) -> FindOneAndUpdateOptions {
+        self.0.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        true
+    }
+}
+
+/// Restricts a query to a single variant of an enum `Doc` type, i.e. to the
+/// documents whose discriminator field identifies the variant with payload
+/// type `V`, and returns the payloads instead of the whole enum. The `Output`
+/// type and the `transform()` of the wrapped query are therefore ignored.
+pub struct OnlyVariant<V, Q = Document> {
+    /// The wrapped query, e.g. a filter `Document`.
+    pub query: Q,
+    /// Records the payload type.
+    marker: PhantomData<fn() -> V>,
+}
+
+impl<V, Q> OnlyVariant<V, Q> {
+    /// Restricts `query` to the variant with payload type `V`.
+    pub fn new(query: Q) -> Self {
+        OnlyVariant {
+            query,
+            marker: PhantomData,
+        }
+    }
+
+    /// Adds the discriminator of the variant to the filter of the query.
+    fn variant_filter<T: Doc>(mut filter: Document) -> Document where V: DocVariant<T> {
+        filter.insert(V::TAG_FIELD, V::TAG);
+        filter
+    }
+}
+
+impl<V, Q: Default> Default for OnlyVariant<V, Q> {
+    fn default() -> Self {
+        Self::new(Q::default())
+    }
+}
+
+impl<V, Q: Clone> Clone for OnlyVariant<V, Q> {
+    fn clone(&self) -> Self {
+        Self::new(self.query.clone())
+    }
+}
+
+impl<V, Q: Debug> Debug for OnlyVariant<V, Q> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("OnlyVariant").field("query", &self.query).finish()
+    }
+}
+
+impl<T: Doc, V: DocVariant<T>, Q: Count<T>> Count<T> for OnlyVariant<V, Q> {
+    fn filter(&self) -> Document {
+        Self::variant_filter::<T>(self.query.filter())
+    }
+
+    fn options(&self) -> CountOptions {
+        self.query.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        self.query.include_deleted()
+    }
+}
+
+impl<T: Doc, V: DocVariant<T>, Q: Query<T>> Query<T> for OnlyVariant<V, Q> {
+    type Output = V;
+
+    fn filter(&self) -> Document {
+        Self::variant_filter::<T>(self.query.filter())
+    }
+
+    fn options(&self) -> FindOptions {
+        self.query.options()
+    }
+
+    fn include_deleted(&self) -> bool {
+        self.query.include_deleted()
+    }
+}
+
+impl<T: Doc, V: DocVariant<T>, Q: Delete<T>> Delete<T> for OnlyVariant<V, Q> {
+    fn filter(&self) -> Document {
+        Self::variant_filter::<T>(self.query.filter())
+    }
+
+    fn options(&self) -> WriteConcern {
+        self.query.options()
+    }
+}
+
+/// Makes a query return the projection `P` of the documents, instead of its
+/// own `Output`, by setting the `projection` option of the query to the
+/// projection of `P`. The `Output` type and the `transform()` of the wrapped
+/// query are therefore ignored.
+///
+/// ```
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// # #[macro_use]
+/// # extern crate avocado_derive;
+/// # extern crate avocado;
+/// #
+/// # use avocado::prelude::*;
+/// #
+/// #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+/// #[serde(rename_all = "camelCase")]
+/// struct User {
+///     #[serde(rename = "_id")]
+///     id: Uid<User>,
+///     legal_name: String,
+///     password_hash: String,
+/// }
+///
+/// #[derive(Debug, Clone, Deserialize, Projection)]
+/// #[serde(rename_all = "camelCase")]
+/// #[projection(of = "User")]
+/// struct UserName {
+///     legal_name: String,
+/// }
+///
+/// # fn main() {
+/// let query = Project::<UserName>::new(doc!{ "legalName": "Alice" });
+///
+/// assert_eq!(UserName::projection(), doc!{ "legalName": true, "_id": false });
+/// assert_eq!(
+///     Query::<User>::options(&query).projection,
+///     Some(UserName::projection())
+/// );
+/// # }
+/// ```
+pub struct Project<P, Q = Document> {
+    /// The wrapped query, e.g. a filter `Document`.
+    pub query: Q,
+    /// Records the projection type.
+    marker: PhantomData<fn() -> P>,
+}
+
+impl<P, Q> Project<P, Q> {
+    /// Makes `query` return the projection `P` of the documents.
+    pub fn new(query: Q) -> Self {
+        Project {
+            query,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<P, Q: Default> Default for Project<P, Q> {
+    fn default() -> Self {
+        Self::new(Q::default())
+    }
+}
+
+impl<P, Q: Clone> Clone for Project<P, Q> {
+    fn clone(&self) -> Self {
+        Self::new(self.query.clone())
+    }
+}
+
+impl<P, Q: Debug> Debug for Project<P, Q> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Project").field("query", &self.query).finish()
+    }
+}
+
+impl<T: Doc, P: Projection<T>, Q: Query<T>> Query<T> for Project<P, Q> {
+    type Output = P;
+
+    fn filter(&self) -> Document {
+        self.query.filter()
+    }
+
+    fn options(&self) -> FindOptions {
+        FindOptions {
+            projection: Some(P::projection()),
+            ..self.query.options()
+        }
+    }
+
+    fn include_deleted(&self) -> bool {
+        self.query.include_deleted()
+    }
+}
+
+/// A query by example: matches the documents whose fields are equal to the
+/// non-`null` fields of a "probe" value, e.g. a struct of `Option`s filled in
+/// from a search form. Embedded documents of the probe are matched field by
+/// field, using dot-notation paths, so that unspecified fields of theirs don't
+/// need to match. Besides querying, it can be used for counting and deleting;
+/// a probe with no non-`null` fields at all is refused by `Example::new()`.
+///
+/// ```
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// # #[macro_use]
+/// # extern crate avocado_derive;
+/// # extern crate avocado;
+/// #
+/// # use avocado::prelude::*;
+/// # use avocado::literal::RegexOpts;
+/// #
+/// #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+/// struct User {
+///     #[serde(rename = "_id")]
+///     id: Uid<User>,
+///     name: String,
+///     email: String,
+///     address: Address,
+/// }
+///
+/// #[derive(Debug, Clone, Serialize, Deserialize)]
+/// struct Address {
+///     city: String,
+///     street: String,
+/// }
+///
+/// #[derive(Debug, Default, Serialize)]
+/// struct UserProbe {
+///     name: Option<String>,
+///     email: Option<String>,
+///     address: AddressProbe,
+/// }
+///
+/// #[derive(Debug, Default, Serialize)]
+/// struct AddressProbe {
+///     city: Option<String>,
+///     street: Option<String>,
+/// }
+///
+/// # fn main() -> AvocadoResult<()> {
+/// let probe = UserProbe {
+///     name: Some(String::from("alice")),
+///     email: Some(String::from("alice@")),
+///     address: AddressProbe {
+///         city: Some(String::from("Budapest")),
+///         street: None,
+///     },
+/// };
+/// let example = Example::<User>::new(&probe)?
+///     .ignore_case("name")
+///     .prefix("email", RegexOpts::default());
+///
+/// assert_eq!(Query::filter(&example), doc!{
+///     "name": { "$regex": "^alice$", "$options": "i" },
+///     "email": { "$regex": "^alice@", "$options": "" },
+///     "address.city": "Budapest",
+/// });
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct Example<T> {
+    /// The flattened filter built from the probe.
+    filter: Document,
+    /// Records the document type.
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Example<T> {
+    /// Creates a query matching the non-`null` fields of `probe`.
+    /// Returns an error if `probe` doesn't serialize as a document. Since an
+    /// empty filter would match, and e.g. `delete_many()` would delete, every
+    /// document in the collection, a probe without any non-`null` fields is
+    /// also refused, with an error of kind `ErrorKind::InvalidConfiguration`.
+    pub fn new<P: Serialize>(probe: &P) -> Result<Self> {
+        let filter = flatten_document(&serialize_document(probe)?);
+
+        if filter.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidConfiguration,
+                "probe of query by example has no non-null fields",
+            ));
+        }
+
+        Ok(Example {
+            filter,
+            marker: PhantomData,
+        })
+    }
+
+    /// Compares the string field at `path` case-insensitively, instead of
+    /// exactly. Has no effect if the field is missing from the probe, or it
+    /// is not a string.
+    pub fn ignore_case(self, path: &str) -> Self {
+        self.match_regex(path, "$", RegexOpts::IGNORE_CASE)
+    }
+
+    /// Matches the documents in which the string field at `path` starts with
+    /// the value in the probe, matching it as modified by `options`, e.g.
+    /// `RegexOpts::IGNORE_CASE`. Has no effect if the field is missing from
+    /// the probe, or it is not a string. Note that only a case-sensitive
+    /// prefix match can make efficient use of an index.
+    pub fn prefix(self, path: &str, options: RegexOpts) -> Self {
+        self.match_regex(path, "", options)
+    }
+
+    /// Replaces the equality condition on the string field at `path` with a
+    /// `$regex` matching the escaped value followed by `suffix`.
+    fn match_regex(mut self, path: &str, suffix: &str, options: RegexOpts) -> Self {
+        let pattern = match self.filter.get(path) {
+            Some(&Bson::String(ref value)) => format!("^{}{}", regex::escape(value), suffix),
+            _ => return self,
+        };
+
+        self.filter.insert(path, doc!{ "$regex": pattern, "$options": options });
+        self
+    }
+}
+
+impl<T> Clone for Example<T> {
+    fn clone(&self) -> Self {
+        Example {
+            filter: self.filter.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for Example<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Example").field("filter", &self.filter).finish()
+    }
+}
+
+impl<T: Doc> Count<T> for Example<T> {
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+}
+
+impl<T: Doc> Query<T> for Example<T> {
+    type Output = T;
+
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+
+    fn after_load(output: &mut T) -> Result<()> {
+        output.after_load()
+    }
+}
+
+impl<T: Doc> Delete<T> for Example<T> {
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+}
+
+/////////////////////////////////////
+// Helpers for `#[derive]`d impls //
+/////////////////////////////////////
+
+/// Converts the value of a field of a parameter struct into an operand of a
+/// filter or an update, in the same manner as documents are serialized. Used
+/// by the `filter()` and `update()` methods generated by `#[derive(Query)]`,
+/// `#[derive(Update)]` and `#[derive(Delete)]`, which reject field types
+/// mentioning integer types that don't fit in BSON. `field` is the name of
+/// the field, for the panic message.
+///
+/// # Panics
+///
+/// If the value still can't be represented in BSON, e.g. it is a custom type
+/// containing a `u64` which doesn't fit in an `i64`, since `filter()` and
+/// `update()` can't return an error.
+pub fn field_operand<T: Serialize + ?Sized>(field: &str, value: &T) -> Bson {
+    serde_json::to_value(value)
+        .map_err(Error::from)
+        .and_then(JsonExt::try_into_bson)
+        .unwrap_or_else(|error| panic!("can't convert field `{}` to BSON: {}", field, error))
 }
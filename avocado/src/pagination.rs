@@ -0,0 +1,625 @@
+//! Keyset pagination over queries and aggregation pipelines.
+//!
+//! Paging with `skip` requires the server to walk all the skipped documents
+//! over and over again, which gets slower and slower as one pages deeper into
+//! a large collection. Keyset pagination instead remembers the sort key of the
+//! last document on the current page, and the next page starts with a filter
+//! that only matches documents sorting strictly after it. This only needs an
+//! index on the sort keys in order to be efficient at any depth.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[index(keys(score = "descending", _id = "ascending"))]
+//! struct Player {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<Player>,
+//!     name: String,
+//!     score: u32,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let players: Collection<Player> = db.existing_collection();
+//! let paginator = Paginator::new(
+//!     doc!{ "score": { "$gt": 0 } },
+//!     vec![("score", Order::Descending)],
+//!     20,
+//! )?;
+//!
+//! let mut page = paginator.find_page(&players, None)?;
+//!
+//! loop {
+//!     for player in &page.items {
+//!         println!("{}: {}", player.name, player.score);
+//!     }
+//!     match page.next {
+//!         // The token can be handed out to a client and sent back later.
+//!         Some(token) => page = paginator.find_page(&players, Some(&token))?,
+//!         None => break,
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::str::{ self, FromStr };
+use std::fmt::{ self, Display, Formatter, Write };
+use serde::{
+    ser::{ Serialize, Serializer },
+    de::{ Deserialize, Deserializer, Error as DeError },
+};
+use bson::{ Bson, Document, from_bson };
+use mongodb::coll::options::{ FindOptions, AggregateOptions };
+use crate::{
+    coll::Collection,
+    cursor::Cursor,
+    doc::Doc,
+    ops::{ Query, Pipeline },
+    literal::Order,
+    sort::{ Sort, SortOrder },
+    bsn::lookup_path,
+    utils::usize_to_i64_with_msg,
+    error::{ Error, ErrorKind, Result },
+};
+
+/// Wraps a `Query` or a `Pipeline` and retrieves its results one page at
+/// a time, in the order given by a sort specification.
+///
+/// The `_id` field is always used as the last, tie-breaking sort key, so that
+/// the position of each document is unique even if the other sort keys aren't.
+/// If `_id` is not part of the sort specification, it will be appended to it,
+/// in ascending order.
+///
+/// All sort keys must be present in the documents produced by the wrapped
+/// query or pipeline. In particular, a `projection` must not exclude them,
+/// and a pipeline must not project them away in its last stage.
+#[derive(Debug, Clone)]
+pub struct Paginator<Q> {
+    /// The wrapped query or pipeline.
+    inner: Q,
+    /// The sort keys, in order of decreasing precedence. Always ends in `_id`.
+    sort: Vec<(String, Order)>,
+    /// The maximal number of items on a page.
+    page_size: usize,
+}
+
+impl<Q> Paginator<Q> {
+    /// Creates a paginator from a query or pipeline, a sort specification
+    /// in the form of key-order pairs, and the maximal number of items per
+    /// page. A `page_size` of 0 is treated as 1.
+    ///
+    /// Returns an error of kind `ErrorKind::InvalidConfiguration` if a key
+    /// occurs more than once in `sort`, like `Sort::then()` does.
+    pub fn new<I, S>(inner: Q, sort: I, page_size: usize) -> Result<Self>
+        where I: IntoIterator<Item = (S, Order)>,
+              S: Into<String>,
+    {
+        let mut keys: Vec<(String, Order)> = Vec::new();
+
+        for (name, order) in sort {
+            let key: String = name.into();
+            let is_id = key == "_id";
+
+            if keys.iter().any(|&(ref existing, _)| *existing == key) {
+                return Err(Error::new(
+                    ErrorKind::InvalidConfiguration,
+                    format!("duplicate sort key `{}`", key)
+                ));
+            }
+
+            keys.push((key, order));
+
+            // No key after `_id` can ever influence the order.
+            if is_id {
+                break;
+            }
+        }
+
+        if keys.last().map_or(true, |&(ref key, _)| key != "_id") {
+            keys.push((String::from("_id"), Order::Ascending));
+        }
+
+        Ok(Paginator {
+            inner,
+            sort: keys,
+            page_size: page_size.max(1),
+        })
+    }
+
+    /// Creates a paginator from a query or pipeline, a typed sort
+    /// specification, and the maximal number of items per page, like `new()`.
+    ///
+    /// Returns an error of kind `ErrorKind::InvalidConfiguration` if `sort`
+    /// contains a text score key, because keyset pagination needs to filter
+    /// on the values of the sort keys, which is impossible for text scores.
+    pub fn sorted_by<T>(inner: Q, sort: &Sort<T>, page_size: usize) -> Result<Self> {
+        let keys = sort
+            .keys()
+            .iter()
+            .map(|&(ref key, order)| match order {
+                SortOrder::Order(direction) => Ok((key.clone(), direction)),
+                SortOrder::TextScore => Err(Error::new(
+                    ErrorKind::InvalidConfiguration,
+                    format!("can't paginate by the text score sort key `{}`", key)
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::new(inner, keys, page_size)
+    }
+
+    /// Returns the wrapped query or pipeline.
+    pub fn inner(&self) -> &Q {
+        &self.inner
+    }
+
+    /// Returns the effective sort specification, including `_id`.
+    pub fn sort_keys(&self) -> &[(String, Order)] {
+        &self.sort
+    }
+
+    /// Returns the maximal number of items on a page.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Retrieves the page following the position described by `token`, or
+    /// the first page if there's no token, using `Collection::find_many()`.
+    ///
+    /// The `sort`, `limit` and `skip` options of the wrapped query are
+    /// overridden by the paginator.
+    pub fn find_page<T>(&self, coll: &Collection<T>, token: Option<&PageToken>) -> Result<Page<Q::Output>>
+        where T: Doc,
+              Q: Query<T>,
+    {
+        let query = PageQuery {
+            paginator: self,
+            filter: self.page_filter(self.inner.filter(), token)?,
+            limit: self.fetch_limit()?,
+        };
+        self.collect_page(coll.find_many(&query)?, Q::transform, Q::after_load)
+    }
+
+    /// Retrieves the page following the position described by `token`, or
+    /// the first page if there's no token, using `Collection::aggregate()`.
+    ///
+    /// The `$match`, `$sort` and `$limit` stages implementing pagination are
+    /// appended to the stages of the wrapped pipeline, so the sort keys refer
+    /// to the fields of the documents produced by the pipeline.
+    pub fn aggregate_page<T>(&self, coll: &Collection<T>, token: Option<&PageToken>) -> Result<Page<Q::Output>>
+        where T: Doc,
+              Q: Pipeline<T>,
+    {
+        let mut stages = self.inner.stages();
+
+        if let Some(last) = token {
+            stages.push(doc!{ "$match": self.keyset_filter(last)? });
+        }
+
+        stages.push(doc!{ "$sort": self.sort_document() });
+        stages.push(doc!{ "$limit": self.fetch_limit()? });
+
+        let pipeline = PagePipeline {
+            paginator: self,
+            stages,
+        };
+        self.collect_page(coll.aggregate(&pipeline)?, Q::transform, |_| Ok(()))
+    }
+
+    /// Builds the sort document corresponding to the sort keys.
+    fn sort_document(&self) -> Document {
+        self.sort
+            .iter()
+            .map(|&(ref key, order)| (key.clone(), Bson::from(order)))
+            .collect()
+    }
+
+    /// The number of documents to request for one page. One more than the
+    /// page size, so that we know whether there are any more pages.
+    /// (A page of `i64::MAX` items can't be followed by another one anyway.)
+    fn fetch_limit(&self) -> Result<i64> {
+        usize_to_i64_with_msg(self.page_size, "page size").map(|n| n.saturating_add(1))
+    }
+
+    /// Combines the original filter with the keyset condition, if any.
+    fn page_filter(&self, filter: Document, token: Option<&PageToken>) -> Result<Document> {
+        let keyset = match token {
+            Some(last) => self.keyset_filter(last)?,
+            None => return Ok(filter),
+        };
+
+        if filter.is_empty() {
+            Ok(keyset)
+        } else {
+            Ok(doc!{ "$and": [filter, keyset] })
+        }
+    }
+
+    /// Builds a filter that only matches documents sorting strictly after
+    /// the position described by the page token. For sort keys `k1, k2, k3`
+    /// and last values `v1, v2, v3`, this is the disjunction of:
+    /// * `k1 > v1`
+    /// * `k1 == v1 && k2 > v2`
+    /// * `k1 == v1 && k2 == v2 && k3 > v3`
+    ///
+    /// where `>` is replaced by `<` for descending keys. Equality is expressed
+    /// with an explicit `$eq`, lest a value which is itself a document with
+    /// `$`-prefixed keys be interpreted as a query operator expression.
+    fn keyset_filter(&self, token: &PageToken) -> Result<Document> {
+        if token.0.len() != self.sort.len() {
+            return Err(Error::new(
+                ErrorKind::BsonDecoding,
+                format!("page token has {} keys but sort specification has {}",
+                        token.0.len(), self.sort.len())
+            ));
+        }
+
+        let keys_values: Vec<_> = self.sort.iter().zip(&token.0).collect();
+        let clauses: Vec<Bson> = (0..keys_values.len())
+            .map(|i| {
+                let mut clause: Document = keys_values[..i]
+                    .iter()
+                    .map(|&(&(ref key, _), value)| {
+                        (key.clone(), Bson::from(doc!{ "$eq": value.clone() }))
+                    })
+                    .collect();
+                let (&(ref key, order), value) = keys_values[i];
+                let operator = match order {
+                    Order::Ascending => "$gt",
+                    Order::Descending => "$lt",
+                };
+                let mut comparison = Document::new();
+
+                comparison.insert(operator, value.clone());
+                clause.insert(key.clone(), comparison);
+
+                Bson::from(clause)
+            })
+            .collect();
+
+        Ok(doc!{ "$or": clauses })
+    }
+
+    /// Extracts the values of the sort keys from a raw document.
+    fn token_for(&self, doc: &Document) -> Result<PageToken> {
+        self.sort
+            .iter()
+            .map(|&(ref key, _)| lookup_path(doc, key).cloned().ok_or_else(
+                || Error::new(
+                    ErrorKind::MissingDocumentField,
+                    format!("sort key `{}` is missing from paginated document", key)
+                )
+            ))
+            .collect::<Result<_>>()
+            .map(PageToken)
+    }
+
+    /// Reads at most one more than a page worth of raw documents, then
+    /// transforms and deserializes the ones belonging to the current page,
+    /// invoking `after_load` on each of them.
+    fn collect_page<U>(
+        &self,
+        cursor: Cursor<Document>,
+        transform: fn(Document) -> Result<Bson>,
+        after_load: fn(&mut U) -> Result<()>,
+    ) -> Result<Page<U>>
+        where U: for<'a> Deserialize<'a>
+    {
+        let mut docs: Vec<Document> = cursor.collect::<Result<_>>()?;
+        let has_more = docs.len() > self.page_size;
+
+        docs.truncate(self.page_size);
+
+        let next = match docs.last() {
+            Some(doc) if has_more => Some(self.token_for(doc)?),
+            _ => None,
+        };
+        let items = docs
+            .into_iter()
+            .map(|doc| {
+                let mut item = from_bson(transform(doc)?)?;
+                after_load(&mut item)?;
+                Ok(item)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Page { items, next })
+    }
+}
+
+/// One page of results, along with the token to the next page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page<T> {
+    /// The items on this page, in sorted order.
+    pub items: Vec<T>,
+    /// The continuation token for retrieving the next page, or `None`
+    /// if this is the last page.
+    pub next: Option<PageToken>,
+}
+
+/// An opaque continuation token pointing right after the last item of a page.
+///
+/// It encodes the values of the sort keys (including `_id`) of the last item.
+/// It serializes as, and can be parsed from, a URL-safe string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageToken(Vec<Bson>);
+
+impl PageToken {
+    /// Encodes the token as a string of hexadecimal digits.
+    pub fn encode(&self) -> Result<String> {
+        let doc = doc!{ "k": self.0.clone() };
+        let mut bytes = Vec::new();
+
+        bson::encode_document(&mut bytes, &doc)?;
+
+        let mut encoded = String::with_capacity(bytes.len() * 2);
+
+        for byte in bytes {
+            write!(encoded, "{:02x}", byte).map_err(|_| Error::new(
+                ErrorKind::BsonEncoding, "can't format page token"
+            ))?;
+        }
+
+        Ok(encoded)
+    }
+
+    /// Decodes a token previously produced by `encode()`.
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let malformed = || Error::new(
+            ErrorKind::BsonDecoding,
+            format!("malformed page token: {:?}", encoded)
+        );
+
+        if encoded.len() % 2 != 0 {
+            return Err(malformed());
+        }
+
+        let bytes: Vec<u8> = encoded
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                if pair.iter().all(u8::is_ascii_hexdigit) {
+                    str::from_utf8(pair).ok().and_then(
+                        |digits| u8::from_str_radix(digits, 16).ok()
+                    )
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(&malformed)?;
+
+        let mut doc = bson::decode_document(&mut bytes.as_slice())?;
+
+        match doc.remove("k") {
+            Some(Bson::Array(values)) => Ok(PageToken(values)),
+            _ => Err(malformed()),
+        }
+    }
+}
+
+impl Display for PageToken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.encode().map_err(|_| fmt::Error)?)
+    }
+}
+
+impl FromStr for PageToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PageToken::decode(s)
+    }
+}
+
+impl Serialize for PageToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::Error as SerError;
+
+        let encoded = self.encode().map_err(S::Error::custom)?;
+        serializer.serialize_str(&encoded)
+    }
+}
+
+impl<'a> Deserialize<'a> for PageToken {
+    fn deserialize<D: Deserializer<'a>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        PageToken::decode(&encoded).map_err(D::Error::custom)
+    }
+}
+
+/// The query actually sent to the database by `Paginator::find_page()`.
+#[derive(Debug)]
+struct PageQuery<'a, Q> {
+    /// The paginator that created this query.
+    paginator: &'a Paginator<Q>,
+    /// The original filter, combined with the keyset condition.
+    filter: Document,
+    /// The number of documents to request, as computed by `fetch_limit()`.
+    limit: i64,
+}
+
+impl<'a, T: Doc, Q: Query<T>> Query<T> for PageQuery<'a, Q> {
+    type Output = Document;
+
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+
+    fn options(&self) -> FindOptions {
+        FindOptions {
+            sort: Some(self.paginator.sort_document()),
+            limit: Some(self.limit),
+            skip: None,
+            ..self.paginator.inner.options()
+        }
+    }
+
+    fn include_deleted(&self) -> bool {
+        self.paginator.inner.include_deleted()
+    }
+}
+
+/// The pipeline actually sent to the database by `Paginator::aggregate_page()`.
+#[derive(Debug)]
+struct PagePipeline<'a, P> {
+    /// The paginator that created this pipeline.
+    paginator: &'a Paginator<P>,
+    /// The original stages, followed by the pagination stages.
+    stages: Vec<Document>,
+}
+
+impl<'a, T: Doc, P: Pipeline<T>> Pipeline<T> for PagePipeline<'a, P> {
+    type Output = Document;
+
+    fn stages(&self) -> Vec<Document> {
+        self.stages.clone()
+    }
+
+    fn options(&self) -> AggregateOptions {
+        self.paginator.inner.options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_is_appended_as_tie_breaker() -> Result<()> {
+        let without_id = Paginator::new(Document::new(), vec![("score", Order::Descending)], 10)?;
+        let with_id = Paginator::new(
+            Document::new(),
+            vec![("_id", Order::Descending), ("ignored", Order::Ascending)],
+            0,
+        )?;
+
+        assert_eq!(without_id.sort_keys(), &[
+            (String::from("score"), Order::Descending),
+            (String::from("_id"), Order::Ascending),
+        ]);
+        assert_eq!(with_id.sort_keys(), &[
+            (String::from("_id"), Order::Descending),
+        ]);
+        assert_eq!(with_id.page_size(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let error = Paginator::new(
+            Document::new(),
+            vec![("score", Order::Descending), ("score", Order::Ascending)],
+            10,
+        ).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidConfiguration);
+    }
+
+    #[test]
+    fn typed_sort_is_accepted() -> Result<()> {
+        let sort = Sort::<()>::by_desc("score").then_asc("name")?;
+        let paginator = Paginator::sorted_by(Document::new(), &sort, 10)?;
+        let text = Sort::<()>::by_text_score("relevance");
+
+        assert_eq!(paginator.sort_keys(), &[
+            (String::from("score"), Order::Descending),
+            (String::from("name"), Order::Ascending),
+            (String::from("_id"), Order::Ascending),
+        ]);
+        assert!(Paginator::sorted_by(Document::new(), &text, 10).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn keyset_filter_respects_order() -> Result<()> {
+        let paginator = Paginator::new(
+            Document::new(),
+            vec![("score", Order::Descending), ("name", Order::Ascending)],
+            10,
+        )?;
+        let token = PageToken(vec![Bson::I32(42), Bson::from("Bob"), Bson::I64(7)]);
+
+        assert_eq!(paginator.keyset_filter(&token)?, doc!{
+            "$or": [
+                { "score": { "$lt": 42 } },
+                { "score": { "$eq": 42 }, "name": { "$gt": "Bob" } },
+                {
+                    "score": { "$eq": 42 },
+                    "name": { "$eq": "Bob" },
+                    "_id": { "$gt": 7_i64 },
+                },
+            ]
+        });
+
+        let short = PageToken(vec![Bson::I32(42)]);
+        assert!(paginator.keyset_filter(&short).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_filter_combines_with_query() -> Result<()> {
+        let paginator = Paginator::new(Document::new(), Vec::<(String, _)>::new(), 5)?;
+        let token = PageToken(vec![Bson::I32(3)]);
+        let filter = doc!{ "active": true };
+
+        assert_eq!(paginator.page_filter(filter.clone(), None)?, filter);
+        assert_eq!(paginator.page_filter(Document::new(), Some(&token))?, doc!{
+            "$or": [{ "_id": { "$gt": 3 } }]
+        });
+        assert_eq!(paginator.page_filter(filter, Some(&token))?, doc!{
+            "$and": [
+                { "active": true },
+                { "$or": [{ "_id": { "$gt": 3 } }] },
+            ]
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_is_extracted_from_nested_keys() -> Result<()> {
+        let paginator = Paginator::new(Document::new(), vec![("stats.score", Order::Ascending)], 5)?;
+        let good = doc!{ "_id": 1, "stats": { "score": 2.5 } };
+        let bad = doc!{ "_id": 1, "score": 2.5 };
+
+        assert_eq!(paginator.token_for(&good)?,
+                   PageToken(vec![Bson::FloatingPoint(2.5), Bson::I32(1)]));
+        assert!(paginator.token_for(&bad).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_round_trip() -> Result<()> {
+        let token = PageToken(vec![
+            Bson::from("some string"),
+            Bson::I64(-1337),
+            Bson::from(doc!{ "nested": [1, 2, 3] }),
+        ]);
+        let encoded = token.encode()?;
+
+        assert!(encoded.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(PageToken::decode(&encoded)?, token);
+        assert_eq!(encoded.parse::<PageToken>()?, token);
+        assert_eq!(token.to_string(), encoded);
+
+        assert!(PageToken::decode("abc").is_err());
+        assert!(PageToken::decode("zz").is_err());
+        assert!(PageToken::decode("00000000").is_err());
+
+        Ok(())
+    }
+}
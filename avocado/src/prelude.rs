@@ -4,19 +4,23 @@
 pub use crate::{
     db::DatabaseExt,
     coll::{ Collection, InsertManyErrorContext },
-    doc::Doc,
+    doc::{ Doc, DocHooks, DocName, DocVariant, CappedOptions },
     uid::Uid,
     ops::*,
+    pagination::{ Paginator, Page, PageToken },
+    sort::{ Sort, SortOrder },
+    tracked::{ Tracked, UnitOfWork },
     ext::*,
     literal::{ IndexType, Order, BsonType },
     error::Error as AvocadoError,
     error::ErrorKind as AvocadoErrorKind,
     error::Result as AvocadoResult,
 };
-pub use bson::{ Bson, Document, oid::ObjectId, doc, bson };
+pub use bson::{ Bson, Document, UtcDateTime, oid::ObjectId, doc, bson };
 pub use mongodb::{
     Client, ThreadedClient,
     db::Database,
+    common::WriteConcern,
     coll::options::{
         IndexModel, IndexOptions, FindOptions,
         FindOneAndUpdateOptions, ReturnDocument,
@@ -0,0 +1,359 @@
+//! Resumable scans over entire collections, with persistent checkpoints.
+//!
+//! A scan visits every document of a collection in ascending `_id` order,
+//! in batches of a fixed size. After each batch has been processed, the `_id`
+//! of its last document is saved to a `CheckpointStore`. If the process dies
+//! in the middle of the scan, re-running it with the same checkpoint store
+//! resumes right after the last completely processed batch.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! # use avocado::scan::FileCheckpoint;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! struct Customer {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<Customer>,
+//!     email: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let customers: Collection<Customer> = db.existing_collection();
+//! let mut checkpoint = FileCheckpoint::new("/var/tmp/lowercase-emails.ckpt");
+//!
+//! let processed = customers.scan(500, &mut checkpoint, |coll, batch| {
+//!     for mut customer in batch {
+//!         customer.email = customer.email.to_lowercase();
+//!         coll.replace_entity(&customer)?;
+//!     }
+//!     Ok(())
+//! })?;
+//!
+//! println!("processed {} customers", processed);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs;
+use std::io::{ Write, ErrorKind as IoErrorKind };
+use std::path::{ Path, PathBuf };
+use std::fmt::{ Debug, Formatter, Result as FmtResult };
+use bson::{ Bson, Document, from_bson, to_bson };
+use mongodb::coll::options::{ FindOptions, UpdateOptions };
+use crate::{
+    coll::Collection,
+    doc::Doc,
+    uid::Uid,
+    ops::Query,
+    literal::Order,
+    utils::usize_to_i64_with_msg,
+    error::{ Error, ErrorKind, Result, ResultExt },
+};
+
+/// A persistent place for storing the progress of a scan.
+pub trait CheckpointStore<T: Doc> {
+    /// Returns the `_id` of the last processed document, if any.
+    fn load(&mut self) -> Result<Option<Uid<T>>>;
+
+    /// Records the `_id` of the last processed document.
+    fn save(&mut self, last: &Uid<T>) -> Result<()>;
+
+    /// Forgets the checkpoint, so that the next scan starts from the beginning.
+    fn clear(&mut self) -> Result<()>;
+}
+
+/// Stores the checkpoint in a local file, as a small BSON document.
+///
+/// The file is replaced atomically upon each save, by writing a temporary file
+/// next to it first and then renaming the temporary file to the final path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileCheckpoint {
+    /// The path of the checkpoint file.
+    path: PathBuf,
+}
+
+impl FileCheckpoint {
+    /// Creates a checkpoint store backed by the file at `path`.
+    /// The file doesn't need to exist yet.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileCheckpoint { path: path.into() }
+    }
+
+    /// Returns the path of the checkpoint file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<T: Doc> CheckpointStore<T> for FileCheckpoint {
+    fn load(&mut self) -> Result<Option<Uid<T>>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(ref error) if error.kind() == IoErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(error) => return Err(Error::with_cause(
+                format!("can't read checkpoint file {}", self.path.display()),
+                error
+            )),
+        };
+        let mut doc = bson::decode_document(&mut bytes.as_slice())?;
+
+        checkpoint_from_document(&mut doc)
+    }
+
+    fn save(&mut self, last: &Uid<T>) -> Result<()> {
+        let mut bytes = Vec::new();
+        let tmp_path = self.path.with_extension("tmp");
+        let message = || format!("can't write checkpoint file {}", self.path.display());
+
+        bson::encode_document(&mut bytes, &doc!{ "last": to_bson(last)? })?;
+
+        fs::File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(&bytes)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .chain(message)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(ref error) if error.kind() == IoErrorKind::NotFound => Ok(()),
+            result => result.chain(
+                || format!("can't remove checkpoint file {}", self.path.display())
+            ),
+        }
+    }
+}
+
+/// Stores checkpoints in a bookkeeping collection, one document per scan.
+/// The `_id` of the document is the name of the scan, and the last processed
+/// `_id` is stored under the key `last`.
+pub struct CollectionCheckpoint {
+    /// The bookkeeping collection.
+    inner: mongodb::coll::Collection,
+    /// The name of the scan, used as the `_id` of its bookkeeping document.
+    name: String,
+}
+
+impl CollectionCheckpoint {
+    /// Creates a checkpoint store for the scan called `name`, persisting
+    /// checkpoints in the given (untyped) bookkeeping collection.
+    pub fn new<S: Into<String>>(collection: mongodb::coll::Collection, name: S) -> Self {
+        CollectionCheckpoint {
+            inner: collection,
+            name: name.into(),
+        }
+    }
+
+    /// Returns the name of the scan.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The filter selecting the bookkeeping document of this scan.
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.name.as_str() }
+    }
+}
+
+impl<T: Doc> CheckpointStore<T> for CollectionCheckpoint {
+    fn load(&mut self) -> Result<Option<Uid<T>>> {
+        let message = || format!("can't load checkpoint `{}`", self.name);
+
+        match self.inner.find_one(self.filter().into(), None).chain(message)? {
+            Some(mut doc) => checkpoint_from_document(&mut doc),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, last: &Uid<T>) -> Result<()> {
+        let replacement = doc!{
+            "_id": self.name.as_str(),
+            "last": to_bson(last)?,
+        };
+        let options = UpdateOptions {
+            upsert: Some(true),
+            write_concern: None,
+        };
+        let message = || format!("can't save checkpoint `{}`", self.name);
+
+        self.inner
+            .replace_one(self.filter(), replacement, options.into())
+            .chain(&message)
+            .and_then(|result| match result.write_exception {
+                Some(error) => Err(Error::with_cause(message(), error)),
+                None => Ok(()),
+            })
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let message = || format!("can't clear checkpoint `{}`", self.name);
+
+        self.inner
+            .delete_one(self.filter(), None)
+            .chain(&message)
+            .and_then(|result| match result.write_exception {
+                Some(error) => Err(Error::with_cause(message(), error)),
+                None => Ok(()),
+            })
+    }
+}
+
+impl Debug for CollectionCheckpoint {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("CollectionCheckpoint")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// Extracts the last processed `_id` from a checkpoint document.
+fn checkpoint_from_document<T: Doc>(doc: &mut Document) -> Result<Option<Uid<T>>> {
+    match doc.remove("last") {
+        Some(Bson::Null) | None => Ok(None),
+        Some(last) => from_bson(last).chain("can't deserialize checkpoint"),
+    }
+}
+
+impl<T: Doc> Collection<T> {
+    /// Visits all documents in ascending `_id` order, `batch_size` documents
+    /// at a time, resuming after the last checkpoint found in `checkpoint`.
+    ///
+    /// `callback` receives this collection, so that it can e.g. update the
+    /// processed entities, as well as the current batch. Once it returns
+    /// successfully, the `_id` of the last entity in the batch is saved as
+    /// the new checkpoint. If it returns an error, the scan stops, and the
+    /// error is propagated without touching the checkpoint, so the failed
+    /// batch will be processed again when the scan is resumed.
+    ///
+    /// The checkpoint is left in place after the scan finishes, so re-running
+    /// a completed scan only visits documents inserted since then with a
+    /// greater `_id`. Call `CheckpointStore::clear()` to start over.
+    ///
+    /// Returns the number of documents processed during this invocation.
+    pub fn scan<C, F>(&self, batch_size: usize, checkpoint: &mut C, mut callback: F) -> Result<usize>
+        where C: CheckpointStore<T>,
+              F: FnMut(&Self, Vec<T>) -> Result<()>,
+              T::Id: Clone,
+    {
+        let limit = usize_to_i64_with_msg(batch_size.max(1), "batch size")?;
+        let mut last = checkpoint.load()?;
+        let mut num_processed = 0;
+
+        loop {
+            let query = ScanBatch {
+                after: match last {
+                    Some(ref id) => Some(to_bson(id)?),
+                    None => None,
+                },
+                limit,
+            };
+            let batch: Vec<T> = self.find_many(query)?.collect::<Result<_>>()?;
+            let batch_len = batch.len();
+            let batch_last = match batch.last() {
+                Some(entity) => entity.id().cloned().ok_or_else(|| Error::new(
+                    ErrorKind::MissingId,
+                    format!("No `_id` in scanned entity of type {}", T::NAME)
+                ))?,
+                None => break,
+            };
+
+            callback(self, batch)?;
+            checkpoint.save(&batch_last)?;
+
+            num_processed += batch_len;
+            last = Some(batch_last);
+        }
+
+        Ok(num_processed)
+    }
+}
+
+/// Retrieves the next batch of a scan.
+#[derive(Debug, Clone)]
+struct ScanBatch {
+    /// The raw `_id` of the last document of the previous batch, if any.
+    after: Option<Bson>,
+    /// The maximal number of documents in the batch.
+    limit: i64,
+}
+
+impl<T: Doc> Query<T> for ScanBatch {
+    type Output = T;
+
+    fn filter(&self) -> Document {
+        match self.after {
+            Some(ref id) => doc!{ "_id": { "$gt": id.clone() } },
+            None => Document::new(),
+        }
+    }
+
+    fn options(&self) -> FindOptions {
+        FindOptions {
+            sort: Some(doc!{ "_id": Order::Ascending }),
+            limit: Some(self.limit),
+            skip: None,
+            ..T::query_options()
+        }
+    }
+
+    fn after_load(output: &mut T) -> Result<()> {
+        output.after_load()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::process;
+    use super::*;
+    use crate::prelude::*;
+    use crate::fixture::Entity;
+
+    #[test]
+    fn file_checkpoint_round_trip() -> Result<()> {
+        let mut path = temp_dir();
+        path.push(format!("avocado_scan_test_{}.ckpt", process::id()));
+
+        let mut store = FileCheckpoint::new(path);
+        let first = Uid::<Entity>::new_oid()?;
+        let second = Uid::<Entity>::new_oid()?;
+
+        CheckpointStore::<Entity>::clear(&mut store)?;
+        assert_eq!(CheckpointStore::<Entity>::load(&mut store)?, None);
+
+        store.save(&first)?;
+        assert_eq!(CheckpointStore::<Entity>::load(&mut store)?, Some(first));
+
+        store.save(&second)?;
+        assert_eq!(CheckpointStore::<Entity>::load(&mut store)?, Some(second));
+
+        CheckpointStore::<Entity>::clear(&mut store)?;
+        CheckpointStore::<Entity>::clear(&mut store)?;
+        assert_eq!(CheckpointStore::<Entity>::load(&mut store)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn scan_batch_query() {
+        let first = ScanBatch { after: None, limit: 10 };
+        let next = ScanBatch { after: Some(Bson::I32(42)), limit: 10 };
+
+        assert_eq!(Query::<Entity>::filter(&first), doc!{});
+        assert_eq!(Query::<Entity>::filter(&next), doc!{ "_id": { "$gt": 42 } });
+        assert_eq!(Query::<Entity>::options(&next).sort, Some(doc!{ "_id": 1 }));
+        assert_eq!(Query::<Entity>::options(&next).limit, Some(10));
+    }
+}
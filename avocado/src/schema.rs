@@ -0,0 +1,563 @@
+//! Client-side validation of documents against a MongoDB `$jsonSchema`.
+//!
+//! This makes it possible to check documents which never reach the server,
+//! e.g. fixtures or data being imported, the same way the server would check
+//! them upon insertion into a collection created by
+//! `DatabaseExt::empty_collection()`.
+//!
+//! The following keywords are supported: `bsonType`, `required`, `properties`,
+//! `additionalProperties`, `enum`, `items`, `minimum` and `maximum` (along with
+//! `exclusiveMinimum` and `exclusiveMaximum`), `pattern`, `anyOf` and `oneOf`.
+//! Other keywords, e.g. `description`, are ignored.
+//!
+//! Violations are reported as an error of kind `ErrorKind::Validation`, with a
+//! `ValidationErrors` context. Its keys are the paths of the offending values
+//! in dot notation, e.g. `address.city` or `tags.0`; violations pertaining to
+//! the document itself are recorded under the empty path.
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate bson;
+//! # extern crate avocado;
+//! #
+//! # use avocado::error::{ ErrorExt, ErrorKind, Result };
+//! # use avocado::schema::validate_document;
+//! # use avocado::validation::ValidationErrors;
+//! #
+//! # fn main() -> Result<()> {
+//! let schema = doc!{
+//!     "bsonType": "object",
+//!     "required": ["name", "age"],
+//!     "properties": {
+//!         "name": { "bsonType": "string", "pattern": "^[A-Z]" },
+//!         "age": { "bsonType": ["int", "long"], "minimum": 0 },
+//!     },
+//! };
+//!
+//! validate_document(&schema, &doc!{ "name": "Alice", "age": 42 })?;
+//!
+//! let error = validate_document(&schema, &doc!{ "name": "bob" }).unwrap_err();
+//! let violations = error.context::<ValidationErrors>().unwrap();
+//!
+//! assert_eq!(error.kind(), ErrorKind::Validation);
+//! assert_eq!(violations.field("name"), &["must match regular expression `^[A-Z]`"]);
+//! assert_eq!(violations.field("age"), &["required field is missing"]);
+//! # Ok(())
+//! # }
+//! ```
+
+use bson::{ Bson, Document };
+use crate::{
+    validation::{ ValidationErrors, check_regex },
+    bsn::join_path,
+    error::{ Error, ErrorKind, Result },
+};
+
+#[cfg(feature = "schema_validation")]
+use magnet_schema::BsonSchema;
+#[cfg(feature = "schema_validation")]
+use crate::{
+    doc::Doc,
+    uid::Uid,
+    bsn::BsonExt,
+    ext::DocumentExt,
+};
+
+/// The aliases accepted by the `bsonType` keyword, except for `number`.
+const TYPE_ALIASES: &[&str] = &[
+    "double", "string", "object", "array", "binData", "undefined",
+    "objectId", "bool", "date", "null", "regex", "dbPointer",
+    "javascript", "symbol", "javascriptWithScope", "int", "timestamp",
+    "long", "decimal", "minKey", "maxKey",
+];
+
+/// The types matched by the `number` alias.
+const NUMERIC_TYPES: &[&str] = &["double", "int", "long", "decimal"];
+
+/// Checks `document` against the `$jsonSchema` `schema`. Returns an error
+/// of kind `ErrorKind::Validation` if the document violates the schema, or
+/// an error of kind `ErrorKind::BsonSchema` if the schema is malformed.
+pub fn validate_document(schema: &Document, document: &Document) -> Result<()> {
+    let mut errors = ValidationErrors::new();
+    check_value(schema, &Bson::Document(document.clone()), "", &mut errors)?;
+    errors.into_result()
+}
+
+/// Checks `document` against the schema of the collection of `T`, exactly
+/// as created by `DatabaseExt::empty_collection()`.
+#[cfg(feature = "schema_validation")]
+pub fn validate_document_for<T>(document: &Document) -> Result<()>
+    where T: Doc + BsonSchema,
+          Uid<T>: BsonSchema,
+{
+    validate_document(&collection_schema::<T>()?, document)
+}
+
+/// Returns the `$jsonSchema` of the collection of `T`: the BSON schema of the
+/// type, with the schema of the `_id` field added or checked for consistency.
+#[cfg(feature = "schema_validation")]
+pub fn collection_schema<T>() -> Result<Document>
+    where T: Doc + BsonSchema,
+          Uid<T>: BsonSchema,
+{
+    let mut schema = T::bson_schema();
+    let mut properties = schema
+        .remove_document("properties")
+        .and_then(Bson::try_into_doc)?;
+
+    if properties.contains_key("_id") {
+        let id_schema = properties.get_document("_id")?;
+
+        if
+            *id_schema != Uid::<T>::bson_schema()
+            &&
+            *id_schema != Option::<Uid<T>>::bson_schema()
+        {
+            return Err(Error::new(ErrorKind::BsonSchema, "BSON schema mismatch for _id"));
+        }
+    } else {
+        properties.insert("_id", Uid::<T>::bson_schema());
+    }
+
+    schema.insert("properties", properties);
+    Ok(schema)
+}
+
+/// Returns the `bsonType` alias for the type of `value`.
+pub fn type_alias(value: &Bson) -> &'static str {
+    match *value {
+        Bson::FloatingPoint(_) => "double",
+        Bson::String(_) => "string",
+        Bson::Array(_) => "array",
+        Bson::Document(_) => "object",
+        Bson::Boolean(_) => "bool",
+        Bson::Null => "null",
+        Bson::RegExp(..) => "regex",
+        Bson::JavaScriptCode(_) => "javascript",
+        Bson::JavaScriptCodeWithScope(..) => "javascriptWithScope",
+        Bson::I32(_) => "int",
+        Bson::I64(_) => "long",
+        Bson::TimeStamp(_) => "timestamp",
+        Bson::Binary(..) => "binData",
+        Bson::ObjectId(_) => "objectId",
+        Bson::UtcDatetime(_) => "date",
+        Bson::Symbol(_) => "symbol",
+    }
+}
+
+/// Returns `true` if `value` is of one of the types specified by `argument`,
+/// i.e. the argument of a `bsonType` keyword: a type alias or an array thereof.
+/// Returns an error if `argument` is malformed or contains an unknown alias.
+pub fn matches_type(argument: &Bson, value: &Bson) -> Result<bool> {
+    let actual = type_alias(value);
+
+    for alias in type_aliases(argument)? {
+        let matches = match alias {
+            "number" => NUMERIC_TYPES.contains(&actual),
+            _ if TYPE_ALIASES.contains(&alias) => alias == actual,
+            _ => return Err(Error::new(
+                ErrorKind::BsonSchema,
+                format!("unknown BSON type alias `{}`", alias)
+            )),
+        };
+
+        if matches {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Checks `value`, found at `path`, against `schema`,
+/// recording violations in `errors`.
+fn check_value(
+    schema: &Document,
+    value: &Bson,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    for (keyword, argument) in schema {
+        match keyword.as_str() {
+            "bsonType" => check_type(argument, value, path, errors)?,
+            "enum" => check_enum(argument, value, path, errors)?,
+            "minimum" | "maximum" => check_bound(schema, keyword, argument, value, path, errors)?,
+            "pattern" => check_pattern(argument, value, path, errors)?,
+            "anyOf" | "oneOf" => check_alternatives(keyword, argument, value, path, errors)?,
+            _ => {}
+        }
+    }
+
+    match *value {
+        Bson::Document(ref document) => check_object(schema, document, path, errors),
+        Bson::Array(ref array) => check_array(schema, array, path, errors),
+        _ => Ok(()),
+    }
+}
+
+/// Implements the `bsonType` keyword.
+fn check_type(
+    argument: &Bson,
+    value: &Bson,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    if !matches_type(argument, value)? {
+        let names: Vec<_> = type_aliases(argument)?
+            .iter()
+            .map(|alias| format!("`{}`", alias))
+            .collect();
+
+        errors.add(path, format!(
+            "must be of type {}, found `{}`", names.join(" or "), type_alias(value)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extracts the type aliases from the argument of a `bsonType` keyword.
+fn type_aliases(argument: &Bson) -> Result<Vec<&str>> {
+    let expected = "a type alias or an array of type aliases";
+
+    match *argument {
+        Bson::String(ref alias) => Ok(vec![alias.as_str()]),
+        Bson::Array(ref array) => array
+            .iter()
+            .map(|item| match *item {
+                Bson::String(ref alias) => Ok(alias.as_str()),
+                _ => malformed("bsonType", expected),
+            })
+            .collect(),
+        _ => malformed("bsonType", expected),
+    }
+}
+
+/// Implements the `enum` keyword.
+fn check_enum(
+    argument: &Bson,
+    value: &Bson,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    let allowed = match *argument {
+        Bson::Array(ref allowed) => allowed,
+        _ => return malformed("enum", "an array"),
+    };
+
+    if !allowed.iter().any(|item| values_equal(item, value)) {
+        errors.add(path, format!("must be one of {}", argument));
+    }
+
+    Ok(())
+}
+
+/// Implements the `minimum` and `maximum` keywords, taking the corresponding
+/// `exclusiveMinimum` or `exclusiveMaximum` keyword into account.
+/// Non-numeric values are ignored.
+fn check_bound(
+    schema: &Document,
+    keyword: &str,
+    argument: &Bson,
+    value: &Bson,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    let bound = match as_f64(argument) {
+        Some(bound) => bound,
+        None => return malformed(keyword, "a number"),
+    };
+    let number = match as_f64(value) {
+        Some(number) => number,
+        None => return Ok(()),
+    };
+    let is_minimum = keyword == "minimum";
+    let exclusive_keyword = if is_minimum { "exclusiveMinimum" } else { "exclusiveMaximum" };
+    let exclusive = match schema.get(exclusive_keyword) {
+        None => false,
+        Some(&Bson::Boolean(exclusive)) => exclusive,
+        Some(_) => return malformed(exclusive_keyword, "a boolean"),
+    };
+    let message = match (is_minimum, exclusive) {
+        (true, false) if number < bound => "must be at least",
+        (true, true) if number <= bound => "must be greater than",
+        (false, false) if number > bound => "must be at most",
+        (false, true) if number >= bound => "must be less than",
+        _ => return Ok(()),
+    };
+
+    errors.add(path, format!("{} {}", message, argument));
+
+    Ok(())
+}
+
+/// Implements the `pattern` keyword. Non-string values are ignored.
+fn check_pattern(
+    argument: &Bson,
+    value: &Bson,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    let pattern = match *argument {
+        Bson::String(ref pattern) => pattern,
+        _ => return malformed("pattern", "a string"),
+    };
+
+    if let Bson::String(ref string) = *value {
+        if let Some(message) = check_regex(string, pattern)? {
+            errors.add(path, message);
+        }
+    }
+
+    Ok(())
+}
+
+/// Implements the `anyOf` and `oneOf` keywords.
+fn check_alternatives(
+    keyword: &str,
+    argument: &Bson,
+    value: &Bson,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    let alternatives = match *argument {
+        Bson::Array(ref alternatives) => alternatives,
+        _ => return malformed(keyword, "an array of documents"),
+    };
+    let mut num_matching = 0;
+
+    for alternative in alternatives {
+        let mut violations = ValidationErrors::new();
+        check_value(as_schema(keyword, alternative)?, value, path, &mut violations)?;
+
+        if violations.is_empty() {
+            num_matching += 1;
+        }
+    }
+
+    if keyword == "anyOf" && num_matching == 0 {
+        errors.add(path, "must match at least one of the `anyOf` schemas");
+    } else if keyword == "oneOf" && num_matching != 1 {
+        errors.add(path, format!(
+            "must match exactly one of the `oneOf` schemas, but matches {}", num_matching
+        ));
+    }
+
+    Ok(())
+}
+
+/// Implements the `required`, `properties` and `additionalProperties`
+/// keywords.
+fn check_object(
+    schema: &Document,
+    document: &Document,
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    if let Some(required) = schema.get("required") {
+        let names = match *required {
+            Bson::Array(ref names) => names,
+            _ => return malformed("required", "an array of strings"),
+        };
+
+        for name in names {
+            match *name {
+                Bson::String(ref name_str) => if !document.contains_key(name_str) {
+                    errors.add(join_path(path, name_str), "required field is missing");
+                },
+                _ => return malformed("required", "an array of strings"),
+            }
+        }
+    }
+
+    let properties = match schema.get("properties") {
+        None => None,
+        Some(&Bson::Document(ref properties)) => Some(properties),
+        Some(_) => return malformed("properties", "a document"),
+    };
+
+    for (key, field) in document {
+        let field_path = join_path(path, key);
+
+        match properties.and_then(|props| props.get(key)) {
+            Some(property) => {
+                check_value(as_schema("properties", property)?, field, &field_path, errors)?;
+            }
+            None => match schema.get("additionalProperties") {
+                None | Some(&Bson::Boolean(true)) => {}
+                Some(&Bson::Boolean(false)) => errors.add(field_path, "field is not allowed"),
+                Some(&Bson::Document(ref additional)) => {
+                    check_value(additional, field, &field_path, errors)?;
+                }
+                Some(_) => return malformed("additionalProperties", "a boolean or a document"),
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Implements the `items` keyword, either with a single schema applied to
+/// every item, or with an array of schemas applied to items positionally.
+fn check_array(
+    schema: &Document,
+    array: &[Bson],
+    path: &str,
+    errors: &mut ValidationErrors,
+) -> Result<()> {
+    let expected = "a document or an array of documents";
+
+    match schema.get("items") {
+        None => Ok(()),
+        Some(&Bson::Document(ref items)) => {
+            for (index, item) in array.iter().enumerate() {
+                check_value(items, item, &join_path(path, &index.to_string()), errors)?;
+            }
+            Ok(())
+        }
+        Some(&Bson::Array(ref items)) => {
+            for (index, (item_schema, item)) in items.iter().zip(array).enumerate() {
+                let item_path = join_path(path, &index.to_string());
+                check_value(as_schema("items", item_schema)?, item, &item_path, errors)?;
+            }
+            Ok(())
+        }
+        Some(_) => malformed("items", expected),
+    }
+}
+
+/// Returns the error for a keyword with an argument of unexpected type.
+fn malformed<T>(keyword: &str, expected: &str) -> Result<T> {
+    Err(Error::new(
+        ErrorKind::BsonSchema,
+        format!("`{}` must be {}", keyword, expected)
+    ))
+}
+
+/// Ensures that a subschema, i.e. the argument of `keyword`, is a document.
+fn as_schema<'a>(keyword: &str, subschema: &'a Bson) -> Result<&'a Document> {
+    match *subschema {
+        Bson::Document(ref document) => Ok(document),
+        _ => malformed(keyword, "a document or contain documents"),
+    }
+}
+
+/// Converts a numeric value to `f64` for comparison.
+#[allow(clippy::cast_precision_loss)]
+fn as_f64(value: &Bson) -> Option<f64> {
+    match *value {
+        Bson::FloatingPoint(number) => Some(number),
+        Bson::I32(number) => Some(f64::from(number)),
+        Bson::I64(number) => Some(number as f64),
+        _ => None,
+    }
+}
+
+/// Compares two values like the server does, i.e. numbers of different
+/// types are equal if their values are.
+#[allow(clippy::float_cmp)]
+fn values_equal(lhs: &Bson, rhs: &Bson) -> bool {
+    match (as_f64(lhs), as_f64(rhs)) {
+        (Some(x), Some(y)) => x == y,
+        _ => lhs == rhs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorExt;
+
+    /// Returns the violations of `document` against `schema`.
+    fn violations(schema: &Document, document: &Document) -> ValidationErrors {
+        validate_document(schema, document)
+            .err()
+            .and_then(|error| error.context::<ValidationErrors>().cloned())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn schema_keywords() {
+        let schema = doc!{
+            "bsonType": "object",
+            "required": ["_id", "name"],
+            "additionalProperties": false,
+            "properties": {
+                "_id": { "bsonType": "objectId" },
+                "name": { "bsonType": "string", "pattern": "^[a-z]+$" },
+                "score": { "bsonType": "number", "minimum": 0, "maximum": 10, "exclusiveMaximum": true },
+                "kind": { "enum": ["user", "admin", 3] },
+                "tags": { "bsonType": "array", "items": { "bsonType": "string" } },
+                "point": { "bsonType": "array", "items": [{ "bsonType": "double" }, { "bsonType": "double" }] },
+                "address": {
+                    "bsonType": ["object", "null"],
+                    "required": ["city"],
+                    "properties": { "city": { "bsonType": "string" } },
+                },
+                "contact": {
+                    "anyOf": [
+                        { "bsonType": "string", "pattern": "@" },
+                        { "bsonType": "long" },
+                    ],
+                },
+                "flag": {
+                    "oneOf": [{ "bsonType": "bool" }, { "bsonType": ["bool", "null"] }],
+                },
+            },
+        };
+        let id = bson::oid::ObjectId::new().expect("can't generate ObjectId");
+
+        assert!(violations(&schema, &doc!{
+            "_id": id.clone(),
+            "name": "foo",
+            "score": 9.5,
+            "kind": 3.0,
+            "tags": ["a", "b"],
+            "point": [1.0, 2.0, "extra"],
+            "address": Bson::Null,
+            "contact": 42_i64,
+            "flag": Bson::Null,
+        }).is_empty());
+
+        let errors = violations(&schema, &doc!{
+            "name": "Foo",
+            "score": 10,
+            "kind": "guest",
+            "tags": ["a", 1],
+            "point": [1, 2.0],
+            "address": {},
+            "contact": "nobody",
+            "flag": true,
+            "extra": 1,
+        });
+
+        assert_eq!(errors.field("_id"), &["required field is missing"]);
+        assert_eq!(errors.field("name"), &["must match regular expression `^[a-z]+$`"]);
+        assert_eq!(errors.field("score"), &["must be less than 10"]);
+        assert_eq!(errors.field("kind").len(), 1);
+        assert_eq!(errors.field("tags.1"), &["must be of type `string`, found `int`"]);
+        assert_eq!(errors.field("point.0"), &["must be of type `double`, found `int`"]);
+        assert_eq!(errors.field("address.city"), &["required field is missing"]);
+        assert_eq!(errors.field("contact"), &["must match at least one of the `anyOf` schemas"]);
+        assert_eq!(errors.field("flag"), &["must match exactly one of the `oneOf` schemas, but matches 2"]);
+        assert_eq!(errors.field("extra"), &["field is not allowed"]);
+        assert_eq!(errors.len(), 10);
+    }
+
+    #[test]
+    fn malformed_schema() {
+        let malformed_schemas = vec![
+            doc!{ "bsonType": "integer" },
+            doc!{ "bsonType": 1 },
+            doc!{ "required": "name" },
+            doc!{ "properties": { "name": "string" } },
+            doc!{ "minimum": "zero" },
+        ];
+        let document = doc!{ "name": "foo", "value": 1 };
+
+        for schema in &malformed_schemas {
+            let error = validate_document(schema, &document).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::BsonSchema);
+        }
+    }
+}
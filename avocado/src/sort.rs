@@ -0,0 +1,274 @@
+//! Typed sort specifications, for queries, aggregation pipelines and
+//! pagination.
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! struct Player {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<Player>,
+//!     name: String,
+//!     score: u32,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! let sort = Sort::<Player>::by_desc("score").then_asc("name")?;
+//!
+//! assert_eq!(sort.to_document(), doc!{ "score": -1, "name": 1 });
+//! assert_eq!(sort.reversed()?.to_document(), doc!{ "score": 1, "name": -1 });
+//! assert_eq!(sort.stage(), doc!{ "$sort": { "score": -1, "name": 1 } });
+//! assert!(sort.clone().then_desc("score").is_err());
+//!
+//! let options = FindOptions::from(sort);
+//! assert_eq!(options.sort, Some(doc!{ "score": -1, "name": 1 }));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::{ self, Debug, Formatter };
+use std::marker::PhantomData;
+use bson::{ Bson, Document };
+use mongodb::coll::options::FindOptions;
+use crate::{
+    literal::Order,
+    error::{ Error, ErrorKind, Result },
+};
+
+/// The way in which documents are ordered by a single sort key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SortOrder {
+    /// Sort by the value of the field, in the given order.
+    Order(Order),
+    /// Sort by the relevance score of a `$text` search, most relevant first.
+    /// The key is the field into which the score is projected, if any.
+    TextScore,
+}
+
+impl From<Order> for SortOrder {
+    fn from(order: Order) -> Self {
+        SortOrder::Order(order)
+    }
+}
+
+/// Converts to `1`, `-1`, or `{ "$meta": "textScore" }`, respectively.
+impl From<SortOrder> for Bson {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Order(direction) => Bson::from(direction),
+            SortOrder::TextScore => Bson::from(doc!{ "$meta": "textScore" }),
+        }
+    }
+}
+
+/// A sort specification for documents of type `T`: a sequence of distinct
+/// keys, in order of decreasing precedence, along with the ordering of each.
+///
+/// Unlike a loose sort `Document`, it can't contain the same key twice, and
+/// it can be reversed, e.g. for paging backwards. It converts into the `sort`
+/// of `FindOptions`, as well as into a `$sort` aggregation stage.
+pub struct Sort<T> {
+    /// The sort keys and their orderings, in order of decreasing precedence.
+    keys: Vec<(String, SortOrder)>,
+    /// Records the sorted document type.
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Sort<T> {
+    /// Sorts by `key`, in the given order.
+    pub fn by_order<S, O>(key: S, order: O) -> Self
+        where S: Into<String>,
+              O: Into<SortOrder>,
+    {
+        Sort {
+            keys: vec![(key.into(), order.into())],
+            marker: PhantomData,
+        }
+    }
+
+    /// Sorts by `key`, in ascending order.
+    pub fn by<S: Into<String>>(key: S) -> Self {
+        Self::by_order(key, Order::Ascending)
+    }
+
+    /// Sorts by `key`, in descending order.
+    pub fn by_desc<S: Into<String>>(key: S) -> Self {
+        Self::by_order(key, Order::Descending)
+    }
+
+    /// Sorts by the text search score, projected into `key`.
+    pub fn by_text_score<S: Into<String>>(key: S) -> Self {
+        Self::by_order(key, SortOrder::TextScore)
+    }
+
+    /// Breaks ties by `key`, in the given order. Returns an error of kind
+    /// `ErrorKind::InvalidConfiguration` if `key` is already a sort key.
+    pub fn then<S, O>(mut self, name: S, order: O) -> Result<Self>
+        where S: Into<String>,
+              O: Into<SortOrder>,
+    {
+        let key: String = name.into();
+
+        if self.keys.iter().any(|&(ref existing, _)| *existing == key) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfiguration,
+                format!("duplicate sort key `{}`", key)
+            ));
+        }
+
+        self.keys.push((key, order.into()));
+
+        Ok(self)
+    }
+
+    /// Breaks ties by `key`, in ascending order. See `then()` for errors.
+    pub fn then_asc<S: Into<String>>(self, key: S) -> Result<Self> {
+        self.then(key, Order::Ascending)
+    }
+
+    /// Breaks ties by `key`, in descending order. See `then()` for errors.
+    pub fn then_desc<S: Into<String>>(self, key: S) -> Result<Self> {
+        self.then(key, Order::Descending)
+    }
+
+    /// Breaks ties by the text search score, projected into `key`.
+    /// See `then()` for errors.
+    pub fn then_text_score<S: Into<String>>(self, key: S) -> Result<Self> {
+        self.then(key, SortOrder::TextScore)
+    }
+
+    /// Returns the sort keys and their orderings, in order of decreasing
+    /// precedence.
+    pub fn keys(&self) -> &[(String, SortOrder)] {
+        &self.keys
+    }
+
+    /// Returns the opposite sort specification, which orders documents by the
+    /// same keys, but in reverse. Returns an error of kind
+    /// `ErrorKind::InvalidConfiguration` if the documents are sorted by their
+    /// text search score, because MongoDB can only sort that in descending order.
+    pub fn reversed(&self) -> Result<Self> {
+        let keys = self.keys
+            .iter()
+            .map(|&(ref key, order)| match order {
+                SortOrder::Order(direction) => Ok((key.clone(), direction.reversed().into())),
+                SortOrder::TextScore => Err(Error::new(
+                    ErrorKind::InvalidConfiguration,
+                    format!("can't reverse the text score sort key `{}`", key)
+                )),
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Sort {
+            keys,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the sort document, e.g. `{ "score": -1, "name": 1 }`.
+    pub fn to_document(&self) -> Document {
+        self.keys
+            .iter()
+            .map(|&(ref key, order)| (key.clone(), Bson::from(order)))
+            .collect()
+    }
+
+    /// Returns the `$sort` stage of an aggregation pipeline.
+    pub fn stage(&self) -> Document {
+        doc!{ "$sort": self.to_document() }
+    }
+}
+
+impl<T> Clone for Sort<T> {
+    fn clone(&self) -> Self {
+        Sort {
+            keys: self.keys.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Sort<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys == other.keys
+    }
+}
+
+impl<T> Eq for Sort<T> {}
+
+impl<T> Debug for Sort<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Sort").field("keys", &self.keys).finish()
+    }
+}
+
+impl<T> From<Sort<T>> for Document {
+    fn from(sort: Sort<T>) -> Self {
+        sort.to_document()
+    }
+}
+
+/// Sets the `sort` field only, leaving the other options at their defaults.
+/// In order to combine the sort with other options, use struct update syntax:
+/// `FindOptions { sort: Some(sort.into()), ..other_options }`.
+impl<T> From<Sort<T>> for FindOptions {
+    fn from(sort: Sort<T>) -> Self {
+        FindOptions {
+            sort: Some(sort.to_document()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_keep_their_order() -> Result<()> {
+        let sort = Sort::<()>::by("z").then_desc("a")?.then_text_score("score")?;
+
+        assert_eq!(sort.keys(), &[
+            (String::from("z"), SortOrder::Order(Order::Ascending)),
+            (String::from("a"), SortOrder::Order(Order::Descending)),
+            (String::from("score"), SortOrder::TextScore),
+        ]);
+        assert_eq!(sort.stage(), doc!{
+            "$sort": {
+                "z": 1,
+                "a": -1,
+                "score": { "$meta": "textScore" },
+            }
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() -> Result<()> {
+        let sort = Sort::<()>::by_desc("a").then_asc("b")?;
+        let error = sort.clone().then_text_score("a").unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidConfiguration);
+        assert!(sort.then_asc("b").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reversal() -> Result<()> {
+        let sort = Sort::<()>::by_desc("a").then_asc("b")?;
+
+        assert_eq!(sort.reversed()?, Sort::by("a").then_desc("b")?);
+        assert_eq!(sort.reversed()?.reversed()?, sort);
+        assert!(Sort::<()>::by_text_score("score").reversed().is_err());
+
+        Ok(())
+    }
+}
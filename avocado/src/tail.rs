@@ -0,0 +1,270 @@
+//! Tailing capped collections, i.e. following newly-inserted documents.
+//!
+//! A `Tail` is an infinite iterator backed by a tailable, await-data cursor.
+//! When no new documents are available, it blocks until one is inserted.
+//! If the cursor dies (e.g. because the collection was empty when the cursor
+//! was opened, or the server dropped it), the iterator transparently reopens
+//! it, resuming after the `_id` of the last document it has yielded. This
+//! relies on `_id`s increasing in insertion order, which is the case with
+//! the default, `ObjectId`-based IDs when there's a single writer process.
+//! Other kinds of IDs, e.g. UUIDs, would cause documents to be skipped upon
+//! resuming, so the iterator yields an error for any document whose `_id`
+//! is not an `ObjectId`. The `_id` is always fetched for this purpose, even
+//! if the projection of the query excludes it; in that case, it is removed
+//! before the document is transformed and deserialized.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[avocado(capped(size = 1048576, max = 1000))]
+//! struct LogLine {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<LogLine>,
+//!     level: String,
+//!     message: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let log: Collection<LogLine> = db.empty_capped_collection()?;
+//!
+//! for line in log.tail(doc!{ "level": "error" }) {
+//!     println!("{}", line?.message);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+use std::time::Duration;
+use std::fmt::{ Debug, Formatter, Result as FmtResult };
+use bson::{ Bson, Document, oid::ObjectId, from_bson };
+use mongodb::coll::options::{ FindOptions, CursorType };
+use crate::{
+    coll::Collection,
+    cursor::Cursor,
+    doc::Doc,
+    ops::Query,
+    error::{ Error, ErrorKind, Result },
+    bsn::BsonExt,
+};
+
+/// The default delay before reopening a dead cursor.
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The default number of consecutive failures tolerated before an error
+/// is reported to the caller.
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+impl<T: Doc> Collection<T> {
+    /// Returns an infinite iterator over the documents matching `query`,
+    /// which blocks while waiting for new documents to be inserted. The
+    /// collection must be a capped collection. See the module-level
+    /// documentation for details.
+    pub fn tail<Q: Query<T>>(&self, query: Q) -> Tail<T, Q> {
+        let strip_id = query.options().projection.map_or(false, |projection| {
+            projection.get("_id").and_then(Bson::try_as_bool) == Some(false)
+        });
+
+        Tail {
+            coll: self,
+            query,
+            cursor: None,
+            cursor_is_fresh: false,
+            last_id: None,
+            strip_id,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            max_retries: DEFAULT_MAX_RETRIES,
+            num_failures: 0,
+        }
+    }
+}
+
+/// An iterator following the newly-inserted documents of a capped collection.
+/// Created by `Collection::tail()`.
+pub struct Tail<'a, T: Doc, Q: Query<T>> {
+    /// The tailed collection.
+    coll: &'a Collection<T>,
+    /// The query specifying which documents to follow.
+    query: Q,
+    /// The current tailable cursor, if it is alive.
+    cursor: Option<Cursor<Document>>,
+    /// Whether the current cursor has not yielded any documents yet.
+    cursor_is_fresh: bool,
+    /// The `_id` of the last yielded document.
+    last_id: Option<ObjectId>,
+    /// Whether the projection of the query excludes the `_id`, which is
+    /// nevertheless fetched for resuming.
+    strip_id: bool,
+    /// How long to wait before reopening a dead cursor.
+    retry_delay: Duration,
+    /// The number of consecutive failures tolerated before reporting an error.
+    max_retries: usize,
+    /// The number of consecutive failures since the last successful step.
+    num_failures: usize,
+}
+
+impl<'a, T: Doc, Q: Query<T>> Tail<'a, T, Q> {
+    /// Sets the delay before reopening a dead cursor. Defaults to 500ms.
+    pub fn retry_delay(self, retry_delay: Duration) -> Self {
+        Tail { retry_delay, ..self }
+    }
+
+    /// Sets the number of consecutive failures (e.g. network errors) that are
+    /// silently retried before the error is yielded from the iterator.
+    /// Defaults to 3.
+    pub fn max_retries(self, max_retries: usize) -> Self {
+        Tail { max_retries, ..self }
+    }
+
+    /// Opens a new tailable cursor, resuming after the last seen document.
+    fn open_cursor(&self) -> Result<Cursor<Document>> {
+        self.coll.find_many(TailQuery {
+            inner: &self.query,
+            after: self.last_id.clone(),
+        })
+    }
+
+    /// Records a failure. Returns the error if it should be reported,
+    /// otherwise waits before the next attempt.
+    fn fail(&mut self, error: Error) -> Option<Error> {
+        self.cursor = None;
+        self.num_failures += 1;
+
+        if self.num_failures > self.max_retries {
+            self.num_failures = 0;
+            Some(error)
+        } else {
+            thread::sleep(self.retry_delay);
+            None
+        }
+    }
+}
+
+impl<'a, T: Doc, Q: Query<T>> Iterator for Tail<'a, T, Q> {
+    type Item = Result<Q::Output>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.cursor.is_none() {
+                match self.open_cursor() {
+                    Ok(cursor) => {
+                        self.cursor = Some(cursor);
+                        self.cursor_is_fresh = true;
+                    }
+                    Err(error) => match self.fail(error) {
+                        Some(report) => return Some(Err(report)),
+                        None => continue,
+                    }
+                }
+            }
+
+            match self.cursor.as_mut().and_then(Iterator::next) {
+                Some(Ok(mut doc)) => {
+                    self.cursor_is_fresh = false;
+                    self.num_failures = 0;
+
+                    match doc.get("_id") {
+                        Some(&Bson::ObjectId(ref id)) => self.last_id = Some(id.clone()),
+                        Some(id) => return Some(Err(Error::new(
+                            ErrorKind::IllTypedDocumentField,
+                            format!("can't tail documents with a non-ObjectId `_id`: {}", id)
+                        ))),
+                        None => {}
+                    }
+
+                    if self.strip_id {
+                        doc.remove("_id");
+                    }
+
+                    return Some(Q::transform(doc).and_then(|b| {
+                        let mut output = from_bson(b)?;
+                        Q::after_load(&mut output)?;
+                        Ok(output)
+                    }));
+                }
+                Some(Err(error)) => match self.fail(error) {
+                    Some(report) => return Some(Err(report)),
+                    None => continue,
+                },
+                None => {
+                    // A cursor that died without yielding anything is not
+                    // worth reopening immediately, lest we spin in a loop.
+                    if self.cursor_is_fresh {
+                        thread::sleep(self.retry_delay);
+                    }
+                    self.cursor = None;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Doc, Q: Query<T>> Debug for Tail<'a, T, Q> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Tail")
+            .field("coll", &self.coll)
+            .field("query", &self.query)
+            .field("last_id", &self.last_id)
+            .field("retry_delay", &self.retry_delay)
+            .field("max_retries", &self.max_retries)
+            .finish()
+    }
+}
+
+/// Wraps the tailed query, making its cursor tailable and restricting
+/// it to documents inserted after the last one seen.
+#[derive(Debug, Clone)]
+struct TailQuery<'a, Q> {
+    /// The user-specified query.
+    inner: &'a Q,
+    /// The `_id` of the last document seen, if any.
+    after: Option<ObjectId>,
+}
+
+impl<'a, T: Doc, Q: Query<T>> Query<T> for TailQuery<'a, Q> {
+    type Output = Document;
+
+    fn filter(&self) -> Document {
+        let filter = self.inner.filter();
+        let after = match self.after {
+            Some(ref id) => doc!{ "_id": { "$gt": id.clone() } },
+            None => return filter,
+        };
+
+        if filter.is_empty() {
+            after
+        } else {
+            doc!{ "$and": [filter, after] }
+        }
+    }
+
+    /// Makes the cursor tailable, and ensures that the `_id` is fetched
+    /// even if the projection of the inner query excludes it.
+    fn options(&self) -> FindOptions {
+        let mut options = self.inner.options();
+
+        if let Some(ref mut projection) = options.projection {
+            if projection.contains_key("_id") {
+                projection.insert("_id", true);
+            }
+        }
+
+        FindOptions {
+            cursor_type: CursorType::TailableAwait,
+            ..options
+        }
+    }
+
+    fn include_deleted(&self) -> bool {
+        self.inner.include_deleted()
+    }
+}
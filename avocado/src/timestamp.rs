@@ -0,0 +1,149 @@
+//! Helpers for maintaining creation and modification timestamps.
+
+use bson::{ Bson, Document };
+use chrono::Utc;
+use crate::{
+    doc::Doc,
+    literal::DateTimeType,
+    bsn::{ remove_from_operators, insert_into_operator },
+};
+
+/// The update operators which may conflict with setting a timestamp field.
+const CONFLICTING_OPERATORS: &[&str] = &["$set", "$unset", "$setOnInsert", "$currentDate"];
+
+/// Sets both timestamps of a document which is about to be inserted.
+pub fn stamp_insert<T: Doc>(document: &mut Document) {
+    let now = now::<T>();
+
+    if let Some(field) = T::CREATED_AT_FIELD {
+        document.insert(field, now.clone());
+    }
+
+    if let Some(field) = T::UPDATED_AT_FIELD {
+        document.insert(field, now);
+    }
+}
+
+/// Sets the modification timestamp of a full replacement document.
+/// The creation timestamp is left as supplied by the caller.
+pub fn stamp_replacement<T: Doc>(document: &mut Document) {
+    if let Some(field) = T::UPDATED_AT_FIELD {
+        document.insert(field, now::<T>());
+    }
+}
+
+/// Makes an upsert which `$set`s all fields of an entity maintain the
+/// timestamps, like `stamp_update()`. The creation timestamp is moved into
+/// `$setOnInsert`, so that it doesn't overwrite the one of an existing
+/// document. If it is missing or `null`, the current date is used instead.
+pub fn stamp_upsert<T: Doc>(update: &mut Document) {
+    if let Some(field) = T::CREATED_AT_FIELD {
+        let created_at = match update.get("$set") {
+            Some(&Bson::Document(ref fields)) => fields.get(field).cloned(),
+            _ => None,
+        };
+
+        remove_from_operators(update, &["$set"], field);
+
+        match created_at {
+            None | Some(Bson::Null) => {}
+            Some(value) => insert_into_operator(update, "$setOnInsert", field, value),
+        }
+    }
+
+    stamp_update::<T>(update, true);
+}
+
+/// Makes an update document consisting of update operators maintain the
+/// timestamps: the modification timestamp is set to the current date by the
+/// server, overriding any other change to it, and if `upsert` is true, the
+/// creation timestamp is set when a document is inserted, unless the update
+/// already changes it explicitly.
+pub fn stamp_update<T: Doc>(update: &mut Document, upsert: bool) {
+    if let Some(field) = T::UPDATED_AT_FIELD {
+        remove_from_operators(update, CONFLICTING_OPERATORS, field);
+        insert_into_operator(update, "$currentDate", field, Bson::from(doc!{
+            "$type": T::TIMESTAMP_TYPE
+        }));
+    }
+
+    if let Some(field) = T::CREATED_AT_FIELD {
+        if upsert && !is_changed(update, field) {
+            insert_into_operator(update, "$setOnInsert", field, now::<T>());
+        }
+    }
+}
+
+/// Returns `true` if any of the update operators in `update` changes `field`.
+fn is_changed(update: &Document, field: &str) -> bool {
+    CONFLICTING_OPERATORS.iter().any(|&op| match update.get(op) {
+        Some(&Bson::Document(ref fields)) => fields.contains_key(field),
+        _ => false,
+    })
+}
+
+/// Returns the current time as a BSON value of type `T::TIMESTAMP_TYPE`.
+fn now<T: Doc>() -> Bson {
+    let now = Utc::now();
+
+    match T::TIMESTAMP_TYPE {
+        DateTimeType::Date => Bson::UtcDatetime(now),
+        // The upper 32 bits are the seconds since the epoch,
+        // the lower 32 bits are an ordinal, which we leave at 0.
+        DateTimeType::Timestamp => Bson::TimeStamp(now.timestamp() << 32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Entity;
+
+    #[test]
+    fn timestamps_are_maintained() {
+        let mut inserted = doc!{ "_id": 1 };
+        stamp_insert::<Entity>(&mut inserted);
+        assert!(inserted.get_time_stamp("created").is_ok());
+        assert_eq!(inserted.get("created"), inserted.get("updated"));
+
+        let mut replacement = doc!{ "_id": 1, "created": Bson::TimeStamp(1 << 32) };
+        stamp_replacement::<Entity>(&mut replacement);
+        assert_eq!(replacement.get("created"), Some(&Bson::TimeStamp(1 << 32)));
+        assert!(replacement.get_time_stamp("updated").is_ok());
+
+        let mut partial = doc!{ "_id": 1, "created": Bson::Null };
+        stamp_replacement::<Entity>(&mut partial);
+        assert_eq!(partial.get("created"), Some(&Bson::Null));
+
+        let mut upsert_entity = doc!{
+            "$set": { "x": 1, "created": Bson::TimeStamp(1 << 32), "updated": Bson::Null }
+        };
+        stamp_upsert::<Entity>(&mut upsert_entity);
+        assert_eq!(upsert_entity, doc!{
+            "$set": { "x": 1 },
+            "$setOnInsert": { "created": Bson::TimeStamp(1 << 32) },
+            "$currentDate": { "updated": { "$type": "timestamp" } },
+        });
+
+        let mut upsert_fresh = doc!{ "$set": { "x": 1, "created": Bson::Null } };
+        stamp_upsert::<Entity>(&mut upsert_fresh);
+        assert_eq!(upsert_fresh.get_document("$set").unwrap(), &doc!{ "x": 1 });
+        assert!(upsert_fresh.get_document("$setOnInsert").unwrap().get_time_stamp("created").is_ok());
+
+        let mut update = doc!{ "$set": { "x": 1, "updated": 0 } };
+        stamp_update::<Entity>(&mut update, false);
+        assert_eq!(update, doc!{
+            "$set": { "x": 1 },
+            "$currentDate": { "updated": { "$type": "timestamp" } },
+        });
+
+        let mut upsert = doc!{ "$unset": { "updated": "" } };
+        stamp_update::<Entity>(&mut upsert, true);
+        assert!(!upsert.contains_key("$unset"));
+        assert!(upsert.get_document("$setOnInsert").unwrap().get_time_stamp("created").is_ok());
+
+        let mut explicit = doc!{ "$set": { "created": 42 } };
+        stamp_update::<Entity>(&mut explicit, true);
+        assert!(!explicit.contains_key("$setOnInsert"));
+    }
+}
@@ -0,0 +1,414 @@
+//! Change tracking for entities, so that only modified fields are persisted.
+//!
+//! A `Tracked<T>` remembers the BSON representation of an entity as it was
+//! loaded from (or last saved to) the database. When it is saved, only the
+//! difference between that snapshot and the current state of the entity is
+//! sent to the database, in the form of `$set` and `$unset` operators.
+//!
+//! A `UnitOfWork` saves many tracked entities at once, potentially from
+//! several different collections, using one bulk write per collection.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! # use avocado::tracked::UnitOfWork;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! struct Account {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<Account>,
+//!     owner: String,
+//!     balance: i64,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let accounts: Collection<Account> = db.existing_collection();
+//! let mut alice = accounts.find_one_tracked(doc!{ "owner": "Alice" })?.unwrap();
+//! let mut bob = accounts.find_one_tracked(doc!{ "owner": "Bob" })?.unwrap();
+//!
+//! alice.balance -= 100;
+//! bob.balance += 100;
+//!
+//! let mut unit = UnitOfWork::new();
+//! unit.add(&mut alice);
+//! unit.add(&mut bob);
+//! unit.flush()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ops::{ Deref, DerefMut };
+use std::collections::BTreeMap;
+use std::fmt::{ Debug, Formatter, Result as FmtResult };
+use bson::{ Bson, Document, from_bson };
+use crate::{
+    coll::{ Collection, UpdateOneResult, UpdateManyResult, entity_diff },
+    doc::Doc,
+    ops::{ Query, Update },
+    bsn::{ serialize_document, diff_documents },
+    version::Version,
+    error::{ Error, ErrorKind, Result },
+};
+
+impl<T: Doc> Collection<T> {
+    /// Retrieves a single entity satisfying the query, wrapped in a `Tracked`
+    /// so that subsequent modifications can be saved efficiently.
+    pub fn find_one_tracked<Q>(&self, query: Q) -> Result<Option<Tracked<T>>>
+        where Q: Query<T, Output = T>
+    {
+        match self.find_one(query)? {
+            Some(entity) => Tracked::new(self, entity).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An entity along with a snapshot of its state in the database.
+/// Dereferences to the entity itself.
+pub struct Tracked<'a, T: Doc> {
+    /// The collection the entity belongs to.
+    coll: &'a Collection<T>,
+    /// The BSON representation of the entity when it was last synchronized.
+    snapshot: Document,
+    /// The (potentially modified) entity.
+    entity: T,
+}
+
+impl<'a, T: Doc> Tracked<'a, T> {
+    /// Starts tracking an entity which is known to be in sync with
+    /// the corresponding document stored in `coll`.
+    pub fn new(coll: &'a Collection<T>, entity: T) -> Result<Self> {
+        let snapshot = serialize_document(&entity)?;
+        Ok(Tracked { coll, snapshot, entity })
+    }
+
+    /// Returns the update document that would be sent by `save()`.
+    /// If the entity hasn't been modified, this is an empty document.
+    pub fn changes(&self) -> Result<Document> {
+        let current = serialize_document(&self.entity)?;
+        Ok(diff_documents(&self.snapshot, &current))
+    }
+
+    /// Returns `true` if the entity was modified since it was last synchronized.
+    pub fn is_modified(&self) -> Result<bool> {
+        self.changes().map(|changes| !changes.is_empty())
+    }
+
+    /// Persists the modified fields of the entity, if any. If nothing changed,
+    /// no request is sent to the database, and the returned result has
+    /// `matched` and `modified` both set to false.
+    ///
+    /// Just like with `Collection::update_entity_diff()`, a modified entity is
+    /// checked using `Doc::validate_entity()`, and the `Doc::before_update()`
+    /// and `Doc::after_update()` hooks are invoked.
+    ///
+    /// If `T::VERSION_FIELD` is set, the save only succeeds if the stored
+    /// version equals the version of the entity when it was last synchronized,
+    /// and both the stored and the in-memory version are incremented.
+    /// Otherwise, an error of kind `ErrorKind::ConcurrentModification`
+    /// is returned.
+    pub fn save(&mut self) -> Result<UpdateOneResult> {
+        let pending = match self.pending_update()? {
+            Some(pending) => pending,
+            None => return Ok(UpdateOneResult::default()),
+        };
+        let result = self.coll.update_one(&pending.update)?;
+
+        if let Some(ref version) = pending.version {
+            if !result.matched {
+                return Err(version.conflict::<T>(&pending.id));
+            }
+        }
+
+        self.synchronize(pending)?;
+
+        if result.matched {
+            self.entity.after_update()?;
+        }
+
+        Ok(result)
+    }
+
+    /// Stops tracking and returns the entity.
+    pub fn into_inner(self) -> T {
+        self.entity
+    }
+
+    /// Computes the update and the new snapshot, if anything changed.
+    fn pending_update(&self) -> Result<Option<PendingUpdate>> {
+        let (mut snapshot, mut change) = match entity_diff(&self.snapshot, &self.entity)? {
+            Some(diff) => diff,
+            None => return Ok(None),
+        };
+
+        let id = self.snapshot.get("_id").cloned().ok_or_else(|| Error::new(
+            ErrorKind::MissingId,
+            format!("No `_id` in tracked entity of type {}", T::NAME)
+        ))?;
+        let mut filter = doc!{ "_id": id.clone() };
+        let version = Version::of::<T>(&self.snapshot)?;
+
+        if let Some(ref version) = version {
+            version.restrict(&mut filter);
+            version.bump_update(&mut change);
+            version.bump_document(&mut snapshot);
+        }
+
+        Ok(Some(PendingUpdate {
+            update: DiffUpdate { filter, change },
+            snapshot,
+            id,
+            version,
+        }))
+    }
+
+    /// Marks the entity as being in sync with the database after the
+    /// pending update was successfully saved. If the update incremented
+    /// the version, the in-memory entity is updated accordingly.
+    fn synchronize(&mut self, pending: PendingUpdate) -> Result<()> {
+        if pending.version.is_some() {
+            self.entity = from_bson(Bson::Document(pending.snapshot.clone()))?;
+        }
+
+        self.snapshot = pending.snapshot;
+
+        Ok(())
+    }
+}
+
+impl<'a, T: Doc> Deref for Tracked<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entity
+    }
+}
+
+impl<'a, T: Doc> DerefMut for Tracked<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entity
+    }
+}
+
+impl<'a, T: Doc + Debug> Debug for Tracked<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Tracked")
+            .field("coll", &self.coll)
+            .field("snapshot", &self.snapshot)
+            .field("entity", &self.entity)
+            .finish()
+    }
+}
+
+/// Saves a number of tracked entities together, using a single bulk write
+/// operation per collection. Entities are borrowed mutably until the unit
+/// of work is flushed, so they should be added after they were modified.
+#[derive(Default)]
+pub struct UnitOfWork<'b> {
+    /// The entities to be saved.
+    entities: Vec<&'b mut dyn PendingSave>,
+}
+
+impl<'b> UnitOfWork<'b> {
+    /// Creates an empty unit of work.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tracked entity to be saved upon flushing.
+    pub fn add<'a: 'b, T: Doc + 'b>(&mut self, tracked: &'b mut Tracked<'a, T>) {
+        self.entities.push(tracked);
+    }
+
+    /// Returns the number of entities added so far.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Returns `true` if no entities have been added.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Persists the modified fields of all added entities. Unmodified entities
+    /// are skipped. Collections are written in the lexicographic order of their
+    /// namespaces; if writing to one of them fails, the changes to collections
+    /// that were already written are not rolled back, and the snapshots of the
+    /// entities in them are updated accordingly.
+    ///
+    /// Modified entities are validated and their update hooks are invoked, just
+    /// like by `Tracked::save()`. All entities are validated and their
+    /// `before_update()` hooks are invoked before anything is written.
+    ///
+    /// Since a bulk write doesn't tell which of its updates didn't match, the
+    /// entities of a collection containing versioned entities (see
+    /// `Doc::VERSION_FIELD`) are written one by one instead. If any of them
+    /// were modified concurrently, the others are still saved, then an error
+    /// of kind `ErrorKind::ConcurrentModification` is returned. Only the
+    /// entities which are still `is_modified()` should then be reloaded.
+    pub fn flush(self) -> Result<UpdateManyResult> {
+        let mut entities = self.entities;
+        let mut groups: BTreeMap<String, Vec<(usize, PendingUpdate)>> = BTreeMap::new();
+
+        for (index, entity) in entities.iter().enumerate() {
+            if let Some(pending) = entity.pending_update()? {
+                groups
+                    .entry(entity.namespace().to_owned())
+                    .or_insert_with(Vec::new)
+                    .push((index, pending));
+            }
+        }
+
+        let mut total = UpdateManyResult::default();
+
+        for (namespace, group) in groups {
+            let first = group[0].0;
+            let num_updates = group.len();
+            let is_versioned = group.iter().any(|&(_, ref pending)| pending.version.is_some());
+
+            if is_versioned {
+                let mut num_conflicts = 0;
+
+                for (index, pending) in group {
+                    let result = entities[index].update_one(&pending.update)?;
+
+                    if result.matched {
+                        total.num_matched += 1;
+                        total.num_modified += usize::from(result.modified);
+                    } else if pending.version.is_some() {
+                        num_conflicts += 1;
+                        continue;
+                    }
+
+                    entities[index].synchronize(pending)?;
+
+                    if result.matched {
+                        entities[index].after_update()?;
+                    }
+                }
+
+                if num_conflicts > 0 {
+                    return Err(Error::new(
+                        ErrorKind::ConcurrentModification,
+                        format!("{} of {} entities in {} were modified concurrently or don't exist",
+                                num_conflicts, num_updates, namespace)
+                    ));
+                }
+
+                continue;
+            }
+
+            let updates: Vec<_> = group.iter().map(|&(_, ref pending)| &pending.update).collect();
+            let result = entities[first].bulk_update(updates)?;
+
+            for (index, pending) in group {
+                entities[index].synchronize(pending)?;
+                entities[index].after_update()?;
+            }
+
+            total.num_matched += result.num_matched;
+            total.num_modified += result.num_modified;
+        }
+
+        Ok(total)
+    }
+}
+
+impl<'b> Debug for UnitOfWork<'b> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("UnitOfWork")
+            .field("len", &self.entities.len())
+            .finish()
+    }
+}
+
+/// Type-erased interface of `Tracked` entities, used by `UnitOfWork`.
+trait PendingSave {
+    /// The full name of the collection the entity belongs to.
+    fn namespace(&self) -> &str;
+
+    /// Computes the update and the new snapshot, if anything changed.
+    fn pending_update(&self) -> Result<Option<PendingUpdate>>;
+
+    /// Writes a single update to the entity's collection.
+    fn update_one(&self, update: &DiffUpdate) -> Result<UpdateOneResult>;
+
+    /// Writes the updates to the entity's collection in one bulk operation.
+    fn bulk_update(&self, updates: Vec<&DiffUpdate>) -> Result<UpdateManyResult>;
+
+    /// Marks the entity as being in sync with the database.
+    fn synchronize(&mut self, pending: PendingUpdate) -> Result<()>;
+
+    /// Invokes the `after_update()` hook of the entity.
+    fn after_update(&self) -> Result<()>;
+}
+
+impl<'a, T: Doc> PendingSave for Tracked<'a, T> {
+    fn namespace(&self) -> &str {
+        self.coll.namespace()
+    }
+
+    fn pending_update(&self) -> Result<Option<PendingUpdate>> {
+        Tracked::pending_update(self)
+    }
+
+    fn update_one(&self, update: &DiffUpdate) -> Result<UpdateOneResult> {
+        self.coll.update_one(update)
+    }
+
+    fn bulk_update(&self, updates: Vec<&DiffUpdate>) -> Result<UpdateManyResult> {
+        self.coll.bulk_update(updates)
+    }
+
+    fn synchronize(&mut self, pending: PendingUpdate) -> Result<()> {
+        Tracked::synchronize(self, pending)
+    }
+
+    fn after_update(&self) -> Result<()> {
+        self.entity.after_update()
+    }
+}
+
+/// The changes of a tracked entity which are yet to be saved.
+#[derive(Debug, Clone)]
+struct PendingUpdate {
+    /// The update operation persisting the changes.
+    update: DiffUpdate,
+    /// The BSON representation of the entity once the update is saved.
+    snapshot: Document,
+    /// The `_id` of the entity.
+    id: Bson,
+    /// The version of the entity, if it has one.
+    version: Option<Version>,
+}
+
+/// An update by `_id`, consisting of the changes of a tracked entity.
+#[derive(Debug, Clone)]
+struct DiffUpdate {
+    /// Selects the tracked entity by its `_id`.
+    filter: Document,
+    /// The `$set` and `$unset` operators describing the changes.
+    change: Document,
+}
+
+impl<T: Doc> Update<T> for DiffUpdate {
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+
+    fn update(&self) -> Document {
+        self.change.clone()
+    }
+
+    /// The tracked entity is saved by identity, like by `update_entity_diff()`.
+    fn include_deleted(&self) -> bool {
+        true
+    }
+}
@@ -1,5 +1,7 @@
 //! Common utility functions and types.
 
+use std::fmt::Display;
+use std::convert::TryFrom;
 use crate::error::{ Error, ErrorKind, Result };
 
 /// Converts an `i8`, `i16`, `i32` or `i64` to a `usize` if the range and
@@ -31,11 +33,23 @@ pub fn int_to_usize_with_msg<T: Into<i64>>(x: T, msg: &str) -> Result<usize> {
     }
 }
 
+/// Converts a `usize` or a `u64` to an `i64` if the value permits.
+/// Constructs an error message based on `msg` otherwise.
+pub fn usize_to_i64_with_msg<T>(x: T, msg: &str) -> Result<i64>
+    where T: Copy + Display,
+          i64: TryFrom<T>,
+{
+    i64::try_from(x).map_err(|_| Error::new(
+        ErrorKind::IntConversionOverflow,
+        format!("{} ({}) overflows `i64`", msg, x)
+    ))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::i64;
-    use super::int_to_usize_with_msg;
-    use crate::error::{ Error, ErrorKind, Result };
+    use std::{ i64, u64 };
+    use super::{ int_to_usize_with_msg, usize_to_i64_with_msg };
+    use crate::error::{ Error, ErrorExt, ErrorKind, Result };
 
     #[test]
     fn int_to_usize_works() -> Result<()> {
@@ -72,4 +86,16 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn usize_to_i64_works() -> Result<()> {
+        assert_eq!(usize_to_i64_with_msg(42_usize, "example value")?, 42);
+        assert_eq!(usize_to_i64_with_msg(i64::MAX as u64, "example value")?, i64::MAX);
+
+        let error = usize_to_i64_with_msg(u64::MAX, "example value").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::IntConversionOverflow);
+        assert!(error.to_string().contains("example value (18446744073709551615) overflows `i64`"));
+
+        Ok(())
+    }
 }
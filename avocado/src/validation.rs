@@ -0,0 +1,347 @@
+//! Client-side validation of entities before they are written.
+//!
+//! Unlike the `$jsonSchema` validator of the server, which only checks the
+//! structure of documents, a `Validate` impl can check arbitrary invariants,
+//! and it reports every violation along with the name of the offending field.
+//!
+//! `Validate` can be `#[derive]`d using the following field attributes:
+//!
+//! * `#[validate(length(min = ..., max = ...))]`: the number of characters
+//!   of a string, or the number of elements of a collection, is in range.
+//!   Either bound may be omitted.
+//! * `#[validate(range(min = ..., max = ...))]`: the value is in range,
+//!   inclusive. Either bound may be omitted. Negative bounds must be
+//!   specified as strings, e.g. `min = "-1"`.
+//! * `#[validate(regex = "...")]`: the string matches the regular expression.
+//! * `#[validate(non_empty)]`: the string or collection is not empty.
+//!
+//! The rules of an `Option<_>` field apply to the wrapped value, if any;
+//! `None` is always valid. (Only a type literally spelled `Option<...>` is
+//! recognized, not an alias of it.)
+//!
+//! Adding the `#[avocado(validate)]` attribute to a type with a `#[derive]`d
+//! `Doc` impl makes `Collection` check the `Validate` impl before inserting,
+//! replacing or upserting entities.
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! # use avocado::error::ErrorExt;
+//! # use avocado::validation::{ Validate, ValidationErrors };
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc, Validate)]
+//! #[serde(rename_all = "camelCase")]
+//! #[avocado(validate)]
+//! struct User {
+//!     #[serde(rename = "_id")]
+//!     id: Uid<User>,
+//!     #[validate(length(min = 1, max = 32), regex = "^[a-z0-9_]*$")]
+//!     user_name: String,
+//!     #[validate(range(min = 13, max = 150))]
+//!     age: u32,
+//!     #[validate(non_empty)]
+//!     emails: Vec<String>,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! let user = User {
+//!     id: Uid::new_oid()?,
+//!     user_name: String::from("Chuck Norris"),
+//!     age: 79,
+//!     emails: Vec::new(),
+//! };
+//! let error = user.validate().unwrap_err();
+//! let violations = error.context::<ValidationErrors>().unwrap();
+//!
+//! assert_eq!(error.kind(), AvocadoErrorKind::Validation);
+//! assert_eq!(violations.len(), 2);
+//! assert_eq!(violations.field("userName").len(), 1);
+//! assert_eq!(violations.field("emails"), &["must not be empty"]);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::hash::{ Hash, BuildHasher };
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet, BTreeMap, BTreeSet, VecDeque };
+use regex::Regex;
+use typemap::Key;
+use crate::error::{ Error, ErrorKind, Result };
+
+/// A type whose values can be checked before they are written to the database.
+pub trait Validate {
+    /// Checks whether `self` is valid. If not, returns an error of kind
+    /// `ErrorKind::Validation`, ideally with a `ValidationErrors` context
+    /// describing all violations, as created by `ValidationErrors::into_result()`.
+    fn validate(&self) -> Result<()>;
+}
+
+/// Validation failure messages, grouped by the (serialized) name of the field
+/// they pertain to. Errors of kind `ErrorKind::Validation` created by
+/// `into_result()` carry this as their context.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationErrors {
+    /// The messages describing the violations, keyed by field name.
+    fields: BTreeMap<String, Vec<String>>,
+}
+
+impl ValidationErrors {
+    /// Creates an empty set of errors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a violation of a rule by the given field.
+    pub fn add<F, M>(&mut self, field: F, message: M)
+        where F: Into<String>,
+              M: Into<String>,
+    {
+        self.fields
+            .entry(field.into())
+            .or_insert_with(Vec::new)
+            .push(message.into());
+    }
+
+    /// Returns `true` if no violations were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the number of fields with at least one violation.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns the messages pertaining to the given field.
+    pub fn field(&self, name: &str) -> &[String] {
+        self.fields.get(name).map_or(&[][..], Vec::as_slice)
+    }
+
+    /// Returns all messages, keyed by field name.
+    pub fn fields(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.fields
+    }
+
+    /// Returns `Ok` if no violations were recorded, otherwise an error of kind
+    /// `ErrorKind::Validation` with `self` as its context.
+    pub fn into_result(self) -> Result<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::Validation, self.to_string())
+                .with_context::<ValidationErrors>(self))
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("validation failed")?;
+
+        for (i, (field, messages)) in self.fields.iter().enumerate() {
+            f.write_str(if i == 0 { ": " } else { "; " })?;
+            write!(f, "`{}` {}", field, messages.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Key for ValidationErrors {
+    type Value = Self;
+}
+
+/// Types with a length, i.e. strings and collections.
+pub trait HasLength {
+    /// The number of characters or elements.
+    fn length(&self) -> usize;
+}
+
+impl HasLength for str {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl HasLength for String {
+    fn length(&self) -> usize {
+        self.as_str().length()
+    }
+}
+
+impl<T> HasLength for [T] {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> HasLength for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> HasLength for VecDeque<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> HasLength for BTreeSet<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K, V> HasLength for BTreeMap<K, V> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Hash + Eq, S: BuildHasher> HasLength for HashSet<T, S> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HasLength for HashMap<K, V, S> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: HasLength + ?Sized> HasLength for &T {
+    fn length(&self) -> usize {
+        (**self).length()
+    }
+}
+
+impl<T: HasLength + ?Sized> HasLength for Box<T> {
+    fn length(&self) -> usize {
+        (**self).length()
+    }
+}
+
+/// Checks that the length of `value` is within the given bounds.
+/// Returns the failure message if it isn't.
+pub fn check_length<T>(value: &T, min: Option<usize>, max: Option<usize>) -> Option<String>
+    where T: HasLength + ?Sized
+{
+    let length = value.length();
+
+    match (min, max) {
+        (Some(lower), _) if length < lower => Some(format!("length must be at least {}", lower)),
+        (_, Some(upper)) if length > upper => Some(format!("length must be at most {}", upper)),
+        _ => None,
+    }
+}
+
+/// Checks that `value` is not empty. Returns the failure message if it is.
+pub fn check_non_empty<T: HasLength + ?Sized>(value: &T) -> Option<String> {
+    if value.length() == 0 {
+        Some(String::from("must not be empty"))
+    } else {
+        None
+    }
+}
+
+/// Checks that `value` is within the given, inclusive bounds.
+/// Returns the failure message if it isn't.
+pub fn check_range<T>(value: &T, min: Option<T>, max: Option<T>) -> Option<String>
+    where T: PartialOrd + fmt::Display
+{
+    match (min, max) {
+        (Some(lower), _) if *value < lower => Some(format!("must be at least {}", lower)),
+        (_, Some(upper)) if *value > upper => Some(format!("must be at most {}", upper)),
+        _ => None,
+    }
+}
+
+/// Checks that `value` matches the regular expression `pattern`. Returns the
+/// failure message if it doesn't, or an error if `pattern` is invalid.
+/// Compiled regular expressions are cached per thread.
+pub fn check_regex<T>(value: &T, pattern: &str) -> Result<Option<String>>
+    where T: AsRef<str> + ?Sized
+{
+    thread_local! {
+        /// The regular expressions compiled so far, keyed by their pattern.
+        static REGEXES: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+    }
+
+    REGEXES.with(|regexes| {
+        let mut cache = regexes.borrow_mut();
+        let regex = match cache.get(pattern) {
+            Some(cached) => cached.clone(),
+            None => {
+                let compiled = Regex::new(pattern)?;
+                cache.insert(pattern.to_owned(), compiled.clone());
+                compiled
+            }
+        };
+
+        if regex.is_match(value.as_ref()) {
+            Ok(None)
+        } else {
+            Ok(Some(format!("must match regular expression `{}`", pattern)))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorExt;
+
+    #[test]
+    fn validation_rules() -> Result<()> {
+        assert_eq!(check_length("héllo", Some(5), Some(5)), None);
+        assert_eq!(check_length("", Some(1), None).unwrap(), "length must be at least 1");
+        assert_eq!(check_length(&vec![1, 2, 3], None, Some(2)).unwrap(), "length must be at most 2");
+
+        assert_eq!(check_non_empty(&BTreeSet::<u8>::new()).unwrap(), "must not be empty");
+        assert_eq!(check_non_empty("x"), None);
+
+        assert_eq!(check_range(&-1, Some(-1), Some(1)), None);
+        assert_eq!(check_range(&2.5, None, Some(2.0)).unwrap(), "must be at most 2");
+        assert_eq!(check_range(&0_u8, Some(1), None).unwrap(), "must be at least 1");
+
+        assert_eq!(check_regex("abc", "^[a-z]+$")?, None);
+        assert!(check_regex("ABC", "^[a-z]+$")?.is_some());
+        assert_eq!(check_regex("", "(")
+                   .unwrap_err()
+                   .kind(),
+                   ErrorKind::InvalidConfiguration);
+
+        Ok(())
+    }
+
+    #[test]
+    fn validation_errors() {
+        let mut errors = ValidationErrors::new();
+        assert!(errors.clone().into_result().is_ok());
+
+        errors.add("name", "must not be empty");
+        errors.add("age", "must be at least 18");
+        errors.add("name", "length must be at least 3");
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors.field("name"), &["must not be empty", "length must be at least 3"]);
+        assert!(errors.field("email").is_empty());
+        assert_eq!(
+            errors.to_string(),
+            "validation failed: `age` must be at least 18; \
+             `name` must not be empty, length must be at least 3"
+        );
+
+        let error = errors.clone().into_result().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Validation);
+        assert_eq!(error.context::<ValidationErrors>(), Some(&errors));
+    }
+}
@@ -0,0 +1,116 @@
+//! Helpers for optimistic concurrency control based on a version field.
+
+use bson::{ Bson, Document };
+use crate::{
+    doc::Doc,
+    bsn::{ remove_from_operators, insert_into_operator },
+    error::{ Error, ErrorKind, Result },
+};
+
+/// The current and the next version of an entity, as found in its BSON
+/// representation, along with the name of the version field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    /// The name of the version field, as specified by `Doc::VERSION_FIELD`.
+    field: &'static str,
+    /// The version of the entity as it was read from the database.
+    current: Bson,
+    /// The version to be written along with the modified entity.
+    next: Bson,
+}
+
+impl Version {
+    /// Extracts the version of a serialized entity of type `T`. Returns `None`
+    /// if `T` doesn't have a version field. A missing or `null` version is
+    /// treated as an initial version, which is followed by version 1.
+    pub fn of<T: Doc>(document: &Document) -> Result<Option<Self>> {
+        let field = match T::VERSION_FIELD {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+        let (current, next) = match document.get(field) {
+            None | Some(&Bson::Null) => (Bson::Null, Bson::I64(1)),
+            Some(&Bson::I32(n)) => (Bson::I32(n), n.checked_add(1).map(Bson::I32).ok_or_else(
+                || overflow_error::<T>(field)
+            )?),
+            Some(&Bson::I64(n)) => (Bson::I64(n), n.checked_add(1).map(Bson::I64).ok_or_else(
+                || overflow_error::<T>(field)
+            )?),
+            Some(other) => return Err(Error::new(
+                ErrorKind::IllTypedDocumentField,
+                format!("version field `{}.{}` must be an integer, found {}", T::NAME, field, other)
+            )),
+        };
+
+        Ok(Some(Version { field, current, next }))
+    }
+
+    /// Restricts `filter` to documents with the current version.
+    pub fn restrict(&self, filter: &mut Document) {
+        filter.insert(self.field, self.current.clone());
+    }
+
+    /// Sets the next version in a full replacement document.
+    pub fn bump_document(&self, document: &mut Document) {
+        document.insert(self.field, self.next.clone());
+    }
+
+    /// Sets the next version in an update document consisting of
+    /// update operators, overriding any other change to the version field.
+    pub fn bump_update(&self, update: &mut Document) {
+        remove_from_operators(update, &["$set", "$unset"], self.field);
+        insert_into_operator(update, "$set", self.field, self.next.clone());
+    }
+
+    /// Creates the error reported when a write didn't find the entity
+    /// with the expected version.
+    pub fn conflict<T: Doc>(&self, id: &Bson) -> Error {
+        Error::new(
+            ErrorKind::ConcurrentModification,
+            format!("{} with _id {} was modified concurrently or doesn't exist (expected {} = {})",
+                    T::NAME, id, self.field, self.current)
+        )
+    }
+}
+
+/// Creates the error reported when the version would overflow.
+fn overflow_error<T: Doc>(field: &str) -> Error {
+    Error::new(
+        ErrorKind::IntConversionOverflow,
+        format!("version field `{}.{}` overflowed", T::NAME, field)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Entity;
+
+    #[test]
+    fn version_is_bumped() -> Result<()> {
+        let version = Version::of::<Entity>(&doc!{ "_id": 1, "rev": 41_i64 })?.unwrap();
+        let mut filter = doc!{ "_id": 1 };
+        let mut replacement = doc!{ "_id": 1, "rev": 41_i64, "x": 0 };
+        let mut update = doc!{
+            "$set": { "rev": 99, "x": 1 },
+            "$unset": { "rev": "" },
+        };
+
+        version.restrict(&mut filter);
+        version.bump_document(&mut replacement);
+        version.bump_update(&mut update);
+
+        assert_eq!(filter, doc!{ "_id": 1, "rev": 41_i64 });
+        assert_eq!(replacement.get("rev"), Some(&Bson::I64(42)));
+        assert_eq!(update, doc!{ "$set": { "x": 1, "rev": 42_i64 } });
+
+        let initial = Version::of::<Entity>(&doc!{ "_id": 1 })?.unwrap();
+        let mut fresh = doc!{ "_id": 1 };
+        initial.bump_document(&mut fresh);
+        assert_eq!(fresh, doc!{ "_id": 1, "rev": 1_i64 });
+
+        assert!(Version::of::<Entity>(&doc!{ "rev": "foo" }).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[avocado(version_field = "revision")] //~ ERROR version field `revision` doesn't exist
+struct Versioned {
+    _id: Uid<Versioned>,
+    #[serde(rename = "rev")]
+    revision: u32,
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[avocado(collection = "users", pluralize)] //~ ERROR `pluralize` can't be combined with `collection`
+struct User {
+    _id: Uid<User>,
+}
+
+fn main() {}
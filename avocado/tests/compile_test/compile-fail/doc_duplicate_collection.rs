@@ -0,0 +1,22 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[avocado(collection = "users")]
+struct User {
+    _id: Uid<User>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR the name `__AVOCADO_COLLECTION_NAME_users` is defined multiple times
+#[serde(rename = "users")]
+struct Admin {
+    _id: Uid<Admin>,
+}
+
+fn main() {}
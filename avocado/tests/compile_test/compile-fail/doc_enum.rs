@@ -5,8 +5,8 @@ extern crate avocado;
 extern crate serde_derive;
 extern crate serde;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR proc-macro derive panicked
-enum Stuff { //~| only a `struct` can be a top-level `Doc`; consider wrapping this type in a struct
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+enum Stuff { //~ ERROR an `enum` must be internally tagged, e.g. `#[serde(tag = "type")]`, in order to be a `Doc`
     Foo {
         _id: Uid<Stuff>
     },
@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[serde(tag = "type")]
+enum Stuff {
+    Foo {
+        _id: Uid<Stuff>
+    },
+    Bar, //~ ERROR variant `Bar` must be a struct or a newtype variant carrying the `_id`
+}
+
+fn main() {}
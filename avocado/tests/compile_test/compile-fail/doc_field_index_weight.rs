@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Weighted {
+    _id: Uid<Weighted>,
+    #[index(descending, weight = 3)] //~ ERROR `weight` is only allowed for `text` indexes
+    score: u32,
+}
+
+fn main() {}
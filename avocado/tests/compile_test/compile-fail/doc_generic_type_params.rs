@@ -5,8 +5,9 @@ extern crate avocado;
 extern crate serde_derive;
 extern crate serde;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR proc-macro derive panicked
-struct GenericType<T> { //~| `Doc` can't be derived for a type that is generic over type parameters
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[avocado(name_template = "generic")] //~ ERROR name template `generic` doesn't mention type parameter `T`, so all instantiations would share the same collection
+struct GenericType<T> {
     _id: Uid<GenericType<T>>,
     dummy: PhantomData<T>,
 }
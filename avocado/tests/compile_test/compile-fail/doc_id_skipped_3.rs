@@ -5,9 +5,9 @@ extern crate avocado;
 extern crate serde_derive;
 extern crate serde;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR proc-macro derive panicked
-#[id_type = "u64"] //~| a `Doc` must contain a field serialized as `_id`
-struct SkippyThree {
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[id_type = "u64"]
+struct SkippyThree { //~ ERROR a `Doc` must contain a field serialized as `_id`
     #[serde(skip)]
     _id: Uid<SkippyThree>,
     #[serde(rename = "_id")]
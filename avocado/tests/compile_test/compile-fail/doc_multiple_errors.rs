@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[avocado(version_field = "revision")] //~ ERROR version field `revision` doesn't exist
+#[index(keys(name = "ascending"), bogus)] //~ ERROR bad path attribute: bogus
+struct Multi {
+    _id: Uid<Multi>,
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Article {
+    _id: Uid<Article>,
+    #[index(text, collation(locale = "en"))]
+    title: String,
+    #[index(text, collation(locale = "fr"))] //~ ERROR conflicting values for `collation` of the merged text index
+    body: String,
+}
+
+fn main() {}
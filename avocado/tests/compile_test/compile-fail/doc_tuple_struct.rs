@@ -5,7 +5,7 @@ extern crate avocado;
 extern crate serde_derive;
 extern crate serde;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR: proc-macro derive panicked
-struct Tuple(String, Vec<u8>); //~| a `Doc` must be a struct with named fields
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Tuple(String, Vec<u8>); //~ ERROR a `Doc` must be a struct with named fields
 
 fn main() {}
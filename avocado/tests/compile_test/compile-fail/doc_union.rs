@@ -5,8 +5,8 @@ extern crate avocado;
 extern crate serde_derive;
 extern crate serde;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR proc-macro derive panicked
-union Foo { //~| only a `struct` can be a top-level `Doc`; consider wrapping this type in a struct
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+union Foo { //~ ERROR only a `struct` or an `enum` can be a top-level `Doc`; consider wrapping this type in a struct
     signed: i32,
     unsigned: u32,
 }
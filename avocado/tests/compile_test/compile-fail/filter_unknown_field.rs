@@ -0,0 +1,22 @@
+#[macro_use]
+extern crate avocado_derive;
+#[macro_use]
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Customer {
+    _id: Uid<Customer>,
+    legal_name: String,
+}
+
+fn main() {
+    let _ = filter!(Customer {
+        legal_nam =~ "^A" //~ ERROR no field `legal_nam` on type `&Customer`
+        //~^ ERROR no associated item named `legal_nam` found
+    });
+}
@@ -0,0 +1,22 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Customer {
+    _id: Uid<Customer>,
+    legal_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Projection)]
+#[projection(of = "Customer")]
+struct CustomerName {
+    legal_nam: String, //~ ERROR no associated item named `__key_legal__nam` found
+}
+
+fn main() {}
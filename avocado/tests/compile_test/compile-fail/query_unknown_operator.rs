@@ -0,0 +1,23 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Member {
+    _id: Uid<Member>,
+    age: u32,
+}
+
+#[derive(Debug, Clone, Query)]
+#[query(doc = "Member")]
+struct MembersByAge {
+    #[filter(between = "age")] //~ ERROR unknown `#[filter]` operator `between`
+    age: u32,
+}
+
+fn main() {}
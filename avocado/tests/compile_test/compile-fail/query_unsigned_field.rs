@@ -0,0 +1,23 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Post {
+    _id: Uid<Post>,
+    views: i64,
+}
+
+#[derive(Debug, Clone, Query)]
+#[query(doc = "Post")]
+struct PopularPosts {
+    #[filter(gte = "views")]
+    min_views: Option<u64>, //~ ERROR `u64` can't always be represented in BSON
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+
+#[derive(Debug, Validate)]
+struct Person {
+    #[validate(size(max = 100))] //~ ERROR unknown validation rule `size`
+    name: String,
+}
+
+fn main() {}
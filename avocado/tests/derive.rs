@@ -2,11 +2,15 @@
 extern crate serde_derive;
 #[macro_use]
 extern crate avocado_derive;
+#[macro_use]
 extern crate avocado;
 
 use std::marker::PhantomData;
 use std::any::TypeId;
 use avocado::prelude::*;
+use avocado::literal::DateTimeType;
+use avocado::error::ErrorExt;
+use avocado::validation::{ Validate, ValidationErrors };
 
 /// This could have been a function, but making it a macro results in the
 /// error messages pointing to the actual line number of the invocation,
@@ -279,6 +283,80 @@ fn doc_index() {
     );
 }
 
+#[test]
+fn doc_field_index() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename_all = "camelCase")]
+    #[index(keys(owner_id = "ascending", tags = "ascending"))]
+    struct FieldIndexed {
+        #[serde(rename = "_id")]
+        guid: Uid<FieldIndexed>,
+        #[index]
+        owner_id: u32,
+        #[index(unique = true, name = "by_email")]
+        email_address: String,
+        #[index(descending, sparse)]
+        #[serde(rename = "ts")]
+        timestamp: Option<u64>,
+        #[index(hashed)]
+        shard_key: String,
+        #[index(text, weight = 10)]
+        title: String,
+        #[index(text, default_language = "french")]
+        summary: String,
+        tags: Vec<String>,
+    }
+
+    assert_doc_impl!(
+        Doc: FieldIndexed,
+        Id: ObjectId,
+        name: FieldIndexed,
+        index: &[
+            IndexModel {
+                keys: doc!{
+                    "owner_id": IndexType::Ordered(Order::Ascending),
+                    "tags": IndexType::Ordered(Order::Ascending),
+                },
+                options: Default::default(),
+            },
+            IndexModel {
+                keys: doc!{ "ownerId": IndexType::Ordered(Order::Ascending) },
+                options: Default::default(),
+            },
+            IndexModel {
+                keys: doc!{ "emailAddress": IndexType::Ordered(Order::Ascending) },
+                options: IndexOptions {
+                    name: Some(String::from("by_email")),
+                    unique: Some(true),
+                    ..Default::default()
+                },
+            },
+            IndexModel {
+                keys: doc!{ "ts": IndexType::Ordered(Order::Descending) },
+                options: IndexOptions {
+                    sparse: Some(true),
+                    ..Default::default()
+                },
+            },
+            IndexModel {
+                keys: doc!{ "shardKey": IndexType::Hashed },
+                options: Default::default(),
+            },
+            IndexModel {
+                keys: doc!{
+                    "title": IndexType::Text,
+                    "summary": IndexType::Text,
+                },
+                options: IndexOptions {
+                    default_language: Some(String::from("french")),
+                    weights: Some(doc!{ "title": 10 }),
+                    ..Default::default()
+                },
+            },
+        ]
+    );
+}
+
 #[test]
 fn doc_index_options() {
     #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
@@ -314,6 +392,90 @@ fn doc_index_options() {
     ]);
 }
 
+#[test]
+fn doc_index_extended_options() {
+    fn active_only() -> Document {
+        doc!{ "active": true }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[index(keys(login = "ascending"), expire_after_seconds = 3600)]
+    #[index(keys(name = "ascending"), collation(locale = "fr", strength = 2), hidden)]
+    #[index(keys(email = "ascending"), unique, partial_filter_expression = "active_only()")]
+    #[index(keys(bio = "text", name = "text"), weights(bio = 2, name = 10))]
+    #[index(keys(settings = "wildcard"))]
+    #[index(wildcard)]
+    struct Subscriber {
+        _id: Uid<Subscriber>,
+        login: u64,
+        name: String,
+        email: String,
+        bio: String,
+        active: bool,
+        #[index(wildcard)]
+        settings: Document,
+    }
+
+    assert_doc_impl!(
+        Doc: Subscriber,
+        Id: ObjectId,
+        name: Subscriber,
+        index: &[
+            IndexModel {
+                keys: doc!{ "login": IndexType::Ordered(Order::Ascending) },
+                options: IndexOptions {
+                    expire_after_seconds: Some(3600),
+                    ..Default::default()
+                },
+            },
+            IndexModel {
+                keys: doc!{ "name": IndexType::Ordered(Order::Ascending) },
+                options: Default::default(),
+            },
+            IndexModel {
+                keys: doc!{ "email": IndexType::Ordered(Order::Ascending) },
+                options: IndexOptions {
+                    unique: Some(true),
+                    ..Default::default()
+                },
+            },
+            IndexModel {
+                keys: doc!{ "bio": IndexType::Text, "name": IndexType::Text },
+                options: IndexOptions {
+                    weights: Some(doc!{ "bio": 2, "name": 10 }),
+                    ..Default::default()
+                },
+            },
+            IndexModel {
+                keys: doc!{ "settings.$**": IndexType::Wildcard },
+                options: Default::default(),
+            },
+            IndexModel {
+                keys: doc!{ "$**": IndexType::Wildcard },
+                options: Default::default(),
+            },
+            IndexModel {
+                keys: doc!{ "settings.$**": IndexType::Wildcard },
+                options: Default::default(),
+            },
+        ]
+    );
+    assert_eq!(Subscriber::extended_index_options(), vec![
+        Document::new(),
+        doc!{
+            "collation": { "locale": "fr", "strength": 2 },
+            "hidden": true,
+        },
+        doc!{
+            "partialFilterExpression": { "active": true },
+        },
+        Document::new(),
+        Document::new(),
+        Document::new(),
+        Document::new(),
+    ]);
+}
+
 #[test]
 fn doc_index_embedded_paths() {
     #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
@@ -379,3 +541,584 @@ fn doc_index_last_field_overwrites_previous() {
         ]
     );
 }
+
+#[test]
+fn doc_capped() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(capped(size = 4096, max = 100))]
+    struct Capped {
+        _id: Uid<Capped>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(capped(size = "65536"))]
+    struct CappedBySize {
+        _id: Uid<CappedBySize>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct Uncapped {
+        _id: Uid<Uncapped>,
+    }
+
+    assert_eq!(Capped::capped_options(), Some(CappedOptions { size: 4096, max: Some(100) }));
+    assert_eq!(CappedBySize::capped_options(), Some(CappedOptions { size: 65536, max: None }));
+    assert_eq!(Uncapped::capped_options(), None);
+}
+
+#[test]
+fn doc_version_field() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(version_field = "rev")]
+    struct Versioned {
+        _id: Uid<Versioned>,
+        #[serde(rename = "rev")]
+        revision: u32,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename_all = "camelCase")]
+    #[avocado(version_field = "entityVersion")]
+    struct CamelCase {
+        _id: Uid<CamelCase>,
+        entity_version: i64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct Unversioned {
+        _id: Uid<Unversioned>,
+    }
+
+    assert_eq!(Versioned::VERSION_FIELD, Some("rev"));
+    assert_eq!(CamelCase::VERSION_FIELD, Some("entityVersion"));
+    assert_eq!(Unversioned::VERSION_FIELD, None);
+}
+
+#[test]
+fn doc_soft_delete_field() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(soft_delete = "deleted")]
+    struct SoftDeletable {
+        _id: Uid<SoftDeletable>,
+        #[serde(rename = "deleted")]
+        deleted_at: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct HardDeletable {
+        _id: Uid<HardDeletable>,
+    }
+
+    assert_eq!(SoftDeletable::SOFT_DELETE_FIELD, Some("deleted"));
+    assert_eq!(HardDeletable::SOFT_DELETE_FIELD, None);
+}
+
+#[test]
+fn doc_hooks() -> AvocadoResult<()> {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(hooks)]
+    struct Hooked {
+        _id: Uid<Hooked>,
+        name: String,
+    }
+
+    impl DocHooks for Hooked {
+        fn on_insert(&self, document: &mut Document) -> AvocadoResult<()> {
+            document.insert("name", self.name.to_lowercase());
+            Ok(())
+        }
+
+        fn on_load(&mut self) -> AvocadoResult<()> {
+            self.name = self.name.to_uppercase();
+            Ok(())
+        }
+
+        fn on_delete(&self) -> AvocadoResult<()> {
+            Err(AvocadoError::new(AvocadoErrorKind::InvalidConfiguration, "deletion is disabled"))
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct Unhooked {
+        _id: Uid<Unhooked>,
+        name: String,
+    }
+
+    let mut hooked = Hooked { _id: Uid::new_oid()?, name: "Foo".into() };
+    let mut unhooked = Unhooked { _id: Uid::new_oid()?, name: "Foo".into() };
+    let mut document = doc!{ "name": "Foo" };
+
+    Doc::before_insert(&hooked, &mut document)?;
+    assert_eq!(document, doc!{ "name": "foo" });
+
+    // Method syntax is unambiguous even though `DocHooks` is in scope too
+    hooked.after_load()?;
+    assert_eq!(hooked.name, "FOO");
+
+    assert!(hooked.before_delete().is_err());
+    assert!(Doc::after_delete(&hooked).is_ok());
+
+    Doc::before_insert(&unhooked, &mut document)?;
+    Doc::after_load(&mut unhooked)?;
+    assert_eq!(document, doc!{ "name": "foo" });
+    assert_eq!(unhooked.name, "Foo");
+    assert!(Doc::before_delete(&unhooked).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn doc_timestamp_fields() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename_all = "camelCase")]
+    #[avocado(created_at = "createdAt", updated_at = "updatedAt")]
+    struct Dated {
+        _id: Uid<Dated>,
+        created_at: Option<String>,
+        updated_at: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(updated_at = "modified", timestamp_type = "timestamp")]
+    struct Timestamped {
+        _id: Uid<Timestamped>,
+        modified: Option<i64>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct Undated {
+        _id: Uid<Undated>,
+    }
+
+    assert_eq!(Dated::CREATED_AT_FIELD, Some("createdAt"));
+    assert_eq!(Dated::UPDATED_AT_FIELD, Some("updatedAt"));
+    assert_eq!(Dated::TIMESTAMP_TYPE, DateTimeType::Date);
+
+    assert_eq!(Timestamped::CREATED_AT_FIELD, None);
+    assert_eq!(Timestamped::UPDATED_AT_FIELD, Some("modified"));
+    assert_eq!(Timestamped::TIMESTAMP_TYPE, DateTimeType::Timestamp);
+
+    assert_eq!(Undated::CREATED_AT_FIELD, None);
+    assert_eq!(Undated::UPDATED_AT_FIELD, None);
+    assert_eq!(Undated::TIMESTAMP_TYPE, DateTimeType::Date);
+}
+
+#[test]
+fn doc_validate() -> AvocadoResult<()> {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc, Validate)]
+    #[serde(rename_all = "camelCase")]
+    #[avocado(validate)]
+    struct Account {
+        _id: Uid<Account>,
+        #[validate(length(min = 3, max = 8), regex = "^[a-z]+$")]
+        login_name: String,
+        #[validate(range(min = "-10", max = 10))]
+        balance: i64,
+        #[validate(range(max = 1.0))]
+        ratio: f64,
+        #[validate(non_empty)]
+        #[serde(rename = "roleList")]
+        roles: Vec<String>,
+        #[validate(length(max = 2))]
+        tags: Vec<String>,
+        #[validate(length(min = 2), regex = "^[a-z]+$")]
+        nickname: Option<String>,
+        #[validate(range(min = 18))]
+        age: Option<u32>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc, Validate)]
+    struct Unchecked {
+        _id: Uid<Unchecked>,
+        #[validate(non_empty)]
+        name: String,
+    }
+
+    let valid = Account {
+        _id: Uid::new_oid()?,
+        login_name: "admin".into(),
+        balance: -10,
+        ratio: 0.5,
+        roles: vec!["root".into()],
+        tags: Vec::new(),
+        nickname: None,
+        age: None,
+    };
+    assert!(Doc::validate_entity(&valid).is_ok());
+    assert!(Doc::validate_entity(&Account {
+        nickname: Some("root".into()),
+        age: Some(18),
+        ..valid.clone()
+    }).is_ok());
+
+    let invalid = Account {
+        login_name: "Administrator".into(),
+        balance: 11,
+        ratio: 1.5,
+        roles: Vec::new(),
+        nickname: Some("X".into()),
+        age: Some(17),
+        ..valid
+    };
+    let error = Doc::validate_entity(&invalid).unwrap_err();
+    let violations = error.context::<ValidationErrors>().unwrap();
+
+    assert_eq!(error.kind(), AvocadoErrorKind::Validation);
+    assert_eq!(violations.len(), 6);
+    assert_eq!(violations.field("loginName").len(), 2);
+    assert_eq!(violations.field("balance"), &["must be at most 10"]);
+    assert_eq!(violations.field("ratio"), &["must be at most 1"]);
+    assert_eq!(violations.field("roleList"), &["must not be empty"]);
+    assert_eq!(violations.field("nickname").len(), 2);
+    assert_eq!(violations.field("age"), &["must be at least 18"]);
+
+    // Without `#[avocado(validate)]`, `Doc::validate_entity()` checks nothing.
+    let unchecked = Unchecked { _id: Uid::new_oid()?, name: String::new() };
+    assert!(Doc::validate_entity(&unchecked).is_ok());
+    assert!(Validate::validate(&unchecked).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn doc_enum() -> AvocadoResult<()> {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    enum Event {
+        OrderCreated(OrderCreated),
+        #[avocado(id_field = "number")]
+        OrderShipped(OrderShipped),
+        #[serde(rename = "cancel", rename_all = "camelCase")]
+        Cancelled {
+            #[serde(rename = "_id")]
+            id: Option<Uid<Event>>,
+            cancel_reason: String,
+        },
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct OrderCreated {
+        _id: Uid<Event>,
+        total: u32,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct OrderShipped {
+        #[serde(rename = "_id")]
+        number: Uid<Event>,
+    }
+
+    assert_doc_impl!(Doc: Event, Id: ObjectId, name: Event, index: &[]);
+    assert_eq!(<OrderCreated as DocVariant<Event>>::TAG_FIELD, "kind");
+    assert_eq!(<OrderCreated as DocVariant<Event>>::TAG, "order_created");
+    assert_eq!(<OrderShipped as DocVariant<Event>>::TAG, "order_shipped");
+
+    let id = Uid::new_oid()?;
+    let mut created = Event::OrderCreated(OrderCreated { _id: Uid::new_oid()?, total: 42 });
+    let shipped = Event::OrderShipped(OrderShipped { number: id.clone() });
+    let mut cancelled = Event::Cancelled { id: None, cancel_reason: "changed mind".into() };
+
+    assert_eq!(shipped.id(), Some(&id));
+    assert_eq!(cancelled.id(), None);
+
+    created.set_id(id.clone());
+    cancelled.set_id(id.clone());
+
+    assert_eq!(created.id(), Some(&id));
+    assert_eq!(cancelled.id(), Some(&id));
+
+    Ok(())
+}
+
+#[test]
+fn doc_generic_type_params() {
+    use std::borrow::Cow;
+    use serde::{ Serialize, de::DeserializeOwned };
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(bound = "")]
+    struct Envelope<P: DocName + Serialize + DeserializeOwned> {
+        _id: Uid<Envelope<P>>,
+        payload: P,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(bound = "")]
+    #[avocado(name_template = "{F}_to_{T}")]
+    struct Link<F, T>
+        where F: DocName + Serialize + DeserializeOwned,
+              T: DocName + Serialize + DeserializeOwned
+    {
+        _id: Uid<Link<F, T>>,
+        #[serde(skip)]
+        marker: PhantomData<(F, T)>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct Order {
+        _id: Uid<Order>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Invoice {
+        number: u32,
+    }
+
+    impl DocName for Invoice {
+        fn doc_name() -> Cow<'static, str> {
+            "invoice".into()
+        }
+    }
+
+    assert_eq!(<Envelope<Order> as Doc>::NAME, "Envelope_{P}");
+    assert_eq!(Envelope::<Order>::collection_name(), "Envelope_Order");
+    assert_eq!(Envelope::<Invoice>::collection_name(), "Envelope_invoice");
+    assert_eq!(Envelope::<Envelope<Invoice>>::collection_name(), "Envelope_Envelope_invoice");
+    assert_eq!(Link::<Order, Invoice>::collection_name(), "Order_to_invoice");
+    assert_eq!(Order::collection_name(), Order::NAME);
+}
+
+#[test]
+fn doc_collection_name_options() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(collection = "people")]
+    struct Person {
+        _id: Uid<Person>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(collection = "people", shared_collection)]
+    struct PersonSummary {
+        _id: Uid<PersonSummary>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename = "profile")]
+    #[avocado(collection_case = "snake_case", pluralize)]
+    struct UserProfile {
+        _id: Uid<UserProfile>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(pluralize)]
+    struct Category {
+        _id: Uid<Category>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(collection_case = "SCREAMING-KEBAB-CASE", pluralize)]
+    struct PostBox {
+        _id: Uid<PostBox>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[avocado(collection_case = "camelCase")]
+    struct DailyReport {
+        _id: Uid<DailyReport>,
+    }
+
+    assert_doc_impl!(Doc: Person, Id: ObjectId, name: people, index: &[]);
+    assert_doc_impl!(Doc: PersonSummary, Id: ObjectId, name: people, index: &[]);
+    assert_doc_impl!(Doc: UserProfile, Id: ObjectId, name: user_profiles, index: &[]);
+    assert_doc_impl!(Doc: Category, Id: ObjectId, name: Categories, index: &[]);
+    assert_eq!(PostBox::NAME, "POST-BOXES");
+    assert_doc_impl!(Doc: DailyReport, Id: ObjectId, name: dailyReport, index: &[]);
+}
+
+#[test]
+fn derive_query_update_delete() -> AvocadoResult<()> {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct Member {
+        _id: Uid<Member>,
+        legal_name: String,
+        age: u32,
+        roles: Vec<String>,
+    }
+
+    fn limited(query: &MembersByName) -> FindOptions {
+        FindOptions {
+            limit: Some(query.limit as i64),
+            ..Default::default()
+        }
+    }
+
+    #[derive(Debug, Clone, Query, Delete)]
+    #[query(doc = "Member", options = "limited")]
+    #[delete(doc = "Member")]
+    struct MembersByName {
+        #[filter(eq = "legal_name")]
+        name: String,
+        #[filter(gte = "age")]
+        min_age: Option<u32>,
+        #[filter(lt = "age")]
+        max_age: Option<u32>,
+        #[filter(in = "roles")]
+        any_role: Option<Vec<String>>,
+        #[filter(skip)]
+        limit: usize,
+    }
+
+    fn name_only(mut raw: Document) -> AvocadoResult<Bson> {
+        raw.remove_str("legal_name")
+    }
+
+    #[derive(Debug, Clone, Copy, Query)]
+    #[query(doc = "Member", output = "String", transform = "name_only", include_deleted)]
+    struct MemberNames {}
+
+    fn journaled(_: &Promote) -> WriteConcern {
+        WriteConcern {
+            j: true,
+            ..WriteConcern::new()
+        }
+    }
+
+    #[derive(Debug, Clone, Update)]
+    #[update(doc = "Member", options = "journaled")]
+    struct Promote {
+        #[filter(eq = "_id")]
+        id: Uid<Member>,
+        #[update(push = "roles")]
+        role: String,
+        #[update(set = "legal_name")]
+        new_name: Option<String>,
+        #[update(inc = "age")]
+        years: Option<i32>,
+    }
+
+    let mut query = MembersByName {
+        name: String::from("Alice"),
+        min_age: Some(18),
+        max_age: Some(65),
+        any_role: None,
+        limit: 10,
+    };
+    let expected = doc!{
+        "legal_name": "Alice",
+        "age": { "$gte": 18_i64, "$lt": 65_i64 },
+    };
+    assert_eq!(Query::<Member>::filter(&query), expected);
+    assert_eq!(Delete::<Member>::filter(&query), expected);
+    assert!(!Query::<Member>::include_deleted(&query));
+    assert_eq!(Query::<Member>::options(&query).limit, Some(10));
+
+    query.min_age = None;
+    query.max_age = None;
+    query.any_role = Some(vec![String::from("admin")]);
+    assert_eq!(Query::<Member>::filter(&query), doc!{
+        "legal_name": "Alice",
+        "roles": { "$in": ["admin"] },
+    });
+
+    assert_eq!(Query::<Member>::filter(&MemberNames {}), Document::new());
+    assert!(Query::<Member>::include_deleted(&MemberNames {}));
+    assert_eq!(
+        <MemberNames as Query<Member>>::transform(doc!{ "legal_name": "Bob", "age": 42 })?,
+        Bson::from("Bob")
+    );
+
+    let promote = Promote {
+        id: Uid::new_oid()?,
+        role: String::from("admin"),
+        new_name: None,
+        years: Some(1),
+    };
+    assert_eq!(Update::<Member>::filter(&promote), doc!{ "_id": promote.id.clone() });
+    assert_eq!(Update::<Member>::update(&promote), doc!{
+        "$push": { "roles": "admin" },
+        "$inc": { "age": 1_i64 },
+    });
+    assert!(Update::<Member>::options(&promote).j);
+    assert!(!Delete::<Member>::options(&query).j);
+
+    Ok(())
+}
+
+#[test]
+fn derive_projection() {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename_all = "camelCase")]
+    struct Customer {
+        #[serde(rename = "_id")]
+        id: Uid<Customer>,
+        legal_name: String,
+        #[serde(rename = "e-mail")]
+        email: String,
+        password_hash: String,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Projection)]
+    #[serde(rename_all = "camelCase")]
+    #[projection(of = "Customer")]
+    struct CustomerContact {
+        legal_name: String,
+        #[serde(rename = "e-mail")]
+        email: String,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Projection)]
+    #[projection(of = "Customer")]
+    struct CustomerId {
+        #[serde(rename = "_id")]
+        id: Uid<Customer>,
+    }
+
+    assert_eq!(CustomerContact::projection(), doc!{
+        "legalName": true,
+        "e-mail": true,
+        "_id": false,
+    });
+    assert_eq!(CustomerId::projection(), doc!{ "_id": true });
+
+    let query = Project::<CustomerContact>::new(doc!{ "legalName": "Alice" });
+
+    assert_eq!(Query::<Customer>::filter(&query), doc!{ "legalName": "Alice" });
+    assert_eq!(
+        Query::<Customer>::options(&query).projection,
+        Some(CustomerContact::projection())
+    );
+}
+
+#[test]
+fn filter_macro() -> AvocadoResult<()> {
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename_all = "camelCase")]
+    struct Patient {
+        #[serde(rename = "_id")]
+        id: Uid<Patient>,
+        legal_name: String,
+        #[serde(rename = "yearsOld")]
+        age: u32,
+        ward: Option<String>,
+        visits: u64,
+    }
+
+    let min_age = 18;
+    let pattern = String::from("^A");
+
+    assert_eq!(filter!(Patient { age >= min_age })?, doc!{
+        "yearsOld": { "$gte": 18_i64 },
+    });
+    assert_eq!(
+        filter!(Patient {
+            age > min_age && legal_name =~ pattern && age <= min_age * 4
+            && ward != None && legal_name != "Alice Wonderland"
+        })?,
+        doc!{
+            "yearsOld": { "$gt": 18_i64, "$lte": 72_i64 },
+            "legalName": { "$regex": "^A", "$ne": "Alice Wonderland" },
+            "ward": { "$ne": null },
+        }
+    );
+    assert_eq!(filter!(Patient { age < 65 && age < 40 && age == 30 })?, doc!{
+        "yearsOld": { "$lt": 65_i64, "$eq": 30_i64 },
+        "$and": [
+            { "yearsOld": { "$lt": 40_i64 } },
+        ],
+    });
+
+    assert_eq!(filter!(Patient { visits > 3 })?, doc!{ "visits": { "$gt": 3_i64 } });
+    assert!(filter!(Patient { age > 18 && visits > u64::max_value() }).is_err());
+
+    Ok(())
+}
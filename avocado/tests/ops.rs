@@ -189,6 +189,134 @@ struct PullRequest {
     lines_changed: usize,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc)]
+#[id_type = "i32"]
+struct Measurement {
+    _id: Uid<Measurement>,
+    value: i32,
+    processed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc)]
+#[avocado(capped(size = 65536, max = 5))]
+struct LogLine {
+    _id: Uid<LogLine>,
+    level: String,
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc)]
+#[avocado(version_field = "rev")]
+struct Article {
+    _id: Uid<Article>,
+    title: String,
+    rev: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BsonSchema, Doc)]
+#[serde(rename_all = "camelCase")]
+#[avocado(soft_delete = "deletedAt")]
+struct Comment {
+    _id: Uid<Comment>,
+    text: String,
+    deleted_at: Option<bson::UtcDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BsonSchema, Doc)]
+#[avocado(created_at = "created", updated_at = "updated")]
+struct Note {
+    _id: Uid<Note>,
+    title: String,
+    created: Option<bson::UtcDateTime>,
+    updated: Option<bson::UtcDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc)]
+#[avocado(hooks)]
+struct Tag {
+    _id: Uid<Tag>,
+    name: String,
+    pinned: bool,
+    loaded: bool,
+}
+
+impl Tag {
+    fn normalize(&self, document: &mut Document) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(AvocadoError::new(AvocadoErrorKind::MissingDocumentField, "empty tag name"));
+        }
+
+        document.insert("name", self.name.to_lowercase());
+        Ok(())
+    }
+}
+
+impl DocHooks for Tag {
+    fn on_insert(&self, document: &mut Document) -> Result<()> {
+        self.normalize(document)
+    }
+
+    fn on_update(&self, document: &mut Document) -> Result<()> {
+        self.normalize(document)
+    }
+
+    fn on_delete(&self) -> Result<()> {
+        if self.pinned {
+            Err(AvocadoError::new(AvocadoErrorKind::InvalidConfiguration, "pinned tag"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn on_load(&mut self) -> Result<()> {
+        self.loaded = true;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc, Validate)]
+#[serde(rename_all = "camelCase")]
+#[avocado(validate)]
+struct Member {
+    _id: Uid<Member>,
+    #[validate(length(min = 2, max = 16))]
+    nick_name: String,
+    #[validate(range(min = 0, max = 120))]
+    age: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Doc)]
+#[serde(tag = "type")]
+enum Payment {
+    Card(CardPayment),
+    Transfer(TransferPayment),
+    Refund {
+        _id: Uid<Payment>,
+        amount: i64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CardPayment {
+    _id: Uid<Payment>,
+    amount: i64,
+    last_digits: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TransferPayment {
+    _id: Uid<Payment>,
+    amount: i64,
+    iban: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Doc)]
+#[serde(bound = "")]
+struct Envelope<P: DocName + serde::Serialize + serde::de::DeserializeOwned> {
+    _id: Uid<Envelope<P>>,
+    payload: P,
+}
+
 // Finally, the actual tests.
 
 implement_tests!{
@@ -692,6 +820,964 @@ implement_tests!{
         Ok(())
     }
 
+    #[test]
+    fn keyset_pagination() -> Result<()> {
+        #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Doc)]
+        #[id_type = "i32"]
+        struct Player {
+            _id: Uid<Player>,
+            score: i32,
+        }
+
+        let coll: Collection<Player> = DB_HANDLE.empty_collection_novalidate()?;
+        // Lots of ties on the score, so that `_id` has to break them,
+        // sometimes across page boundaries.
+        let scores = [5, 3, 5, 1, 3, 5, 2, 3, 5, 1, 4];
+        let players: Vec<_> = scores
+            .iter()
+            .enumerate()
+            .map(|(i, &score)| Player { _id: Uid::from_raw(i as i32), score })
+            .collect();
+
+        coll.insert_many(&players)?;
+
+        let mut expected = players.clone();
+        expected.sort_by_key(|p| (-p.score, p._id.clone().into_raw()));
+
+        let paginator = Paginator::new(doc!{}, vec![("score", Order::Descending)], 3)?;
+        let mut visited = Vec::new();
+        let mut page = paginator.find_page(&coll, None)?;
+        let mut num_pages = 1;
+
+        loop {
+            assert!(page.items.len() <= 3);
+            visited.extend(page.items);
+
+            match page.next {
+                Some(token) => {
+                    // Tokens survive being round-tripped through a client.
+                    let token: PageToken = token.to_string().parse()?;
+                    page = paginator.find_page(&coll, Some(&token))?;
+                    num_pages += 1;
+                }
+                None => break,
+            }
+        }
+
+        // No duplicates, no gaps, and the right order.
+        assert_eq!(visited, expected);
+        assert_eq!(num_pages, 4);
+
+        // Filters combine with the keyset condition.
+        let paginator = Paginator::new(
+            doc!{ "score": { "$gte": 3 } },
+            vec![("score", Order::Descending)],
+            2,
+        )?;
+        let first = paginator.find_page(&coll, None)?;
+        let second = paginator.find_page(&coll, first.next.as_ref())?;
+        let ids: Vec<_> = first.items
+            .iter()
+            .chain(&second.items)
+            .map(|p| p._id.clone().into_raw())
+            .collect();
+
+        assert_eq!(ids, vec![0, 2, 5, 8]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resumable_scan() -> Result<()> {
+        use avocado::scan::{ CheckpointStore, FileCheckpoint };
+
+        #[derive(Debug, Clone, Copy)]
+        struct MarkProcessed(i32);
+
+        impl Update<Measurement> for MarkProcessed {
+            fn filter(&self) -> Document {
+                doc!{ "_id": self.0 }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "processed": true } }
+            }
+        }
+
+        let coll: Collection<Measurement> = DB_HANDLE.empty_collection()?;
+        let measurements: Vec<_> = (0..10)
+            .map(|i| Measurement {
+                _id: Uid::from_raw(i),
+                value: i * i,
+                processed: false,
+            })
+            .collect();
+
+        coll.insert_many(&measurements)?;
+
+        let mut checkpoint = FileCheckpoint::new({
+            let mut path = temp_dir();
+            path.push("avocado_resumable_scan.ckpt");
+            path
+        });
+        CheckpointStore::<Measurement>::clear(&mut checkpoint)?;
+
+        // Simulate a crash while processing the 3rd batch
+        let mut num_batches = 0;
+        let crashed = coll.scan(3, &mut checkpoint, |c, batch| {
+            num_batches += 1;
+
+            if num_batches == 3 {
+                return Err(AvocadoError::new(AvocadoErrorKind::MongoDbError, "crash"));
+            }
+            for m in batch {
+                c.update_one(MarkProcessed(m._id.into_raw()))?;
+            }
+
+            Ok(())
+        });
+
+        assert!(crashed.is_err());
+        assert_eq!(CheckpointStore::<Measurement>::load(&mut checkpoint)?,
+                   Some(Uid::from_raw(5)));
+        assert_eq!(coll.count(doc!{ "processed": true })?, 6);
+
+        // Resuming should only visit the rest of the documents
+        let mut seen = Vec::new();
+        let num_processed = coll.scan(3, &mut checkpoint, |c, batch| {
+            for m in batch {
+                seen.push(m._id.clone());
+                c.update_one(MarkProcessed(m._id.into_raw()))?;
+            }
+            Ok(())
+        })?;
+
+        assert_eq!(num_processed, 4);
+        assert_eq!(seen, (6..10).map(Uid::from_raw).collect::<Vec<_>>());
+        assert_eq!(coll.count(doc!{ "processed": true })?, 10);
+
+        CheckpointStore::<Measurement>::clear(&mut checkpoint)
+    }
+
+    #[test]
+    fn parallel_batch_processing() -> Result<()> {
+        #[cfg(feature = "rayon")]
+        fn run() -> Result<()> {
+            use std::sync::atomic::{ AtomicUsize, Ordering };
+            use avocado::error::ErrorExt;
+
+            #[derive(Debug, Clone, Copy)]
+            struct SmallBatches;
+
+            impl Query<Measurement> for SmallBatches {
+                type Output = Measurement;
+
+                fn filter(&self) -> Document {
+                    Document::new()
+                }
+
+                fn options(&self) -> FindOptions {
+                    FindOptions {
+                        batch_size: Some(7),
+                        ..Default::default()
+                    }
+                }
+            }
+
+            let coll: Collection<Measurement> = DB_HANDLE.empty_collection()?;
+            let measurements: Vec<_> = (0..100)
+                .map(|i| Measurement {
+                    _id: Uid::from_raw(i),
+                    value: i,
+                    processed: false,
+                })
+                .collect();
+
+            coll.insert_many(&measurements)?;
+
+            // Every document should be visited exactly once
+            let sum = AtomicUsize::new(0);
+            let count = AtomicUsize::new(0);
+
+            coll.find_many(SmallBatches)?.par_for_each_batch(|m| {
+                sum.fetch_add(m.value as usize, Ordering::SeqCst);
+                count.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })?;
+
+            assert_eq!(count.load(Ordering::SeqCst), 100);
+            assert_eq!(sum.load(Ordering::SeqCst), (0..100).sum::<usize>());
+
+            // The first error should stop processing and be propagated
+            let error = coll.find_many(SmallBatches)?.par_for_each_batch(|m| {
+                if m.value == 42 {
+                    Err(AvocadoError::new(AvocadoErrorKind::MissingId, "bad value"))
+                } else {
+                    Ok(())
+                }
+            }).unwrap_err();
+
+            assert_eq!(error.kind(), AvocadoErrorKind::MissingId);
+
+            Ok(())
+        }
+
+        #[cfg(not(feature = "rayon"))]
+        fn run() -> Result<()> {
+            Ok(())
+        }
+
+        run()
+    }
+
+    #[test]
+    fn tail_capped_collection() -> Result<()> {
+        use std::time::Duration;
+
+        fn log_line(level: &str, message: &str) -> LogLine {
+            LogLine {
+                _id: Uid::new_oid().unwrap(),
+                level: level.into(),
+                message: message.into(),
+            }
+        }
+
+        let coll: Collection<LogLine> = DB_HANDLE.empty_capped_collection()?;
+        let mut tail = coll
+            .tail(doc!{ "level": "error" })
+            .retry_delay(Duration::from_millis(50));
+
+        coll.insert_many(&[
+            log_line("error", "disk full"),
+            log_line("info", "starting up"),
+            log_line("error", "out of memory"),
+        ])?;
+
+        assert_eq!(tail.next().unwrap()?.message, "disk full");
+        assert_eq!(tail.next().unwrap()?.message, "out of memory");
+
+        // Documents inserted later are picked up by the same iterator
+        coll.insert_one(&log_line("error", "connection reset"))?;
+        assert_eq!(tail.next().unwrap()?.message, "connection reset");
+
+        // The collection only retains the newest 5 documents
+        for i in 0..10 {
+            coll.insert_one(&log_line("debug", &i.to_string()))?;
+        }
+        assert_eq!(coll.count(doc!{})?, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_entity_diff() -> Result<()> {
+        use avocado::coll::UpdateOneResult;
+
+        #[derive(Debug, Clone)]
+        struct SetDescription(Uid<Group>, &'static str);
+
+        impl Update<Group> for SetDescription {
+            fn filter(&self) -> Document {
+                doc!{ "_id": self.0.clone() }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "description": self.1 } }
+            }
+        }
+
+        let coll: Collection<Group> = DB_HANDLE.empty_collection()?;
+        let old = Group {
+            _id: Uid::new_oid()?,
+            name: "admins".into(),
+            description: "administrators".into(),
+        };
+        coll.insert_one(&old)?;
+
+        // Nothing changed, so nothing should be sent to the database
+        assert_eq!(coll.update_entity_diff(&old, &old.clone())?,
+                   UpdateOneResult { matched: false, modified: false });
+
+        // A concurrent writer modifies a different field
+        coll.update_one(SetDescription(old._id.clone(), "superusers"))?;
+
+        let new = Group {
+            name: "root".into(),
+            ..old.clone()
+        };
+        assert_eq!(coll.update_entity_diff(&old, &new)?,
+                   UpdateOneResult { matched: true, modified: true });
+
+        let stored = coll.find_one(doc!{ "_id": old._id.clone() })?.unwrap();
+        assert_eq!(stored.name, "root");
+        assert_eq!(stored.description, "superusers");
+
+        Ok(())
+    }
+
+    #[test]
+    fn tracked_entities_and_unit_of_work() -> Result<()> {
+        use avocado::coll::{ UpdateOneResult, UpdateManyResult };
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection()?;
+        let prs: Collection<PullRequest> = DB_HANDLE.empty_collection()?;
+
+        let group = Group {
+            _id: Uid::new_oid()?,
+            name: "maintainers".into(),
+            description: "people with commit access".into(),
+        };
+        let pr_1 = PullRequest {
+            id: Uid::new_oid()?,
+            title: "Fix typo".into(),
+            lines_changed: 1,
+        };
+        let pr_2 = PullRequest {
+            id: Uid::new_oid()?,
+            title: "Rewrite everything".into(),
+            lines_changed: 10000,
+        };
+
+        groups.insert_one(&group)?;
+        prs.insert_many(&[pr_1.clone(), pr_2.clone()])?;
+
+        // Saving an unmodified entity is a no-op
+        let mut tracked_group = groups.find_one_tracked(doc!{ "_id": group._id.clone() })?.unwrap();
+        assert!(!tracked_group.is_modified()?);
+        assert_eq!(tracked_group.save()?, UpdateOneResult { matched: false, modified: false });
+
+        tracked_group.name = "owners".into();
+        assert_eq!(tracked_group.changes()?, doc!{ "$set": { "name": "owners" } });
+        assert_eq!(tracked_group.save()?, UpdateOneResult { matched: true, modified: true });
+        assert!(!tracked_group.is_modified()?);
+
+        // A unit of work spanning several collections
+        let mut tracked_pr_1 = prs.find_one_tracked(doc!{ "_id": pr_1.id.clone() })?.unwrap();
+        let mut tracked_pr_2 = prs.find_one_tracked(doc!{ "_id": pr_2.id.clone() })?.unwrap();
+
+        tracked_group.description = "people with admin access".into();
+        tracked_pr_1.lines_changed = 2;
+        tracked_pr_2.title = "Rewrite some things".into();
+
+        let mut unit = UnitOfWork::new();
+        unit.add(&mut tracked_group);
+        unit.add(&mut tracked_pr_1);
+        unit.add(&mut tracked_pr_2);
+        assert_eq!(unit.len(), 3);
+        assert_eq!(unit.flush()?, UpdateManyResult { num_matched: 3, num_modified: 3 });
+
+        assert!(!tracked_pr_1.is_modified()?);
+        assert_eq!(groups.find_one(doc!{})?.unwrap().description, "people with admin access");
+        assert_eq!(prs.find_one(doc!{ "_id": pr_1.id.clone() })?.unwrap().lines_changed, 2);
+        assert_eq!(prs.find_one(doc!{ "_id": pr_2.id.clone() })?.unwrap().title, "Rewrite some things");
+
+        Ok(())
+    }
+
+    #[test]
+    fn optimistic_concurrency() -> Result<()> {
+        use avocado::coll::UpdateOneResult;
+        use avocado::error::ErrorExt;
+
+        let coll: Collection<Article> = DB_HANDLE.empty_collection()?;
+        let original = Article {
+            _id: Uid::new_oid()?,
+            title: "Draft".into(),
+            rev: 0,
+        };
+        coll.insert_one(&original)?;
+
+        // The first writer succeeds and bumps the version
+        let first = Article { title: "First".into(), ..original.clone() };
+        assert_eq!(coll.replace_entity(&first)?,
+                   UpdateOneResult { matched: true, modified: true });
+        assert_eq!(coll.find_one(doc!{})?.unwrap().rev, 1);
+
+        // The second writer still has the old version
+        let second = Article { title: "Second".into(), ..original.clone() };
+        let error = coll.replace_entity(&second).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+
+        let error = coll.upsert_entity(&second).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+
+        let error = coll.update_entity_diff(&original, &second).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+
+        let stored = coll.find_one(doc!{})?.unwrap();
+        assert_eq!(stored.title, "First");
+        assert_eq!(stored.rev, 1);
+
+        // Diffing against the current version works, and bumps the version
+        let third = Article { title: "Third".into(), ..stored.clone() };
+        assert_eq!(coll.update_entity_diff(&stored, &third)?,
+                   UpdateOneResult { matched: true, modified: true });
+        assert_eq!(coll.find_one(doc!{})?.unwrap().rev, 2);
+
+        // Tracked entities keep their in-memory version in sync
+        let mut tracked = coll.find_one_tracked(doc!{})?.unwrap();
+        let mut stale = coll.find_one_tracked(doc!{})?.unwrap();
+
+        tracked.title = "Fourth".into();
+        tracked.save()?;
+        assert_eq!(tracked.rev, 3);
+
+        tracked.title = "Fifth".into();
+        tracked.save()?;
+        assert_eq!(tracked.rev, 4);
+
+        stale.title = "Stale".into();
+        let error = stale.save().unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+
+        let stored = coll.find_one(doc!{})?.unwrap();
+        assert_eq!(stored.title, "Fifth");
+        assert_eq!(stored.rev, 4);
+
+        // Stale copies can't delete the document either
+        let error = coll.delete_entity(&third).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+
+        let error = coll.delete_entities(&[third.clone()]).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+        assert_eq!(coll.count(doc!{})?, 1);
+
+        assert!(coll.delete_entity(&stored)?);
+        assert_eq!(coll.count(doc!{})?, 0);
+
+        // A unit of work still saves the entities which weren't stale
+        let other = Article {
+            _id: Uid::new_oid()?,
+            title: "Other".into(),
+            rev: 0,
+        };
+        coll.insert_one(&other)?;
+
+        let mut fresh = coll.find_one_tracked(doc!{ "_id": other._id.clone() })?.unwrap();
+        fresh.title = "Updated".into();
+
+        let mut unit = UnitOfWork::new();
+        unit.add(&mut stale);
+        unit.add(&mut fresh);
+
+        let error = unit.flush().unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::ConcurrentModification);
+        assert!(stale.is_modified()?);
+        assert!(!fresh.is_modified()?);
+        assert_eq!(fresh.rev, 1);
+        assert_eq!(coll.find_one(doc!{})?.unwrap().title, "Updated");
+
+        Ok(())
+    }
+
+    #[test]
+    fn soft_delete() -> Result<()> {
+        use avocado::error::ErrorExt;
+
+        let coll: Collection<Comment> = DB_HANDLE.empty_collection()?;
+        let comments: Vec<_> = (0..4).map(|i| Comment {
+            _id: Uid::new_oid().expect("can't generate ObjectId"),
+            text: format!("comment #{}", i),
+            deleted_at: None,
+        }).collect();
+
+        coll.insert_many(&comments)?;
+
+        // Deleted documents are kept, but they are excluded from queries
+        assert!(coll.delete_one(doc!{ "text": "comment #0" })?);
+        assert!(!coll.delete_one(doc!{ "text": "comment #0" })?);
+        assert_eq!(coll.delete_many(doc!{ "text": { "$in": ["comment #0", "comment #1"] } })?, 1);
+        assert!(coll.delete_entity(&comments[2])?);
+
+        assert_eq!(coll.count(doc!{})?, 1);
+        assert_eq!(coll.find_one(doc!{ "text": "comment #0" })?, None);
+        assert_eq!(coll.find_many(doc!{})?.collect::<Result<Vec<_>>>()?, &comments[3..]);
+
+        // ...unless they are explicitly asked for
+        assert_eq!(coll.count(IncludeDeleted(doc!{}))?, 4);
+
+        let deleted = coll.find_one(IncludeDeleted(doc!{ "text": "comment #0" }))?.unwrap();
+        assert!(deleted.deleted_at.is_some());
+
+        // `find_one_and_delete()` returns the document as it was before deletion
+        let found = coll.find_one_and_delete(doc!{ "text": "comment #3" })?;
+        assert_eq!(found.as_ref(), Some(&comments[3]));
+        assert_eq!(coll.count(doc!{})?, 0);
+
+        // Deleted documents can be restored...
+        assert_eq!(coll.restore(doc!{ "text": { "$in": ["comment #0", "comment #3"] } })?, 2);
+        assert_eq!(coll.find_one(doc!{ "text": "comment #0" })?.as_ref(), Some(&comments[0]));
+        assert_eq!(coll.count(doc!{})?, 2);
+
+        // ...or purged, which only removes documents that are already deleted
+        assert_eq!(coll.purge(doc!{})?, 2);
+        assert_eq!(coll.count(IncludeDeleted(doc!{}))?, 2);
+        assert_eq!(coll.count(doc!{})?, 2);
+
+        // Updates leave deleted documents alone, unless they are asked for
+        #[derive(Debug, Clone, Copy)]
+        struct Edit(&'static str);
+
+        impl Update<Comment> for Edit {
+            fn filter(&self) -> Document {
+                doc!{}
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "text": self.0 } }
+            }
+        }
+
+        assert!(coll.delete_one(doc!{ "text": "comment #0" })?);
+        assert_eq!(coll.update_many(Edit("edited"))?.num_matched, 1);
+        assert_eq!(coll.update_many(IncludeDeleted(Edit("edited")))?.num_matched, 2);
+
+        // Types without a soft-delete field can't be restored or purged
+        let groups: Collection<Group> = DB_HANDLE.empty_collection()?;
+        let error = groups.purge(doc!{}).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::InvalidConfiguration);
+
+        Ok(())
+    }
+
+    #[test]
+    fn automatic_timestamps() -> Result<()> {
+        #[derive(Debug, Clone, Copy)]
+        struct Retitle(&'static str, &'static str);
+
+        impl Update<Note> for Retitle {
+            fn filter(&self) -> Document {
+                doc!{ "title": self.0 }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "title": self.1 } }
+            }
+        }
+
+        impl Upsert<Note> for Retitle {
+            fn filter(&self) -> Document {
+                doc!{ "title": self.0 }
+            }
+
+            fn upsert(&self) -> Document {
+                doc!{ "$set": { "title": self.1 } }
+            }
+        }
+
+        let coll: Collection<Note> = DB_HANDLE.empty_collection()?;
+        let mut note = Note {
+            _id: Uid::new_oid()?,
+            title: String::from("first"),
+            created: None,
+            updated: None,
+        };
+
+        // Inserting sets both timestamps to the same value
+        coll.insert_one(&note)?;
+
+        let inserted = coll.find_one(doc!{})?.unwrap();
+        assert!(inserted.created.is_some());
+        assert_eq!(inserted.created, inserted.updated);
+
+        // Updates only touch the modification timestamp
+        coll.update_one(Retitle("first", "second"))?;
+
+        let updated = coll.find_one(doc!{})?.unwrap();
+        assert_eq!(updated.title, "second");
+        assert_eq!(updated.created, inserted.created);
+        assert!(updated.updated >= inserted.updated);
+
+        // Replacing preserves an existing creation timestamp...
+        note.title = String::from("third");
+        note.created = inserted.created;
+        coll.replace_entity(&note)?;
+
+        let replaced = coll.find_one(doc!{})?.unwrap();
+        assert_eq!(replaced.title, "third");
+        assert_eq!(replaced.created, inserted.created);
+        assert!(replaced.updated >= updated.updated);
+
+        // Upserting an entity loaded without it doesn't overwrite it...
+        note.title = String::from("partial");
+        note.created = None;
+        coll.upsert_entity(&note)?;
+
+        let partial = coll.find_one(doc!{})?.unwrap();
+        assert_eq!(partial.title, "partial");
+        assert_eq!(partial.created, inserted.created);
+
+        // ...but replacing writes whatever the entity carries
+        coll.replace_entity(&note)?;
+
+        let cleared = coll.find_one(doc!{})?.unwrap();
+        assert_eq!(cleared.title, "partial");
+        assert_eq!(cleared.created, None);
+
+        // Upserting a new entity sets it
+        let other = Note {
+            _id: Uid::new_oid()?,
+            title: String::from("other"),
+            created: None,
+            updated: None,
+        };
+        coll.upsert_entity(&other)?;
+
+        let created = coll.find_one(doc!{ "title": "other" })?.unwrap();
+        assert!(created.created.is_some());
+        assert!(created.updated.is_some());
+
+        let upserted = coll.upsert_one(Retitle("fourth", "fourth"))?;
+        assert!(upserted.upserted_id.is_some());
+
+        let fresh = coll.find_one(doc!{ "title": "fourth" })?.unwrap();
+        assert!(fresh.created.is_some());
+        assert!(fresh.updated.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lifecycle_hooks() -> Result<()> {
+        use avocado::error::ErrorExt;
+
+        let coll: Collection<Tag> = DB_HANDLE.empty_collection()?;
+        let rust = Tag {
+            _id: Uid::new_oid()?,
+            name: String::from("Rust"),
+            pinned: false,
+            loaded: false,
+        };
+        let mongo = Tag {
+            _id: Uid::new_oid()?,
+            name: String::from("MongoDB"),
+            pinned: true,
+            loaded: false,
+        };
+        let empty = Tag {
+            _id: Uid::new_oid()?,
+            name: String::new(),
+            pinned: false,
+            loaded: false,
+        };
+
+        // Hooks can modify the document to be inserted...
+        coll.insert_many(&[rust.clone(), mongo.clone()])?;
+        assert_eq!(coll.count(doc!{ "name": { "$in": ["rust", "mongodb"] } })?, 2);
+
+        // ...or veto the insertion altogether
+        assert!(coll.insert_one(&empty).is_err());
+        assert!(coll.insert_many(&[empty.clone()]).is_err());
+        assert_eq!(coll.count(doc!{})?, 2);
+
+        // Loaded entities are passed to `after_load()`
+        let loaded = coll.find_one(doc!{ "name": "rust" })?.unwrap();
+        assert!(loaded.loaded);
+        assert!(coll.find_many(doc!{})?.all(|result| result.map_or(false, |tag| tag.loaded)));
+
+        let replacement = Tag { name: String::from("rust"), ..rust.clone() };
+        let replaced = coll.find_one_and_replace(doc!{ "name": "rust" }, &replacement)?.unwrap();
+        assert!(replaced.loaded);
+
+        // Replacements are hooked too
+        let mut renamed = loaded.clone();
+        renamed.name = String::new();
+        assert!(coll.replace_entity(&renamed).is_err());
+
+        renamed.name = String::from("Rust-Lang");
+        coll.replace_entity(&renamed)?;
+        assert_eq!(coll.count(doc!{ "name": "rust-lang" })?, 1);
+
+        // So are diff-based updates and tracked saves
+        let mut unnamed = renamed.clone();
+        unnamed.name = String::new();
+        assert!(coll.update_entity_diff(&renamed, &unnamed).is_err());
+
+        let mut tracked = coll.find_one_tracked(doc!{ "name": "rust-lang" })?.unwrap();
+        tracked.name = String::new();
+        assert!(tracked.save().is_err());
+
+        tracked.name = String::from("Rust 2018");
+        tracked.save()?;
+        assert_eq!(coll.count(doc!{ "name": "rust 2018" })?, 1);
+
+        // Deletions can be vetoed
+        let error = coll.delete_entity(&mongo).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::InvalidConfiguration);
+        assert!(coll.delete_entity(&renamed)?);
+        assert_eq!(coll.count(doc!{})?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_side_validation() -> Result<()> {
+        use avocado::error::ErrorExt;
+        use avocado::validation::ValidationErrors;
+
+        let coll: Collection<Member> = DB_HANDLE.empty_collection()?;
+        let mut member = Member {
+            _id: Uid::new_oid()?,
+            nick_name: String::from("x"),
+            age: -1,
+        };
+
+        // Invalid entities are rejected before reaching the server
+        let error = coll.insert_one(&member).unwrap_err();
+        let violations = error.context::<ValidationErrors>().unwrap();
+        assert_eq!(error.kind(), AvocadoErrorKind::Validation);
+        assert_eq!(violations.field("nickName"), &["length must be at least 2"]);
+        assert_eq!(violations.field("age"), &["must be at least 0"]);
+
+        assert!(coll.insert_many(&[member.clone()]).is_err());
+        assert!(coll.upsert_entity(&member).is_err());
+        assert_eq!(coll.count(doc!{})?, 0);
+
+        // Valid ones are written as usual
+        member.nick_name = String::from("xyz");
+        member.age = 42;
+        coll.insert_one(&member)?;
+
+        member.age = 121;
+        let error = coll.replace_entity(&member).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::Validation);
+        assert_eq!(coll.count(doc!{ "age": 42 })?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_side_schema_validation() -> Result<()> {
+        use avocado::error::ErrorExt;
+        use avocado::schema::validate_document_for;
+        use avocado::validation::ValidationErrors;
+        use mongodb::db::ThreadedDatabase;
+
+        let _: Collection<Member> = DB_HANDLE.empty_collection()?;
+        let raw = DB_HANDLE.collection(Member::NAME);
+        let valid = doc!{ "_id": ObjectId::new()?, "nickName": "abc", "age": 30 };
+        let invalid = doc!{ "_id": ObjectId::new()?, "nickName": 42, "age": 30 };
+
+        // The client-side validator agrees with the server
+        validate_document_for::<Member>(&valid)?;
+        assert!(raw.insert_one(valid, None)?.write_exception.is_none());
+
+        let error = validate_document_for::<Member>(&invalid).unwrap_err();
+        let violations = error.context::<ValidationErrors>().unwrap();
+        assert_eq!(error.kind(), AvocadoErrorKind::Validation);
+        assert_eq!(violations.field("nickName").len(), 1);
+        assert!(raw.insert_one(invalid, None)?.write_exception.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_drift() -> Result<()> {
+        use avocado::drift::Drift;
+        use mongodb::db::ThreadedDatabase;
+
+        let coll: Collection<Member> = DB_HANDLE.empty_collection_novalidate()?;
+        let raw = DB_HANDLE.collection(Member::NAME);
+        let ids = vec![ObjectId::new()?, ObjectId::new()?, ObjectId::new()?, ObjectId::new()?];
+
+        raw.insert_many(vec![
+            doc!{ "_id": ids[0].clone(), "nickName": "abc", "age": 30 },
+            doc!{ "_id": ids[1].clone(), "nickName": 42, "age": 31 },
+            doc!{ "_id": ids[2].clone(), "nickName": "def" },
+            doc!{ "_id": ids[3].clone(), "nickName": "ghi", "age": 33, "email": "a@b" },
+        ], None)?;
+
+        let report = coll.schema_drift(None)?;
+        let mismatch = report.get("nickName", Drift::TypeMismatch).unwrap();
+
+        assert_eq!(report.num_documents(), 4);
+        assert_eq!(report.fields().len(), 3);
+        assert_eq!(mismatch.count(), 1);
+        assert_eq!(mismatch.found_types().get("int"), Some(&1));
+        assert_eq!(mismatch.example_ids(), &[Bson::ObjectId(ids[1].clone())]);
+        assert_eq!(report.get("age", Drift::Missing).unwrap().example_ids(),
+                   &[Bson::ObjectId(ids[2].clone())]);
+        assert_eq!(report.get("email", Drift::Undeclared).unwrap().count(), 1);
+
+        // Sampling never checks more documents than requested
+        let sampled = coll.schema_drift(Some(2))?;
+        assert_eq!(sampled.num_documents(), 2);
+
+        raw.delete_many(doc!{ "_id": { "$in": [ids[1].clone(), ids[2].clone()] } }, None)?;
+        raw.update_one(doc!{ "_id": ids[3].clone() }, doc!{ "$unset": { "email": "" } }, None)?;
+
+        assert!(coll.schema_drift(None)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn polymorphic_collection() -> Result<()> {
+        let coll: Collection<Payment> = DB_HANDLE.empty_collection_novalidate()?;
+        let card = CardPayment {
+            _id: Uid::new_oid()?,
+            amount: 100,
+            last_digits: "1234".into(),
+        };
+        let transfers = vec![
+            TransferPayment { _id: Uid::new_oid()?, amount: 200, iban: "HU42".into() },
+            TransferPayment { _id: Uid::new_oid()?, amount: 300, iban: "DE89".into() },
+        ];
+        let mut payments = vec![
+            Payment::Card(card.clone()),
+            Payment::Refund { _id: Uid::new_oid()?, amount: -100 },
+        ];
+        payments.extend(transfers.iter().cloned().map(Payment::Transfer));
+
+        coll.insert_many(&payments)?;
+
+        // The whole enum round-trips through the collection
+        let refund = coll.find_one(doc!{ "type": "Refund" })?.unwrap();
+        assert_eq!(refund, payments[1]);
+        assert_eq!(coll.find_one(doc!{ "_id": &card._id })?, Some(payments[0].clone()));
+
+        // Querying a single variant returns its payload
+        let found: Vec<TransferPayment> = coll
+            .find_many(OnlyVariant::new(doc!{ "amount": { "$gte": 150 } }))?
+            .collect::<Result<_>>()?;
+        assert_eq!(found, transfers);
+
+        let cards: Vec<CardPayment> = coll
+            .find_many(OnlyVariant::<CardPayment>::default())?
+            .collect::<Result<_>>()?;
+        assert_eq!(cards, vec![card]);
+
+        assert_eq!(coll.count(OnlyVariant::<TransferPayment>::default())?, 2);
+        assert_eq!(coll.delete_many(OnlyVariant::<TransferPayment>::default())?, 2);
+        assert_eq!(coll.count(doc!{})?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn generic_doc_collections() -> Result<()> {
+        let groups: Collection<Envelope<Group>> = DB_HANDLE.empty_collection_novalidate()?;
+        let tags: Collection<Envelope<Tag>> = DB_HANDLE.empty_collection_novalidate()?;
+        let group = Envelope {
+            _id: Uid::new_oid()?,
+            payload: Group {
+                _id: Uid::new_oid()?,
+                name: "admins".into(),
+                description: "administrators".into(),
+            },
+        };
+
+        assert_eq!(Envelope::<Group>::collection_name(), "Envelope_Group");
+        assert_eq!(Envelope::<Tag>::collection_name(), "Envelope_Tag");
+
+        groups.insert_one(&group)?;
+
+        assert_eq!(groups.find_one(doc!{})?, Some(group));
+        assert_eq!(tags.count(doc!{})?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_by_example() -> Result<()> {
+        use avocado::literal::RegexOpts;
+
+        #[derive(Debug, Default, Serialize)]
+        struct GroupProbe {
+            name: Option<String>,
+            description: Option<String>,
+        }
+
+        let coll: Collection<Group> = DB_HANDLE.empty_collection_novalidate()?;
+        let groups = vec![
+            Group {
+                _id: Uid::new_oid()?,
+                name: "Admins".into(),
+                description: "administrators".into(),
+            },
+            Group {
+                _id: Uid::new_oid()?,
+                name: "admin-bots".into(),
+                description: "automated administrators".into(),
+            },
+            Group {
+                _id: Uid::new_oid()?,
+                name: "users".into(),
+                description: "regular users".into(),
+            },
+        ];
+
+        coll.insert_many(&groups)?;
+
+        // An empty probe is refused, lest it match everything
+        let error = Example::<Group>::new(&GroupProbe::default()).unwrap_err();
+        assert_eq!(error.kind(), AvocadoErrorKind::InvalidConfiguration);
+        assert_eq!(coll.count(doc!{})?, 3);
+
+        // Exact match is case-sensitive by default
+        let probe = GroupProbe { name: Some("admins".into()), description: None };
+        assert_eq!(coll.find_one(Example::new(&probe)?)?, None);
+        assert_eq!(coll.find_one(Example::new(&probe)?.ignore_case("name"))?,
+                   Some(groups[0].clone()));
+
+        // Prefix matching, with regex metacharacters in the probe escaped
+        let probe = GroupProbe { name: Some("ADMIN".into()), description: None };
+        let found: Vec<_> = coll
+            .find_many(Example::new(&probe)?.prefix("name", RegexOpts::IGNORE_CASE))?
+            .collect::<Result<_>>()?;
+        assert_eq!(found, &groups[..2]);
+
+        let probe = GroupProbe { name: Some("admin.".into()), description: None };
+        assert_eq!(coll.count(Example::new(&probe)?.prefix("name", RegexOpts::IGNORE_CASE))?, 0);
+
+        // Several fields must all match
+        let probe = GroupProbe {
+            name: Some("users".into()),
+            description: Some("regular users".into()),
+        };
+        assert_eq!(coll.delete_many(Example::new(&probe)?)?, 1);
+        assert_eq!(coll.count(doc!{})?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn projection_output() -> Result<()> {
+        #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+        struct Venture {
+            _id: Uid<Venture>,
+            name: String,
+            budget: u32,
+        }
+
+        #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Projection)]
+        #[projection(of = "Venture")]
+        struct VentureName {
+            name: String,
+        }
+
+        let coll: Collection<Venture> = DB_HANDLE.empty_collection_novalidate()?;
+        let ventures = vec![
+            Venture { _id: Uid::new_oid()?, name: "avocado".into(), budget: 100 },
+            Venture { _id: Uid::new_oid()?, name: "magnet".into(), budget: 200 },
+        ];
+
+        coll.insert_many(&ventures)?;
+
+        let names: Vec<VentureName> = coll
+            .find_many(Project::new(doc!{}))?
+            .collect::<Result<_>>()?;
+        assert_eq!(names, vec![
+            VentureName { name: "avocado".into() },
+            VentureName { name: "magnet".into() },
+        ]);
+
+        let name = coll.find_one(Project::<VentureName>::new(doc!{ "budget": 200 }))?;
+        assert_eq!(name, Some(VentureName { name: "magnet".into() }));
+
+        Ok(())
+    }
+
     #[test]
     fn keep_server_alive() {}
 }
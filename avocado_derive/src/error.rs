@@ -3,17 +3,26 @@
 use std::fmt;
 use std::error;
 use std::result;
-use std::ops::Deref;
 use std::num::{ ParseIntError, ParseFloatError };
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
+use proc_macro2::{ Span, TokenStream };
 use syn::synom::ParseError;
+use syn::spanned::Spanned;
 
 /// Returns an `Err(Error::new(...))` with the given formatted error message.
 macro_rules! err_fmt {
     ($($arg:tt)*) => { Err(crate::error::Error::new(format!($($arg)*))) }
 }
 
+/// Returns an `Err(Error::new(...))` with the given formatted error message,
+/// pointing to the source code of the given syntax tree node.
+macro_rules! err_at {
+    ($node:expr, $($arg:tt)*) => {
+        Err(crate::error::Error::new(format!($($arg)*)).with_span_of(&$node))
+    }
+}
+
 /// Returns an `Err(Error::new(...))` with the given literal error message.
 pub fn err_msg<T>(message: &str) -> Result<T> {
     Err(Error::new(message))
@@ -22,41 +31,116 @@ pub fn err_msg<T>(message: &str) -> Result<T> {
 /// Convenience type alias for a result that holds a `avocado_derive::Error` value.
 pub type Result<T> = result::Result<T, Error>;
 
-/// An error that potentially happens while `#[derive]`ing `Doc`.
+/// An error that potentially happens while `#[derive]`ing `Doc`. It may
+/// consist of several independent problems, which are all reported at once.
 #[derive(Debug)]
 pub struct Error {
-    /// The error message.
-    message: String,
-    /// The underlying error, if any.
-    cause: Option<Box<dyn error::Error + 'static>>,
+    /// The error messages, along with the location they refer to, if known.
+    /// Never empty.
+    messages: Vec<(String, Option<Span>)>,
 }
 
 impl Error {
     /// Creates an `Error` instance with the specified message.
     pub fn new<T: Into<String>>(message: T) -> Self {
         Error {
-            message: message.into(),
-            cause: None,
+            messages: vec![(message.into(), None)],
+        }
+    }
+
+    /// Makes the messages which don't yet refer to a location point to the
+    /// source code of `node`. Messages are thus attributed to the innermost
+    /// node which they were attached to.
+    pub fn with_span_of<S: Spanned>(mut self, node: &S) -> Self {
+        let node_span = node.span();
+
+        for &mut (_, ref mut span) in &mut self.messages {
+            span.get_or_insert(node_span);
         }
+
+        self
+    }
+
+    /// Appends the messages of `other` to those of `self`.
+    pub fn combine(&mut self, other: Error) {
+        self.messages.extend(other.messages);
+    }
+
+    /// Generates a `compile_error!()` invocation for every message, so that
+    /// the compiler reports it at the corresponding location. Messages without
+    /// a known location are reported at the `#[derive]` attribute.
+    pub fn to_compile_error(&self) -> TokenStream {
+        let errors = self.messages.iter().map(|&(ref message, span)| {
+            quote_spanned! { span.unwrap_or_else(Span::call_site) =>
+                compile_error!(#message);
+            }
+        });
+
+        quote!(#(#errors)*)
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.cause {
-            Some(ref cause) => write!(f, "{}: {}", self.message, cause),
-            None => self.message.fmt(f),
-        }
+        let messages: Vec<_> = self.messages.iter().map(|&(ref message, _)| message.as_str()).collect();
+        f.write_str(&messages.join("; "))
     }
 }
 
 impl error::Error for Error {
     fn description(&self) -> &str {
-        &self.message
+        &self.messages[0].0
     }
+}
+
+/// Collects the errors of independent steps of a derivation, so that all of
+/// them can be reported at once, instead of stopping at the first one.
+#[derive(Debug, Default)]
+pub struct Errors {
+    /// The errors found so far, if any.
+    error: Option<Error>,
+}
+
+impl Errors {
+    /// Creates an empty error collector.
+    pub fn new() -> Self {
+        Errors::default()
+    }
+
+    /// Records an error.
+    pub fn push(&mut self, error: Error) {
+        match self.error {
+            Some(ref mut previous) => previous.combine(error),
+            None => self.error = Some(error),
+        }
+    }
+
+    /// Records the error of `result`, if any. Returns the successful value,
+    /// or a placeholder default value, which must not end up in the output,
+    /// since `finish()` then fails anyway.
+    pub fn record<T: Default>(&mut self, result: Result<T>) -> T {
+        result.unwrap_or_else(|error| {
+            self.push(error);
+            T::default()
+        })
+    }
+
+    /// Returns all recorded errors, if there were any.
+    pub fn finish(self) -> Result<()> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+/// Augments `Result`s with error location information.
+pub trait ResultExt {
+    /// Makes the messages of an error which don't yet refer to a location
+    /// point to the source code of `node`. See `Error::with_span_of()`.
+    fn spanned<S: Spanned>(self, node: &S) -> Self;
+}
 
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        self.cause.as_ref().map(Deref::deref)
+impl<T> ResultExt for Result<T> {
+    fn spanned<S: Spanned>(self, node: &S) -> Self {
+        self.map_err(|error| error.with_span_of(node))
     }
 }
 
@@ -65,10 +149,7 @@ macro_rules! impl_error {
     ($($ty:ident => $message:expr;)*) => {$(
         impl From<$ty> for Error {
             fn from(error: $ty) -> Self {
-                Error {
-                    message: String::from($message),
-                    cause: Some(Box::new(error)),
-                }
+                Error::new(format!("{}: {}", $message, error))
             }
         }
     )*}
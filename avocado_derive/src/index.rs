@@ -1,15 +1,19 @@
 //! Types for describing index specifications.
 
 use std::str::FromStr;
+use std::fmt::Debug;
 use proc_macro2::TokenStream;
-use syn::Attribute;
+use syn::{ Attribute, Field, Expr };
 use quote::{ ToTokens, TokenStreamExt };
 use crate::{
-    error::{ Error, Result, err_msg },
+    error::{ Error, Errors, Result, ResultExt, err_msg },
     attr::*,
     meta::*,
 };
 
+/// The index types which can be specified for a field-level index.
+const FIELD_INDEX_TYPES: &[&str] = &["ascending", "descending", "text", "hashed", "wildcard"];
+
 /// Describes the parts of an index that can be derived using attributes.
 #[derive(Debug, Clone, Default)]
 pub struct Spec {
@@ -34,6 +38,26 @@ pub struct Spec {
     bucket_size: Option<i32>,
     /// The actual indexed field names and their type.
     keys: Vec<(String, Type)>,
+    /// The weights of the fields of a text index, in range `[1, 99999]`.
+    weights: Vec<(String, i32)>,
+    /// The number of seconds after which documents expire, for TTL indexes.
+    expire_after_seconds: Option<i32>,
+    /// An expression evaluating to the `Document` which restricts the index
+    /// to the documents matching it.
+    partial_filter_expression: Option<Expr>,
+    /// The language-specific string comparison rules of the index.
+    collation: Option<Collation>,
+    /// Whether the index is hidden from the query planner.
+    hidden: Option<bool>,
+}
+
+/// The collation of an index.
+#[derive(Debug, Clone, PartialEq)]
+struct Collation {
+    /// The ICU locale, e.g. `"en"` or `"fr_CA"`.
+    locale: String,
+    /// The level of comparison, in range `[1, 5]`.
+    strength: Option<i32>,
 }
 
 impl Spec {
@@ -48,7 +72,7 @@ impl Spec {
             None => return Ok(None),
             Some(meta) => meta,
         };
-        let meta = match meta {
+        let nested_metas = match meta {
             ExtMeta::List(path, _, nested) => {
                 if path.into_token_stream().to_string() == "index" {
                     nested
@@ -59,7 +83,7 @@ impl Spec {
             ExtMeta::Path(path) | ExtMeta::KeyValue(path, ..) => {
                 if path.into_token_stream().to_string() == "index" {
                     // index attribute, but malformed
-                    err_msg("attribute must have form `#[index(...)]`")?
+                    err_at!(attr, "attribute must have form `#[index(...)]`")?
                 } else {
                     // none of our business
                     return Ok(None);
@@ -67,86 +91,315 @@ impl Spec {
             }
         };
 
-        let inner_metas: Vec<_> = meta
+        let inner_metas: Vec<_> = nested_metas
             .into_iter()
             .map(|nested| match nested {
                 NestedExtMeta::Meta(nested_meta) => Ok(nested_meta),
                 NestedExtMeta::Literal(lit) => {
-                    err_fmt!("expected a meta item, found literal: {:#?}", lit)
+                    err_at!(lit, "expected a meta item, found literal: {:#?}", lit)
                 }
             })
             .collect::<Result<_>>()?;
 
-        Self::from_metas(inner_metas)
+        Self::from_metas(inner_metas).spanned(attr)
     }
 
     /// Attempts to create a `Spec` from a list of pre-parsed `Meta` items.
+    /// Problems with all items are reported, not only the first one.
     fn from_metas<I>(inner_metas: I) -> Result<Option<Self>>
         where I: IntoIterator<Item = ExtMeta>
     {
         let mut spec = Spec::default();
+        let mut errors = Errors::new();
 
         for inner_meta in inner_metas {
-            let path_str = inner_meta.path_str();
+            let path = inner_meta.path().clone();
+            errors.record(spec.add_meta(inner_meta).spanned(&path));
+        }
+
+        errors.finish()?;
 
-            match inner_meta {
-                ExtMeta::Path(_) => match path_str.as_str() {
-                    "unique" => spec.unique = Some(true),
-                    "sparse" => spec.sparse = Some(true),
-                    _ => err_fmt!("bad path attribute: {}", path_str)?
+        if spec.keys.is_empty() {
+            err_msg("at least one field must be specified for indexing")
+        } else {
+            Ok(Some(spec))
+        }
+    }
+
+    /// Updates the specification based on a single `Meta` item.
+    fn add_meta(&mut self, inner_meta: ExtMeta) -> Result<()> {
+        let path_str = inner_meta.path_str();
+
+        match inner_meta {
+            ExtMeta::Path(_) => match path_str.as_str() {
+                "unique" => self.unique = Some(true),
+                "sparse" => self.sparse = Some(true),
+                "hidden" => self.hidden = Some(true),
+                "wildcard" => self.keys.push((wildcard_key(""), Type::Wildcard)),
+                _ => err_fmt!("bad path attribute: {}", path_str)?
+            }
+            ExtMeta::KeyValue(_, _, lit) => match path_str.as_str() {
+                "unique" => {
+                    self.unique = value_as_bool(&path_str, &lit)?.into()
                 }
-                ExtMeta::KeyValue(_, _, lit) => match path_str.as_str() {
-                    "unique" => {
-                        spec.unique = value_as_bool(&path_str, &lit)?.into()
-                    }
-                    "sparse" => {
-                        spec.sparse = value_as_bool(&path_str, &lit)?.into()
-                    }
-                    "name" => {
-                        spec.name = lit_value_as_str(&path_str, &lit)?.into()
-                    }
-                    "min" => spec.min = value_as_f64(&path_str,
-                                                     &lit,
-                                                     -180.0..=180.0)?.into(),
-                    "max" => spec.max = value_as_f64(&path_str,
-                                                     &lit,
-                                                     -180.0..=180.0)?.into(),
-                    "bits" => spec.bits = value_as_i32(&path_str,
-                                                       &lit,
-                                                       1..=32)?.into(),
-                    "bucket_size" => spec.bucket_size = value_as_i32(
+                "sparse" => {
+                    self.sparse = value_as_bool(&path_str, &lit)?.into()
+                }
+                "hidden" => {
+                    self.hidden = value_as_bool(&path_str, &lit)?.into()
+                }
+                "name" => {
+                    self.name = lit_value_as_str(&path_str, &lit)?.into()
+                }
+                "expire_after_seconds" => {
+                    self.expire_after_seconds = value_as_i32(
                         &path_str,
                         &lit,
-                        1..
-                    )?.into(),
-                    "default_language" => {
-                        spec.default_language = lit_value_as_str(
-                            &path_str,
-                            &lit
-                        )?.into()
-                    }
-                    "language_override" => {
-                        spec.language_override = lit_value_as_str(
-                            &path_str,
-                            &lit
-                        )?.into()
-                    }
-                    _ => err_fmt!("bad name-value attribute: {}", path_str)?
-                },
-                ExtMeta::List(_, _, list) => match path_str.as_str() {
-                    "keys" => {
-                        spec.keys = list_into_names_and_values(&path_str, list)?
+                        0..
+                    )?.into()
+                }
+                "partial_filter_expression" => {
+                    let expr = lit_value_as_str(&path_str, &lit)?;
+                    self.partial_filter_expression = syn::parse_str::<Expr>(&expr)
+                        .map_err(Error::from)
+                        .spanned(&lit)?
+                        .into();
+                }
+                "min" => self.min = value_as_f64(&path_str,
+                                                 &lit,
+                                                 -180.0..=180.0)?.into(),
+                "max" => self.max = value_as_f64(&path_str,
+                                                 &lit,
+                                                 -180.0..=180.0)?.into(),
+                "bits" => self.bits = value_as_i32(&path_str,
+                                                   &lit,
+                                                   1..=32)?.into(),
+                "bucket_size" => self.bucket_size = value_as_i32(
+                    &path_str,
+                    &lit,
+                    1..
+                )?.into(),
+                "default_language" => {
+                    self.default_language = lit_value_as_str(
+                        &path_str,
+                        &lit
+                    )?.into()
+                }
+                "language_override" => {
+                    self.language_override = lit_value_as_str(
+                        &path_str,
+                        &lit
+                    )?.into()
+                }
+                _ => err_fmt!("bad name-value attribute: {}", path_str)?
+            },
+            ExtMeta::List(_, _, list) => match path_str.as_str() {
+                "keys" => {
+                    self.keys = list_into_names_and_values(&path_str, list)?
+                        .into_iter()
+                        .map(|(key, ty)| match ty {
+                            Type::Wildcard => (wildcard_key(&key), ty),
+                            _ => (key, ty),
+                        })
+                        .collect()
+                }
+                "weights" => {
+                    self.weights = weights_from_list(list)?
+                }
+                "collation" => self.collation = Some(Collation::from_list(list)?),
+                _ => err_fmt!("bad list attribute: {}", path_str)?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Attempts to parse a field-level `#[index]` or `#[index(...)]` attribute
+    /// as a `Spec` of a single-key index. `key` is the name the field is
+    /// serialized as. The index is ascending unless one of the `descending`,
+    /// `text` or `hashed` words is given; text indexes may also specify the
+    /// `weight` of the field. The options of struct-level indexes apply too.
+    pub fn from_field_attribute(attr: &Attribute, key: &str) -> Result<Option<Self>> {
+        let nested_metas = match attr.parse_ext_meta() {
+            Some(ExtMeta::Path(ref path)) if path.colon_sep_str() == "index" => Vec::new(),
+            Some(ExtMeta::List(ref path, _, ref nested)) if path.colon_sep_str() == "index" => {
+                nested.iter().cloned().collect()
+            }
+            Some(ExtMeta::KeyValue(ref path, ..)) if path.colon_sep_str() == "index" => {
+                return err_at!(attr, "attribute must have form `#[index]` or `#[index(...)]`");
+            }
+            _ => return Ok(None),
+        };
+        let mut spec = Spec::default();
+        let mut explicit_type = None;
+        let mut weight = None;
+        let mut errors = Errors::new();
+
+        for nested in nested_metas {
+            let inner_meta = match nested {
+                NestedExtMeta::Meta(inner_meta) => inner_meta,
+                NestedExtMeta::Literal(lit) => {
+                    errors.push(Error::new(
+                        format!("expected a meta item, found literal: {:#?}", lit)
+                    ).with_span_of(&lit));
+                    continue;
+                }
+            };
+            let path = inner_meta.path().clone();
+            let path_str = inner_meta.path_str();
+            let result = match inner_meta {
+                ExtMeta::Path(_) if FIELD_INDEX_TYPES.contains(&path_str.as_str()) => {
+                    if explicit_type.is_some() {
+                        err_msg("the index type must be specified at most once")
+                    } else {
+                        path_str.parse().map(|ty| explicit_type = Some(ty))
                     }
-                    _ => err_fmt!("bad list attribute: {}", path_str)?
                 }
+                ExtMeta::KeyValue(_, _, ref lit) if path_str == "weight" => {
+                    value_as_i32(&path_str, lit, 1..=99_999).map(|value| weight = Some(value))
+                }
+                ExtMeta::List(..) if path_str == "keys" => err_msg(
+                    "a field-level index is always on the field itself; \
+                     use a struct-level `#[index(keys(...))]` for compound indexes"
+                ),
+                other => spec.add_meta(other),
+            };
+
+            errors.record(result.spanned(&path));
+        }
+
+        let index_type = explicit_type.unwrap_or(Type::Ascending);
+
+        if let Some(value) = weight {
+            match index_type {
+                Type::Text => spec.weights.push((key.into(), value)),
+                _ => errors.push(
+                    Error::new("`weight` is only allowed for `text` indexes").with_span_of(attr)
+                ),
             }
         }
 
-        if spec.keys.is_empty() {
-            err_msg("at least one field must be specified for indexing")
-        } else {
-            Ok(Some(spec))
+        errors.finish()?;
+
+        match index_type {
+            Type::Wildcard => spec.keys.push((wildcard_key(key), index_type)),
+            _ => spec.keys.push((key.into(), index_type)),
         }
+
+        Ok(Some(spec))
+    }
+
+    /// Collects the indexes specified by the `#[index]` attributes of the
+    /// serialized fields of a struct. A collection can only have one text
+    /// index, so text indexes on individual fields are merged into a single
+    /// compound text index, placed last.
+    pub fn from_fields(fields: &[(Field, String)]) -> Result<Vec<Spec>> {
+        let mut errors = Errors::new();
+        let mut specs = Vec::new();
+        let mut merged_text: Option<Spec> = None;
+
+        for &(ref field, ref key) in fields {
+            for attr in &field.attrs {
+                let spec = match errors.record(Spec::from_field_attribute(attr, key)) {
+                    Some(spec) => spec,
+                    None => continue,
+                };
+
+                if !spec.is_text() {
+                    specs.push(spec);
+                    continue;
+                }
+
+                match merged_text {
+                    Some(ref mut text_spec) => {
+                        errors.record(text_spec.merge_text_index(spec).spanned(attr));
+                    }
+                    None => merged_text = Some(spec),
+                }
+            }
+        }
+
+        errors.finish()?;
+        specs.extend(merged_text);
+
+        Ok(specs)
+    }
+
+    /// Returns `true` if this is a text index.
+    fn is_text(&self) -> bool {
+        self.keys.iter().any(|&(_, ty)| match ty {
+            Type::Text => true,
+            _ => false,
+        })
+    }
+
+    /// Adds the keys, weights and options of another text index to `self`.
+    fn merge_text_index(&mut self, other: Spec) -> Result<()> {
+        merge_option("name", &mut self.name, other.name)?;
+        merge_option("unique", &mut self.unique, other.unique)?;
+        merge_option("sparse", &mut self.sparse, other.sparse)?;
+        merge_option("default_language", &mut self.default_language, other.default_language)?;
+        merge_option("language_override", &mut self.language_override, other.language_override)?;
+        merge_option("bits", &mut self.bits, other.bits)?;
+        merge_option("max", &mut self.max, other.max)?;
+        merge_option("min", &mut self.min, other.min)?;
+        merge_option("bucket_size", &mut self.bucket_size, other.bucket_size)?;
+        merge_option(
+            "expire_after_seconds",
+            &mut self.expire_after_seconds,
+            other.expire_after_seconds,
+        )?;
+        merge_option("collation", &mut self.collation, other.collation)?;
+        merge_option("hidden", &mut self.hidden, other.hidden)?;
+        merge_option(
+            "partial_filter_expression",
+            &mut self.partial_filter_expression,
+            other.partial_filter_expression,
+        )?;
+        self.keys.extend(other.keys);
+        self.weights.extend(other.weights);
+
+        Ok(())
+    }
+
+    /// Returns `true` if the index has options which the `IndexOptions` of the
+    /// driver can't express, and which thus need `extended_index_options()`.
+    pub fn has_extended_options(&self) -> bool {
+        self.partial_filter_expression.is_some() || self.collation.is_some() || self.hidden.is_some()
+    }
+
+    /// Generates an expression evaluating to the `Document` of the options
+    /// which `has_extended_options()` refers to, in their BSON form.
+    pub fn extended_options(&self) -> TokenStream {
+        let filter = self.partial_filter_expression.as_ref().map(|expr| quote! {
+            let filter: ::avocado::prelude::Document = #expr;
+            avocado_options.insert("partialFilterExpression", filter);
+        });
+        let collation = self.collation.as_ref().map(|collation| {
+            let locale = &collation.locale;
+            let strength = collation.strength.as_ref().map(|n| quote! {
+                avocado_collation.insert("strength", #n);
+            });
+
+            quote! {
+                let mut avocado_collation = ::avocado::prelude::Document::new();
+                avocado_collation.insert("locale", #locale);
+                #strength
+                avocado_options.insert("collation", avocado_collation);
+            }
+        });
+        let hidden = self.hidden.as_ref().map(|b| quote! {
+            avocado_options.insert("hidden", #b);
+        });
+
+        quote!({
+            #[allow(unused_mut)]
+            let mut avocado_options = ::avocado::prelude::Document::new();
+            #filter
+            #collation
+            #hidden
+            avocado_options
+        })
     }
 
     /// Attempts to create an array of `Spec`s from several attributes.
@@ -162,16 +415,15 @@ impl Spec {
     pub fn from_attributes<'a, I>(attrs: I) -> Result<Vec<Spec>>
         where I: IntoIterator<Item = &'a Attribute>
     {
-        attrs
+        let mut errors = Errors::new();
+        let specs = attrs
             .into_iter()
-            .filter_map(|attr| {
-                match Spec::from_attribute(attr) {
-                    Ok(Some(spec)) => Some(Ok(spec)),
-                    Ok(None) => None,
-                    Err(error) => Some(Err(error)),
-                }
-            })
-            .collect()
+            .filter_map(|attr| errors.record(Spec::from_attribute(attr)))
+            .collect();
+
+        errors.finish()?;
+
+        Ok(specs)
     }
 }
 
@@ -192,8 +444,23 @@ impl ToTokens for Spec {
             |n| quote!(bucket_size: Some(#n),)
         );
         let bits = self.bits.as_ref().map(|n| quote!(bits: Some(#n),));
+        let expire_after_seconds = self.expire_after_seconds.as_ref().map(
+            |n| quote!(expire_after_seconds: Some(#n),)
+        );
         let min = self.min.as_ref().map(|x| quote!(min: Some(#x),));
         let max = self.max.as_ref().map(|x| quote!(max: Some(#x),));
+        let weights = if self.weights.is_empty() {
+            None
+        } else {
+            let weighted = self.weights.iter().map(|&(ref field, _)| field);
+            let values = self.weights.iter().map(|&(_, value)| value);
+
+            Some(quote!(weights: Some({
+                let mut avocado_weights = ::avocado::prelude::Document::new();
+                #(avocado_weights.insert(#weighted, #values);)*
+                avocado_weights
+            }),))
+        };
         let fields = self.keys.iter().map(|&(ref field, _)| field);
         let types  = self.keys.iter().map(|&(_, ty)| ty);
 
@@ -214,6 +481,8 @@ impl ToTokens for Spec {
                     #bucket_size
                     #default_language
                     #language_override
+                    #weights
+                    #expire_after_seconds
                     ..Default::default()
                 },
             }
@@ -221,6 +490,86 @@ impl ToTokens for Spec {
     }
 }
 
+impl Collation {
+    /// Parses the contents of a `collation(locale = "...", strength = ...)`
+    /// list. The `locale` is mandatory.
+    fn from_list<I>(list: I) -> Result<Self>
+        where I: IntoIterator<Item = NestedExtMeta>
+    {
+        let mut locale = None;
+        let mut strength = None;
+
+        for nested in list {
+            match nested {
+                NestedExtMeta::Meta(ExtMeta::KeyValue(path, _, literal)) => {
+                    let key = path.colon_sep_str();
+
+                    match key.as_str() {
+                        "locale" => locale = Some(lit_value_as_str(&key, &literal)?),
+                        "strength" => strength = Some(value_as_i32(&key, &literal, 1..=5).spanned(&literal)?),
+                        _ => return err_at!(path, "unknown collation option `{}`", key),
+                    }
+                }
+                _ => return err_fmt!(
+                    "attribute `collation` must contain key-value pairs only, not {:#?}",
+                    nested
+                ),
+            }
+        }
+
+        match locale {
+            Some(name) => Ok(Collation { locale: name, strength }),
+            None => err_msg("a collation must specify a `locale`"),
+        }
+    }
+}
+
+/// Parses the contents of a `weights(field = 10, ...)` list. Like index keys,
+/// the fields may be given as paths, e.g. `author::name = 3`.
+fn weights_from_list<I>(list: I) -> Result<Vec<(String, i32)>>
+    where I: IntoIterator<Item = NestedExtMeta>
+{
+    list.into_iter()
+        .map(|nested| match nested {
+            NestedExtMeta::Meta(ExtMeta::KeyValue(path, _, literal)) => {
+                value_as_i32(&path.colon_sep_str(), &literal, 1..=99_999)
+                    .map(|value| (path.dot_sep_str(), value))
+                    .spanned(&literal)
+            }
+            _ => err_fmt!(
+                "attribute `weights` must contain key-value pairs only, not {:#?}",
+                nested
+            )
+        })
+        .collect()
+}
+
+/// Returns the key of a wildcard index on the fields of the embedded document
+/// at `path`, or on all fields if `path` is empty.
+fn wildcard_key(path: &str) -> String {
+    if path.is_empty() {
+        String::from("$**")
+    } else {
+        format!("{}.$**", path)
+    }
+}
+
+/// Sets an option of a merged index to `other`, unless it has already been
+/// set to a different value.
+fn merge_option<T: PartialEq + Debug>(name: &str, option: &mut Option<T>, other: Option<T>) -> Result<()> {
+    match (option.as_ref(), other) {
+        (Some(current), Some(ref value)) if current != value => err_fmt!(
+            "conflicting values for `{}` of the merged text index: {:?} and {:?}",
+            name, current, value
+        ),
+        (_, Some(value)) => {
+            *option = Some(value);
+            Ok(())
+        }
+        (_, None) => Ok(()),
+    }
+}
+
 /// An index type, applied to a single indexed field.
 #[derive(Debug, Clone, Copy)]
 enum Type {
@@ -238,6 +587,8 @@ enum Type {
     Geo2DSphere,
     /// 2D geospatial index optimized for very small areas.
     GeoHaystack,
+    /// Wildcard index on the fields of an embedded document.
+    Wildcard,
 }
 
 impl FromStr for Type {
@@ -252,6 +603,7 @@ impl FromStr for Type {
             "2d"          => Type::Geo2D,
             "2dsphere"    => Type::Geo2DSphere,
             "geoHaystack" => Type::GeoHaystack,
+            "wildcard"    => Type::Wildcard,
             _ => err_fmt!("unknown index type '{}'", string)?
         })
     }
@@ -267,6 +619,7 @@ impl ToTokens for Type {
             Type::Geo2D       => "2d".to_tokens(tokens),
             Type::Geo2DSphere => "2dsphere".to_tokens(tokens),
             Type::GeoHaystack => "geoHaystack".to_tokens(tokens),
+            Type::Wildcard    => 1.to_tokens(tokens),
         }
     }
 }
@@ -1,4 +1,7 @@
-//! This crate only contains the `#[derive(Doc)]` proc-macro for Avocado.
+//! This crate only contains the `#[derive(Doc)]` and `#[derive(Validate)]`
+//! proc-macros for Avocado, as well as `#[derive(Projection)]` for read models,
+//! and `#[derive(Query)]`, `#[derive(Update)]` and `#[derive(Delete)]` for
+//! parameter structs.
 //! For documentation, please see the main [`avocado`][1] crate.
 //!
 //! [1]: https://docs.rs/avocado
@@ -43,77 +46,528 @@ mod meta;
 mod attr;
 mod case;
 mod index;
+mod name;
 mod option;
+mod validate;
+mod ops;
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use syn::{
-    DeriveInput, Data, Generics, Fields, Ident,
+    DeriveInput, Data, Generics, Fields, Field, Ident, Variant, Visibility,
     Type, Attribute, TypePath, Path, PathSegment,
+    punctuated::Punctuated,
 };
 use self::{
     meta::*,
     case::RenameRule,
     index::Spec,
-    option::DocOptions,
-    error::{ Error, Result, err_msg },
+    name::{ CollectionName, escaped_ident },
+    option::{ DocOptions, CappedSpec },
+    validate::field_checks,
+    ops::{ OpKind, OpOptions, Clauses },
+    error::{ Error, Errors, Result, ResultExt, err_msg },
 };
 
+/// The prefix of the names of the constants holding the serialized names of
+/// the fields of a `Doc` type, generated by `doc_fields_impl()`.
+const FIELD_CONST_PREFIX: &str = "__key_";
+
 /// The top-level entry point of this proc-macro. Only here to be exported
-/// and to handle `Result::Err` return values by `panic!()`ing.
+/// and to turn `Result::Err` return values into `compile_error!()`s.
 #[proc_macro_derive(Doc, attributes(avocado, index, id_type, options))]
 pub fn derive_avocado_doc(input: TokenStream) -> TokenStream {
-    impl_avocado_doc(input).unwrap_or_else(|error| panic!("{}", error))
+    impl_avocado_doc(input).unwrap_or_else(|error| error.to_compile_error()).into()
 }
 
-/// Implements `Doc` for the specified type.
-fn impl_avocado_doc(input: TokenStream) -> Result<TokenStream> {
-    let parsed_ast: DeriveInput = syn::parse(input)?;
-    let ty = parsed_ast.ident;
-    let generics = parsed_ast.generics;
-    let ty_name = serde_renamed_ident(&parsed_ast.attrs, ty.to_string())?;
+/// The entry point of the `Validate` proc-macro.
+#[proc_macro_derive(Validate, attributes(validate))]
+pub fn derive_avocado_validate(input: TokenStream) -> TokenStream {
+    impl_avocado_validate(input).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// The entry point of the `Projection` proc-macro.
+#[proc_macro_derive(Projection, attributes(projection))]
+pub fn derive_avocado_projection(input: TokenStream) -> TokenStream {
+    impl_avocado_projection(input).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// The entry point of the `Query` proc-macro.
+#[proc_macro_derive(Query, attributes(query, filter))]
+pub fn derive_avocado_query(input: TokenStream) -> TokenStream {
+    impl_avocado_op(input, OpKind::Query).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// The entry point of the `Update` proc-macro.
+#[proc_macro_derive(Update, attributes(update, filter))]
+pub fn derive_avocado_update(input: TokenStream) -> TokenStream {
+    impl_avocado_op(input, OpKind::Update).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// The entry point of the `Delete` proc-macro.
+#[proc_macro_derive(Delete, attributes(delete, filter))]
+pub fn derive_avocado_delete(input: TokenStream) -> TokenStream {
+    impl_avocado_op(input, OpKind::Delete).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// Implements `Doc` for the specified type. Independent problems with the
+/// attributes and the fields are all reported, not only the first one.
+fn impl_avocado_doc(input: TokenStream) -> Result<proc_macro2::TokenStream> {
+    let DeriveInput { attrs, vis, ident: ty, generics: raw_generics, data } = syn::parse(input)?;
+    let mut errors = Errors::new();
+    let name = errors.record(
+        serde_renamed_ident(&attrs, ty.to_string()).and_then(
+            |ty_name| CollectionName::from_attributes(&attrs, &ty, ty_name, &raw_generics)
+        )
+    );
+    let name_const = name.name_const();
+    let collection_name = name.collection_name_fn();
+    let uniqueness_check = name.uniqueness_check(&ty);
+    let generics = errors.record(doc_generics(raw_generics));
     let (impl_gen, ty_gen, where_cls) = generics.split_for_impl();
-    let id_ty = raw_id_type(&parsed_ast.attrs)?;
-    let indexes = Spec::from_attributes(&parsed_ast.attrs)?;
-    let options = DocOptions::from_attributes(&parsed_ast.attrs)?;
+    let id_ty = errors.record(raw_id_type(&attrs).map(|id_ty| quote!(#id_ty)));
+    let mut indexes = errors.record(Spec::from_attributes(&attrs));
+    let options = errors.record(DocOptions::from_attributes(&attrs).map(|opts| quote!(#opts)));
+    let capped = errors.record(CappedSpec::from_attributes(&attrs));
+    let (members, variant_impls, field_indexes, struct_fields) = errors.record(match data {
+        Data::Struct(s) => struct_members(s.fields, &attrs).map(
+            |(members, field_indexes, fields)| (members, Vec::new(), field_indexes, Some(fields))
+        ),
+        Data::Enum(e) => enum_members(&ty, &generics, e.variants, &attrs).map(
+            |(members, variant_impls)| (members, variant_impls, Vec::new(), None)
+        ),
+        Data::Union(u) => err_at!(
+            u.union_token,
+            "only a `struct` or an `enum` can be a top-level `Doc`; \
+             consider wrapping this type in a struct"
+        ),
+    });
+    indexes.extend(field_indexes);
     let index_count = indexes.len();
+    let extended_index_options = if indexes.iter().any(Spec::has_extended_options) {
+        let extended_options = indexes.iter().map(Spec::extended_options);
+
+        quote! {
+            fn extended_index_options() -> ::std::vec::Vec<::avocado::prelude::Document> {
+                vec![#(#extended_options),*]
+            }
+        }
+    } else {
+        quote!()
+    };
+    let doc_fields = struct_fields.map_or_else(
+        proc_macro2::TokenStream::new,
+        |fields| doc_fields_impl(&vis, &ty, &generics, &fields),
+    );
+    let timestamp_type = errors.record(timestamp_type(&attrs));
+    let hooks = errors.record(forward_hooks(&attrs));
+    let validate = errors.record(forward_validate(&attrs));
 
-    ensure_only_lifetime_params(&generics)?;
+    // Problems not attributable to a more specific location concern the type.
+    errors.finish().spanned(&ty)?;
 
-    match parsed_ast.data {
-        Data::Struct(s) => {
-            let id_name = name_of_id_field(s.fields, &parsed_ast.attrs)?;
-            let ast = quote! {
-                impl #impl_gen ::avocado::doc::Doc for #ty #ty_gen #where_cls {
-                    const NAME: &'static str = #ty_name;
+    Ok(quote! {
+        impl #impl_gen ::avocado::doc::Doc for #ty #ty_gen #where_cls {
+            #name_const
 
-                    type Id = #id_ty;
+            type Id = #id_ty;
 
-                    fn id(&self) -> ::std::option::Option<&::avocado::uid::Uid<Self>> {
-                        ::std::convert::From::from(&self.#id_name)
-                    }
+            #collection_name
+            #members
+            #timestamp_type
+            #hooks
+            #validate
+
+            fn indexes() -> ::std::vec::Vec<::avocado::prelude::IndexModel> {
+                let mut index_vector = ::std::vec::Vec::with_capacity(#index_count);
+                #(index_vector.push(#indexes);)*
+                index_vector
+            }
+
+            #extended_index_options
+
+            #options
+            #capped
+        }
+
+        impl #impl_gen ::avocado::doc::DocName for #ty #ty_gen #where_cls {
+            fn doc_name() -> ::std::borrow::Cow<'static, str> {
+                <Self as ::avocado::doc::Doc>::collection_name()
+            }
+        }
+
+        #(#variant_impls)*
+
+        #doc_fields
+
+        #uniqueness_check
+    })
+}
+
+/// The parts of the `Doc` impl of a struct which depend on its fields: the
+/// members of the impl, the indexes specified on individual fields, and the
+/// fields along with their serialized names.
+type StructMembers = (proc_macro2::TokenStream, Vec<Spec>, Vec<(Ident, String)>);
+
+/// Generates the members of the `Doc` impl of a struct which depend on its
+/// fields: the special field name constants and the `id()` and `set_id()`
+/// methods. Also returns the indexes specified on individual fields, and the
+/// fields along with their serialized names.
+fn struct_members(raw_fields: Fields, attrs: &[Attribute]) -> Result<StructMembers> {
+    let named_fields = serialized_fields(raw_fields, attrs, "Doc")?;
+    let mut errors = Errors::new();
+    let indexes = errors.record(Spec::from_fields(&named_fields));
+    let fields = field_idents(named_fields);
+    let id_name = errors.record(name_of_id_field(&fields).map(Some));
+    let version = field_name_const(
+        "VERSION_FIELD",
+        errors.record(special_field(&fields, attrs, "version_field", "version"))
+    );
+    let soft_delete = field_name_const(
+        "SOFT_DELETE_FIELD",
+        errors.record(special_field(&fields, attrs, "soft_delete", "soft-delete"))
+    );
+    let created_at = field_name_const(
+        "CREATED_AT_FIELD",
+        errors.record(special_field(&fields, attrs, "created_at", "creation timestamp"))
+    );
+    let updated_at = field_name_const(
+        "UPDATED_AT_FIELD",
+        errors.record(special_field(&fields, attrs, "updated_at", "modification timestamp"))
+    );
+
+    errors.finish()?;
+
+    let members = quote! {
+        #version
+        #soft_delete
+        #created_at
+        #updated_at
+
+        fn id(&self) -> ::std::option::Option<&::avocado::uid::Uid<Self>> {
+            ::std::convert::From::from(&self.#id_name)
+        }
+
+        fn set_id(&mut self, id: ::avocado::uid::Uid<Self>) {
+            self.#id_name = ::std::convert::From::from(id);
+        }
+    };
+
+    Ok((members, indexes, fields))
+}
+
+/// Generates the `DocFields` impl of a struct, along with the hidden type
+/// holding the serialized names of its fields, which has the same visibility
+/// as the struct itself. Every name is held by two constants: one named after
+/// the Rust identifier of the field, for the `filter!()` macro, and another
+/// one named after the serialized name, for looking up the field by that.
+fn doc_fields_impl(
+    vis: &Visibility,
+    ty: &Ident,
+    generics: &Generics,
+    fields: &[(Ident, String)],
+) -> proc_macro2::TokenStream {
+    let (impl_gen, ty_gen, where_cls) = generics.split_for_impl();
+    let fields_ty = Ident::new(&format!("__AvocadoFields_{}", ty), ty.span());
+    let consts = fields.iter().map(|&(ref ident, ref name)| {
+        let const_name = Ident::new(&escaped_ident(FIELD_CONST_PREFIX, name), ident.span());
+
+        quote! {
+            pub const #ident: &'static str = #name;
+            pub const #const_name: &'static str = #name;
+        }
+    });
+
+    quote! {
+        #[doc(hidden)]
+        #[allow(non_camel_case_types, dead_code)]
+        #vis struct #fields_ty;
+
+        #[allow(non_upper_case_globals, dead_code)]
+        impl #fields_ty {
+            #(#consts)*
+        }
+
+        impl #impl_gen ::avocado::doc::DocFields for #ty #ty_gen #where_cls {
+            type Fields = #fields_ty;
+        }
+    }
+}
+
+/// The parts of the `Doc` impl of an enum generated for a single variant:
+/// the arms of the `match` in `id()` and `set_id()`, and the `DocVariant`
+/// impl of the payload type of a newtype variant.
+type VariantMembers = (
+    proc_macro2::TokenStream,
+    proc_macro2::TokenStream,
+    Option<proc_macro2::TokenStream>,
+);
+
+/// Generates the `id()` and `set_id()` methods of the `Doc` impl of an
+/// internally-tagged enum, along with the `DocVariant` impls of the payload
+/// types of its newtype variants. Every variant must carry the `_id`: struct
+/// variants directly, newtype variants in the field of the payload named by
+/// `#[avocado(id_field = "...")]`, or in its `_id` field by default.
+fn enum_members(
+    ty: &Ident,
+    generics: &Generics,
+    variants: Punctuated<Variant, Token![,]>,
+    attrs: &[Attribute],
+) -> Result<(proc_macro2::TokenStream, Vec<proc_macro2::TokenStream>)> {
+    let tag_field = match serde_name_value(attrs, "tag")? {
+        Some(nv) => value_as_str(&nv)?,
+        None => return err_msg(
+            "an `enum` must be internally tagged, e.g. `#[serde(tag = \"type\")]`, \
+             in order to be a `Doc`"
+        ),
+    };
+    let mut errors = Errors::new();
+
+    if let Some(nv) = serde_name_value(attrs, "content")? {
+        errors.push(Error::new(
+            "an adjacently tagged `enum` can't be a `Doc`; remove `content = \"...\"`"
+        ).with_span_of(&nv));
+    }
+
+    for &key in &["version_field", "soft_delete", "created_at", "updated_at"] {
+        if let Some(nv) = errors.record(avocado_name_value(attrs, key)) {
+            errors.push(Error::new(
+                format!("`#[avocado({} = \"...\")]` is not supported for an `enum`", key)
+            ).with_span_of(&nv));
+        }
+    }
+
+    let rename_rule = errors.record(rename_all_rule(attrs));
+    let mut id_arms = Vec::with_capacity(variants.len());
+    let mut set_id_arms = Vec::with_capacity(variants.len());
+    let mut variant_impls = Vec::new();
+
+    for variant in variants {
+        let variant_name = variant.ident.clone();
+        let result = variant_members(ty, generics, variant, &tag_field, rename_rule)
+            .spanned(&variant_name)
+            .map(Some);
+
+        if let Some((id_arm, set_id_arm, variant_impl)) = errors.record(result) {
+            id_arms.push(id_arm);
+            set_id_arms.push(set_id_arm);
+            variant_impls.extend(variant_impl);
+        }
+    }
+
+    errors.finish()?;
 
-                    fn set_id(&mut self, id: ::avocado::uid::Uid<Self>) {
-                        self.#id_name = ::std::convert::From::from(id);
+    let members = quote! {
+        fn id(&self) -> ::std::option::Option<&::avocado::uid::Uid<Self>> {
+            match *self {
+                #(#id_arms,)*
+            }
+        }
+
+        fn set_id(&mut self, id: ::avocado::uid::Uid<Self>) {
+            match *self {
+                #(#set_id_arms)*
+            }
+        }
+    };
+
+    Ok((members, variant_impls))
+}
+
+/// Generates the parts of the `Doc` impl of an enum specific to `variant`.
+/// `tag_field` is the name of the discriminator field, while `rename_rule`
+/// is the `rename_all` rule of the enum, if any.
+fn variant_members(
+    ty: &Ident,
+    generics: &Generics,
+    variant: Variant,
+    tag_field: &str,
+    rename_rule: Option<RenameRule>,
+) -> Result<VariantMembers> {
+    let (impl_gen, ty_gen, where_cls) = generics.split_for_impl();
+    let name = variant.ident;
+    let tag = serde_renamed_ident(
+        &variant.attrs,
+        rename_rule.map_or_else(
+            || name.to_string(),
+            |rule| rule.apply_to_variant(name.to_string()),
+        ),
+    )?;
+
+    match variant.fields {
+        Fields::Named(_) => {
+            let fields = serialized_field_names(variant.fields, &variant.attrs)?;
+            let id_name = name_of_id_field(&fields)?;
+
+            Ok((
+                quote! {
+                    #ty::#name { #id_name: ref id, .. } => ::std::convert::From::from(id)
+                },
+                quote! {
+                    #ty::#name { #id_name: ref mut field, .. } => {
+                        *field = ::std::convert::From::from(id);
                     }
+                },
+                None,
+            ))
+        }
+        Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => {
+            let payload_ty = &fields.unnamed[0].ty;
+            let id_name: Ident = match avocado_name_value(&variant.attrs, "id_field")? {
+                Some(nv) => syn::parse_str(&value_as_str(&nv)?).map_err(Error::from).spanned(&nv.lit)?,
+                None => Ident::new("_id", Span::call_site()),
+            };
 
-                    fn indexes() -> ::std::vec::Vec<::avocado::prelude::IndexModel> {
-                        let mut index_vector = ::std::vec::Vec::with_capacity(#index_count);
-                        #(index_vector.push(#indexes);)*
-                        index_vector
+            Ok((
+                quote! {
+                    #ty::#name(ref payload) => ::std::convert::From::from(&payload.#id_name)
+                },
+                quote! {
+                    #ty::#name(ref mut payload) => {
+                        payload.#id_name = ::std::convert::From::from(id);
+                    }
+                },
+                Some(quote! {
+                    impl #impl_gen ::avocado::doc::DocVariant<#ty #ty_gen> for #payload_ty #where_cls {
+                        const TAG_FIELD: &'static str = #tag_field;
+                        const TAG: &'static str = #tag;
                     }
+                }),
+            ))
+        }
+        _ => err_fmt!(
+            "variant `{}` must be a struct or a newtype variant carrying the `_id`", name
+        ),
+    }
+}
 
-                    #options
-                }
-            };
-            Ok(ast.into())
-        },
-        _ => err_msg(
-            "only a `struct` can be a top-level `Doc`; consider wrapping this type in a struct"
+/// Implements `Validate` for the specified type, based on the
+/// `#[validate(...)]` attributes of its fields. Problems with the attributes
+/// of all fields are reported, not only the first one.
+fn impl_avocado_validate(input: TokenStream) -> Result<proc_macro2::TokenStream> {
+    let parsed_ast: DeriveInput = syn::parse(input)?;
+    let ty = parsed_ast.ident;
+    let (impl_gen, ty_gen, where_cls) = parsed_ast.generics.split_for_impl();
+    let fields = match parsed_ast.data {
+        Data::Struct(s) => serialized_fields(s.fields, &parsed_ast.attrs, "Validate").spanned(&ty)?,
+        _ => return err_at!(ty, "`Validate` can only be derived for a `struct`"),
+    };
+    let mut errors = Errors::new();
+    let mut checks = Vec::new();
+
+    for (field, name) in &fields {
+        if let Some(ref ident) = field.ident {
+            checks.extend(errors.record(field_checks(ident, &field.ty, name, &field.attrs).spanned(field)));
+        }
+    }
+
+    errors.finish()?;
+
+    Ok(quote! {
+        impl #impl_gen ::avocado::validation::Validate for #ty #ty_gen #where_cls {
+            fn validate(&self) -> ::avocado::error::Result<()> {
+                #[allow(unused_mut)]
+                let mut errors = ::avocado::validation::ValidationErrors::new();
+                #(#checks)*
+                errors.into_result()
+            }
+        }
+    })
+}
+
+/// Implements `Projection` for a struct holding a subset of the fields of the
+/// `Doc` type specified by `#[projection(of = "...")]`. The projection document
+/// is built from the field name constants of the `DocFields` impl of the `Doc`
+/// type, so a field that doesn't exist in the `Doc` is a compile-time error.
+fn impl_avocado_projection(input: TokenStream) -> Result<proc_macro2::TokenStream> {
+    let DeriveInput { attrs, ident: ty, generics, data, .. } = syn::parse(input)?;
+    let (impl_gen, ty_gen, where_cls) = generics.split_for_impl();
+    let mut errors = Errors::new();
+    let doc_ty = errors.record(projection_source(&attrs).map(Some));
+    let fields = errors.record(match data {
+        Data::Struct(s) => serialized_fields(s.fields, &attrs, "Projection").map(field_idents),
+        _ => err_at!(ty, "`Projection` can only be derived for a `struct`"),
+    });
+
+    errors.finish().spanned(&ty)?;
+
+    let inserts = fields.iter().map(|&(ref ident, ref name)| {
+        let const_name = Ident::new(&escaped_ident(FIELD_CONST_PREFIX, name), ident.span());
+
+        quote! {
+            projection.insert(<#doc_ty as ::avocado::doc::DocFields>::Fields::#const_name, true);
+        }
+    });
+    let exclude_id = if fields.iter().any(|&(_, ref name)| name == "_id") {
+        quote!()
+    } else {
+        quote! {
+            projection.insert("_id", false);
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_gen ::avocado::ops::Projection<#doc_ty> for #ty #ty_gen #where_cls {
+            fn projection() -> ::avocado::prelude::Document {
+                let mut projection = ::avocado::prelude::Document::new();
+                #(#inserts)*
+                #exclude_id
+                projection
+            }
+        }
+    })
+}
+
+/// Returns the `Doc` type of which a `Projection` holds a subset of fields,
+/// as specified by the `#[projection(of = "...")]` attribute.
+fn projection_source(attrs: &[Attribute]) -> Result<Type> {
+    match projection_name_value(attrs, "of")? {
+        Some(nv) => value_as_str(&nv)
+            .and_then(|ty_str| syn::parse_str(&ty_str).map_err(Error::from))
+            .spanned(&nv.lit),
+        None => err_msg(
+            "a `Projection` requires the `Doc` type it is a projection of, \
+             as in `#[projection(of = \"...\")]`"
         ),
     }
 }
 
+/// Implements `Query`, `Update` or `Delete` for a parameter struct, based on
+/// its type-level attribute and the `#[filter(...)]` and `#[update(...)]`
+/// attributes of its fields.
+fn impl_avocado_op(input: TokenStream, kind: OpKind) -> Result<proc_macro2::TokenStream> {
+    let DeriveInput { attrs, ident: ty, generics, data, .. } = syn::parse(input)?;
+    let (impl_gen, ty_gen, where_cls) = generics.split_for_impl();
+    let trait_name = kind.trait_name();
+    let mut errors = Errors::new();
+    let op_options = errors.record(OpOptions::from_attributes(kind, &attrs));
+    let clauses = errors.record(match data {
+        Data::Struct(s) => serialized_fields(s.fields, &attrs, trait_name).and_then(
+            |fields| Clauses::from_fields(kind, &fields)
+        ),
+        _ => err_at!(ty, "`{}` can only be derived for a `struct`", trait_name),
+    });
+
+    errors.finish().spanned(&ty)?;
+
+    let trait_ident = Ident::new(trait_name, Span::call_site());
+    let doc_ty = op_options.doc_type();
+    let filter = clauses.filter_fn();
+    let update = match kind {
+        OpKind::Update => clauses.update_fn(),
+        OpKind::Query | OpKind::Delete => quote!(),
+    };
+    let members = op_options.members(kind);
+
+    Ok(quote! {
+        impl #impl_gen ::avocado::ops::#trait_ident<#doc_ty> for #ty #ty_gen #where_cls {
+            #filter
+            #update
+            #members
+        }
+    })
+}
+
 /// Returns the collection name based on the the type name,
 /// taking Serde renaming into account as well.
 fn serde_renamed_ident(attrs: &[Attribute], ident: String) -> Result<String> {
@@ -158,27 +612,63 @@ fn raw_id_type(attrs: &[Attribute]) -> Result<Type> {
 
 /// Returns an error if there is no field serializing as `_id` or if there
 /// are more than 1 of them. (The `_id` field must be unambiguous and unique.)
-fn name_of_id_field(fields: Fields, attrs: &[Attribute]) -> Result<Ident> {
+fn name_of_id_field(fields: &[(Ident, String)]) -> Result<Ident> {
+    let mut id_fields = fields.iter().filter(|&&(_, ref name)| name == "_id");
+    let id_name = id_fields.next().map(|&(ref ident, _)| ident.clone());
+
+    if let Some(&(ref duplicate, _)) = id_fields.next() {
+        return err_at!(duplicate, "more than one fields serialize as `_id`");
+    }
+
+    id_name.ok_or_else(
+        || Error::new("a `Doc` must contain a field serialized as `_id`")
+    )
+}
+
+/// Returns the identifiers of the fields of a struct along with the names
+/// they are serialized as. Fields that are never serialized nor deserialized
+/// are omitted. Returns an error if the struct doesn't have named fields.
+fn serialized_field_names(fields: Fields, attrs: &[Attribute]) -> Result<Vec<(Ident, String)>> {
+    serialized_fields(fields, attrs, "Doc").map(field_idents)
+}
+
+/// Replaces the fields returned by `serialized_fields()` with their names.
+fn field_idents(named: Vec<(Field, String)>) -> Vec<(Ident, String)> {
+    named
+        .into_iter()
+        .filter_map(|(field, name)| field.ident.map(|ident| (ident, name)))
+        .collect()
+}
+
+/// Returns the named fields of a struct along with the names they are
+/// serialized as, omitting fields that are never serialized nor deserialized.
+/// `trait_name` is the name of the derived trait, used in the error message
+/// if the struct doesn't have named fields.
+fn serialized_fields(
+    fields: Fields,
+    attrs: &[Attribute],
+    trait_name: &str,
+) -> Result<Vec<(Field, String)>> {
     let named = match fields {
-        Fields::Named(fields) => fields.named,
-        _ => return err_msg("a `Doc` must be a struct with named fields"),
-    };
-    let rename_attr = serde_name_value(attrs, "rename_all")?;
-    let rename_rule: Option<RenameRule> = match rename_attr {
-        None => None,
-        Some(kv) => Some(value_as_str(&kv)?.parse()?)
+        Fields::Named(named_fields) => named_fields.named,
+        Fields::Unnamed(unnamed_fields) => return err_at!(
+            unnamed_fields, "a `{}` must be a struct with named fields", trait_name
+        ),
+        Fields::Unit => return err_fmt!("a `{}` must be a struct with named fields", trait_name),
     };
-    let mut id_name = None;
+    let rename_rule = rename_all_rule(attrs)?;
+    let mut errors = Errors::new();
+    let mut names = Vec::with_capacity(named.len());
 
     for field in named {
         // The field isn't inspected if it's never serialized or deserialized.
-        if field_is_always_skipped(&field.attrs)? {
+        if errors.record(field_is_always_skipped(&field.attrs).spanned(&field)) {
             continue;
         }
 
         // The original identifier of the field name.
         let ident = match field.ident {
-            Some(ident) => ident,
+            Some(ref ident) => ident.clone(),
             None => continue,
         };
 
@@ -192,36 +682,168 @@ fn name_of_id_field(fields: Fields, attrs: &[Attribute]) -> Result<Ident> {
         // The final field name is the exact name specified in the immediate
         // `#[serde(rename = "...")]` attribute applied directly to the field,
         // or the potentially-`rename_all`'d name, if the former doesn't exist.
-        let field_name = serde_renamed_ident(&field.attrs, rename_all_ident)?;
+        match serde_renamed_ident(&field.attrs, rename_all_ident).spanned(&field) {
+            Ok(field_name) => names.push((field, field_name)),
+            Err(error) => errors.push(error),
+        }
+    }
 
-        if field_name == "_id" {
-            if id_name.is_some() {
-                return err_msg("more than one fields serialize as `_id`");
-            } else {
-                id_name = Some(ident);
-            }
+    errors.finish()?;
+
+    Ok(names)
+}
+
+/// Returns the renaming rule specified by `#[serde(rename_all = "...")]`, if any.
+fn rename_all_rule(attrs: &[Attribute]) -> Result<Option<RenameRule>> {
+    match serde_name_value(attrs, "rename_all")? {
+        None => Ok(None),
+        Some(kv) => value_as_str(&kv)?.parse().map(Some).spanned(&kv.lit),
+    }
+}
+
+/// Returns the name of the field specified by an attribute of the form
+/// `#[avocado(key = "...")]`, e.g. the version or the soft-delete field,
+/// if any, ensuring that a field with that (serialized) name actually exists.
+/// `description` is used in the error message.
+fn special_field(
+    fields: &[(Ident, String)],
+    attrs: &[Attribute],
+    key: &str,
+    description: &str,
+) -> Result<Option<String>> {
+    let nv = match avocado_name_value(attrs, key)? {
+        Some(nv) => nv,
+        None => return Ok(None),
+    };
+    let name = value_as_str(&nv)?;
+
+    if fields.iter().any(|&(_, ref field_name)| *field_name == name) {
+        Ok(Some(name))
+    } else {
+        err_at!(nv.lit, "{} field `{}` doesn't exist", description, name)
+    }
+}
+
+/// Generates the definition of an associated constant of type
+/// `Option<&'static str>` holding the name of a special field, if any.
+fn field_name_const(const_name: &str, field: Option<String>) -> proc_macro2::TokenStream {
+    let const_ident = Ident::new(const_name, Span::call_site());
+
+    match field {
+        Some(name) => quote! {
+            const #const_ident: ::std::option::Option<&'static str> =
+                ::std::option::Option::Some(#name);
+        },
+        None => quote!(),
+    }
+}
+
+/// Generates the definition of the `TIMESTAMP_TYPE` associated constant
+/// based on the `#[avocado(timestamp_type = "date" | "timestamp")]`
+/// attribute, if any.
+fn timestamp_type(attrs: &[Attribute]) -> Result<proc_macro2::TokenStream> {
+    let nv = match avocado_name_value(attrs, "timestamp_type")? {
+        Some(nv) => nv,
+        None => return Ok(quote!()),
+    };
+    let name = value_as_str(&nv)?;
+    let variant = match name.as_str() {
+        "date" => quote!(Date),
+        "timestamp" => quote!(Timestamp),
+        _ => return err_at!(
+            nv.lit, "timestamp type must be `date` or `timestamp`, found `{}`", name
+        ),
+    };
+
+    Ok(quote! {
+        const TIMESTAMP_TYPE: ::avocado::literal::DateTimeType =
+            ::avocado::literal::DateTimeType::#variant;
+    })
+}
+
+/// If the `#[avocado(hooks)]` attribute is present, generates the lifecycle
+/// hook methods of `Doc` so that they forward to the `DocHooks` impl.
+fn forward_hooks(attrs: &[Attribute]) -> Result<proc_macro2::TokenStream> {
+    if !has_avocado_word(attrs, "hooks")? {
+        return Ok(quote!());
+    }
+
+    Ok(quote! {
+        fn before_insert(
+            &self,
+            document: &mut ::avocado::prelude::Document,
+        ) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_insert(self, document)
+        }
+
+        fn after_insert(&self) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_inserted(self)
         }
+
+        fn before_update(
+            &self,
+            document: &mut ::avocado::prelude::Document,
+        ) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_update(self, document)
+        }
+
+        fn after_update(&self) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_updated(self)
+        }
+
+        fn before_delete(&self) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_delete(self)
+        }
+
+        fn after_delete(&self) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_deleted(self)
+        }
+
+        fn after_load(&mut self) -> ::avocado::error::Result<()> {
+            ::avocado::doc::DocHooks::on_load(self)
+        }
+    })
+}
+
+/// If the `#[avocado(validate)]` attribute is present, generates the
+/// `Doc::validate_entity()` method so that it forwards to the `Validate` impl.
+fn forward_validate(attrs: &[Attribute]) -> Result<proc_macro2::TokenStream> {
+    if !has_avocado_word(attrs, "validate")? {
+        return Ok(quote!());
     }
 
-    id_name.ok_or_else(
-        || Error::new("a `Doc` must contain a field serialized as `_id`")
-    )
+    Ok(quote! {
+        fn validate_entity(&self) -> ::avocado::error::Result<()> {
+            ::avocado::validation::Validate::validate(self)
+        }
+    })
 }
 
-/// Returns `Ok` if the generics only contain lifetime parameters.
-/// Returns `Err` if there are also type and/or const parameters.
-fn ensure_only_lifetime_params(generics: &Generics) -> Result<()> {
-    let make_error = |param_type| err_fmt!(
-        "`Doc` can't be derived for a type that is generic over {} parameters",
-        param_type
-    );
+/// Returns the generics of the `Doc` impl: every type parameter is required
+/// to implement `DocName`, for computing the collection name, as well as
+/// `Serialize` and `DeserializeOwned`, so that the type itself implements
+/// Serde's traits, as `Doc` requires. Returns an error if there are const
+/// parameters.
+fn doc_generics(mut generics: Generics) -> Result<Generics> {
+    if let Some(param) = generics.const_params().next() {
+        return err_at!(param, "`Doc` can't be derived for a type that is generic over const parameters");
+    }
 
-    if generics.type_params().next().is_some() {
-        return make_error("type");
+    let params: Vec<_> = generics.type_params().map(|param| param.ident.clone()).collect();
+
+    if params.is_empty() {
+        return Ok(generics);
     }
-    if generics.const_params().next().is_some() {
-        return make_error("const");
+
+    let where_cls = generics.make_where_clause();
+
+    for param in params {
+        where_cls.predicates.push(parse_quote! {
+            #param: ::avocado::doc::DocName
+                  + ::avocado::serde::Serialize
+                  + ::avocado::serde::de::DeserializeOwned
+        });
     }
 
-    Ok(())
+    Ok(generics)
 }
@@ -9,7 +9,7 @@ use syn::{ Attribute, Meta, MetaList, NestedMeta, MetaNameValue, Lit };
 use syn::synom::Synom;
 use crate::{
     attr::{ ExtMeta, NestedExtMeta, PathExt },
-    error::{ Error, Result },
+    error::{ Error, Result, ResultExt },
 };
 
 /// Utilities for working with ranges.
@@ -78,8 +78,8 @@ fn meta(attrs: &[Attribute], name: &str, key: &str) -> Option<Meta> {
 fn name_value(attrs: &[Attribute], name: &str, key: &str) -> Result<Option<MetaNameValue>> {
     match meta(attrs, name, key) {
         Some(Meta::NameValue(name_value)) => Ok(Some(name_value)),
-        Some(_) => {
-            err_fmt!("attribute must have form `#[{}({} = \"...\")]`", name, key)
+        Some(meta) => {
+            err_at!(meta, "attribute must have form `#[{}({} = \"...\")]`", name, key)
         }
         None => Ok(None),
     }
@@ -89,8 +89,8 @@ fn name_value(attrs: &[Attribute], name: &str, key: &str) -> Result<Option<MetaN
 fn has_meta_word(attrs: &[Attribute], name: &str, key: &str) -> Result<bool> {
     match meta(attrs, name, key) {
         Some(Meta::Word(_)) => Ok(true),
-        Some(_) => {
-            err_fmt!("attribute must have form `#[{}({})]`", name, key)
+        Some(meta) => {
+            err_at!(meta, "attribute must have form `#[{}({})]`", name, key)
         }
         None => Ok(false),
     }
@@ -106,26 +106,39 @@ pub fn has_serde_word(attrs: &[Attribute], key: &str) -> Result<bool> {
     has_meta_word(attrs, "serde", key)
 }
 
+/// Search for an `#[avocado(...)]` attribute, provided that it's a name-value pair.
+pub fn avocado_name_value(attrs: &[Attribute], key: &str) -> Result<Option<MetaNameValue>> {
+    name_value(attrs, "avocado", key)
+}
+
+/// Search for an `#[avocado(...)]` attribute, provided that it's a single word.
+pub fn has_avocado_word(attrs: &[Attribute], key: &str) -> Result<bool> {
+    has_meta_word(attrs, "avocado", key)
+}
+
+/// Search for an `#[avocado(...)]` attribute with the given key, in any form.
+pub fn avocado_meta(attrs: &[Attribute], key: &str) -> Option<Meta> {
+    meta(attrs, "avocado", key)
+}
+
+/// Search for a `#[projection(...)]` attribute, provided that it's a name-value pair.
+pub fn projection_name_value(attrs: &[Attribute], key: &str) -> Result<Option<MetaNameValue>> {
+    name_value(attrs, "projection", key)
+}
+
 /// Extracts a boolean value from an attribute value.
 /// Returns `Err` if the value is not a `LitBool`.
 pub fn value_as_bool(key: &str, lit: &Lit) -> Result<bool> {
     match *lit {
         Lit::Bool(ref lit) => Ok(lit.value),
-        _ => err_fmt!("value for key `{}` must be a bool", key)
+        _ => err_at!(lit, "value for key `{}` must be a bool", key)
     }
 }
 
 /// Extracts a string value from an attribute value.
 /// Returns `Err` if the value is not a `LitStr` nor a valid UTF-8 `LitByteStr`.
 pub fn value_as_str(nv: &MetaNameValue) -> Result<String> {
-    match nv.lit {
-        Lit::Str(ref string) => Ok(string.value()),
-        Lit::ByteStr(ref string) => {
-            String::from_utf8(string.value()).map_err(Into::into)
-        }
-        _ => err_fmt!("value for key `{}` must be a valid UTF-8 string",
-                      nv.ident.to_string())
-    }
+    lit_value_as_str(&nv.ident.to_string(), &nv.lit)
 }
 
 /// Similar to `value_as_str()`, but for `ExtMeta`-related usage.
@@ -133,9 +146,9 @@ pub fn lit_value_as_str(key: &str, lit: &Lit) -> Result<String> {
     match *lit {
         Lit::Str(ref string) => Ok(string.value()),
         Lit::ByteStr(ref string) => {
-            String::from_utf8(string.value()).map_err(Into::into)
+            String::from_utf8(string.value()).map_err(Error::from).spanned(lit)
         }
-        _ => err_fmt!("value for key `{}` must be a valid UTF-8 string", key)
+        _ => err_at!(lit, "value for key `{}` must be a valid UTF-8 string", key)
     }
 }
 
@@ -170,6 +183,17 @@ pub fn value_as_i32<R>(key: &str, lit: &Lit, range: R) -> Result<i32>
     }
 }
 
+/// Extracts a non-negative integer value from an attribute value.
+/// Accepts string-valued attributes as well, for symmetry with `value_as_i32`.
+pub fn value_as_u64(key: &str, lit: &Lit) -> Result<u64> {
+    match *lit {
+        Lit::Int(ref lit) => Ok(lit.value()),
+        Lit::Str(ref lit) => lit.value().parse().map_err(Into::into),
+        Lit::ByteStr(ref lit) => str::from_utf8(&lit.value())?.parse().map_err(Into::into),
+        _ => err_fmt!("value for key `{}` must be a non-negative integer", key)
+    }
+}
+
 /// Extracts an `f64` value from an attribute value.
 /// Ensures that the resulting value is contained in the specified `range`.
 ///
@@ -207,18 +231,13 @@ pub fn list_into_names_and_values<T, I>(outer_name: &str, list: I) -> Result<Vec
     list.into_iter()
         .map(|nested| match nested {
             NestedExtMeta::Meta(ExtMeta::KeyValue(path, _, literal)) => {
-                let val_str = match literal {
-                    Lit::Str(ref s) => s.value(),
-                    Lit::ByteStr(ref s) => String::from_utf8(s.value())?,
-                    _ => return err_fmt!(
-                        "value for key `{}` must be a valid UTF-8 string",
-                        path.colon_sep_str()
-                    )
-                };
+                let val_str = lit_value_as_str(&path.colon_sep_str(), &literal)?;
+
                 val_str
                     .parse()
                     .map_err(Into::into)
                     .map(|value| (path.dot_sep_str(), value))
+                    .spanned(&literal)
             }
             _ => err_fmt!(
                 "attribute `{}` must contain key-value pairs only, not {:#?}",
@@ -238,6 +257,7 @@ pub fn literal_value_for_name<T: Synom>(attrs: &[Attribute], name: &str) -> Resu
                 if nv.ident == name {
                     value_as_str(&nv)
                         .and_then(|s| syn::parse_str(&s).map_err(Into::into))
+                        .spanned(&nv.lit)
                         .into()
                 } else {
                     None
@@ -246,7 +266,7 @@ pub fn literal_value_for_name<T: Synom>(attrs: &[Attribute], name: &str) -> Resu
             Meta::Word(ident) | Meta::List(MetaList { ident, .. }) => {
                 if ident == name {
                     Some(
-                        err_fmt!("attribute must have form `#[{} = ...]`", name)
+                        err_at!(ident, "attribute must have form `#[{} = ...]`", name)
                     )
                 } else {
                     None
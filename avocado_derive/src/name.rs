@@ -0,0 +1,290 @@
+//! Computing the collection name of a `Doc` type, i.e. the value of
+//! `Doc::NAME` and, for generic types, `Doc::collection_name()`.
+
+use proc_macro2::TokenStream;
+use syn::{ Attribute, Generics, Ident };
+use crate::{
+    meta::*,
+    case::RenameRule,
+    error::{ Result, ResultExt, err_msg },
+};
+
+/// The `#[avocado(...)]` options which specify the collection name. Only
+/// one of `collection` and `name_template` may be given, and neither of
+/// them may be combined with `collection_case` or `pluralize`.
+const NAME_OPTIONS: &[&str] = &["collection", "name_template", "collection_case", "pluralize"];
+
+/// A part of a collection name template.
+#[derive(Debug, Clone)]
+enum Segment {
+    /// Literal text, copied verbatim.
+    Literal(String),
+    /// A `{P}` placeholder, replaced by the `DocName` of type parameter `P`.
+    Param(Ident),
+}
+
+/// The collection name of a `Doc` type, as specified by the type name, the
+/// `#[serde(rename = "...")]` attribute, the `collection`, `collection_case`
+/// and `pluralize` options of the `#[avocado(...)]` attribute, and, for
+/// types with type parameters, the `#[avocado(name_template = "...")]`
+/// attribute.
+#[derive(Debug, Clone, Default)]
+pub struct CollectionName {
+    /// The unexpanded template, which is also the value of `Doc::NAME`.
+    template: String,
+    /// The parsed template.
+    segments: Vec<Segment>,
+    /// Whether other `Doc` types of the same module may use the collection.
+    shared: bool,
+}
+
+impl CollectionName {
+    /// Parses the name template of a type. `ty_name` is the (possibly
+    /// renamed) name of the type, which is the collection name unless it
+    /// is given explicitly or a naming convention is requested. The default
+    /// template of a generic type is the collection name followed by a `_{P}`
+    /// placeholder for every type parameter.
+    pub fn from_attributes(
+        attrs: &[Attribute],
+        ty: &Ident,
+        ty_name: String,
+        generics: &Generics,
+    ) -> Result<Self> {
+        let params: Vec<_> = generics.type_params().map(|param| param.ident.clone()).collect();
+        let template_nv = avocado_name_value(attrs, "name_template")?;
+        let template = match template_nv {
+            Some(ref nv) => {
+                reject_combination(attrs, "name_template")?;
+                value_as_str(nv)?
+            }
+            None => {
+                let base_name = base_name(attrs, ty, ty_name)?;
+                params.iter().fold(base_name, |name, param| format!("{}_{{{}}}", name, param))
+            }
+        };
+        let result = Self::from_template(template, &params);
+        let mut name = match template_nv {
+            Some(ref nv) => result.spanned(&nv.lit)?,
+            None => result?,
+        };
+
+        name.shared = has_avocado_word(attrs, "shared_collection")?;
+
+        Ok(name)
+    }
+
+    /// Parses a template and checks that it mentions every type parameter.
+    fn from_template(template: String, params: &[Ident]) -> Result<Self> {
+        let segments = parse_template(&template, params)?;
+
+        for param in params {
+            let mentioned = segments.iter().any(|segment| match *segment {
+                Segment::Param(ref ident) => ident == param,
+                Segment::Literal(_) => false,
+            });
+
+            if !mentioned {
+                return err_fmt!(
+                    "name template `{}` doesn't mention type parameter `{}`, \
+                     so all instantiations would share the same collection",
+                    template, param
+                );
+            }
+        }
+
+        Ok(CollectionName { template, segments, shared: false })
+    }
+
+    /// Generates the definition of the `NAME` associated constant.
+    pub fn name_const(&self) -> TokenStream {
+        let template = &self.template;
+
+        quote! {
+            const NAME: &'static str = #template;
+        }
+    }
+
+    /// Generates a hidden constant named after the collection, next to the
+    /// type, so that the compiler rejects two types in the same module (or
+    /// block) which claim the same collection. Unlike an exported item, this
+    /// doesn't clash with types defined locally in different functions. The
+    /// collection names of generic types are only known at runtime, and
+    /// `#[avocado(shared_collection)]` opts out of the check deliberately.
+    pub fn uniqueness_check(&self, ty: &Ident) -> TokenStream {
+        if self.shared || !self.is_static() {
+            return quote!();
+        }
+
+        let const_name = Ident::new(
+            &escaped_ident("__AVOCADO_COLLECTION_NAME_", &self.template),
+            ty.span(),
+        );
+
+        quote! {
+            #[doc(hidden)]
+            #[allow(dead_code, non_upper_case_globals)]
+            const #const_name: () = ();
+        }
+    }
+
+    /// Returns `true` if the name doesn't contain placeholders.
+    fn is_static(&self) -> bool {
+        self.segments.iter().all(|segment| match *segment {
+            Segment::Literal(_) => true,
+            Segment::Param(_) => false,
+        })
+    }
+
+    /// Generates the `collection_name()` method, which substitutes the
+    /// `DocName` of the type arguments into the template. Types without
+    /// placeholders in their name rely on the default implementation.
+    pub fn collection_name_fn(&self) -> TokenStream {
+        if self.is_static() {
+            return quote!();
+        }
+
+        let pushes = self.segments.iter().map(|segment| match *segment {
+            Segment::Literal(ref text) => quote! {
+                name.push_str(#text);
+            },
+            Segment::Param(ref param) => quote! {
+                name.push_str(&<#param as ::avocado::doc::DocName>::doc_name());
+            },
+        });
+
+        quote! {
+            fn collection_name() -> ::std::borrow::Cow<'static, str> {
+                let mut name = ::std::string::String::new();
+                #(#pushes)*
+                ::std::borrow::Cow::Owned(name)
+            }
+        }
+    }
+}
+
+/// Encodes a collection or field name as an identifier starting with `prefix`,
+/// so that distinct names map to distinct identifiers: ASCII letters and
+/// digits are kept, underscores are doubled, and every other character is
+/// replaced by `_x<hex code point>_`.
+pub fn escaped_ident(prefix: &str, name: &str) -> String {
+    let mut escaped = String::from(prefix);
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            escaped.push(c);
+        } else if c == '_' {
+            escaped.push_str("__");
+        } else {
+            escaped.push_str(&format!("_x{:x}_", u32::from(c)));
+        }
+    }
+
+    escaped
+}
+
+/// Returns the collection name of a non-generic type, or the part of the
+/// default template of a generic type preceding the placeholders.
+fn base_name(attrs: &[Attribute], ty: &Ident, ty_name: String) -> Result<String> {
+    let case_nv = avocado_name_value(attrs, "collection_case")?;
+    let pluralize = has_avocado_word(attrs, "pluralize")?;
+
+    if let Some(nv) = avocado_name_value(attrs, "collection")? {
+        reject_combination(attrs, "collection")?;
+        return value_as_str(&nv);
+    }
+
+    if case_nv.is_none() && !pluralize {
+        return Ok(ty_name);
+    }
+
+    // A naming convention is applied to the name of the Rust type, so that
+    // the collection name doesn't depend on the serialized name of the type.
+    let name = match case_nv {
+        Some(ref nv) => {
+            let case = value_as_str(nv)?;
+            let rule: RenameRule = match case.parse() {
+                Ok(rule) => rule,
+                Err(_) => return err_at!(nv.lit, "unknown `collection_case` rule: {}", case),
+            };
+            rule.apply_to_variant(ty.to_string())
+        }
+        None => ty.to_string(),
+    };
+
+    Ok(if pluralize { pluralized(name) } else { name })
+}
+
+/// Ensures that no other collection name option accompanies `explicit`,
+/// which specifies the collection name verbatim.
+fn reject_combination(attrs: &[Attribute], explicit: &str) -> Result<()> {
+    for &option in NAME_OPTIONS.iter().filter(|&&option| option != explicit) {
+        if let Some(meta) = avocado_meta(attrs, option) {
+            return err_at!(meta, "`{}` can't be combined with `{}`", option, explicit);
+        }
+    }
+
+    Ok(())
+}
+
+/// Naively forms the English plural of a (possibly multi-word) name by
+/// inflecting its last word, preserving the case of the last letter.
+fn pluralized(mut name: String) -> String {
+    let lower = name.to_lowercase();
+    let uppercase = name.chars().last().map_or(false, char::is_uppercase);
+    let suffix = if ["s", "x", "z", "ch", "sh"].iter().any(|ending| lower.ends_with(ending)) {
+        "es"
+    } else if lower.ends_with('y') && !lower[..lower.len() - 1].ends_with(|c| "aeiou".contains(c)) {
+        name.pop();
+        "ies"
+    } else {
+        "s"
+    };
+
+    if uppercase {
+        name + &suffix.to_uppercase()
+    } else {
+        name + suffix
+    }
+}
+
+/// Splits a template into literal text and `{P}` placeholders, where each
+/// `P` must be one of `params`.
+fn parse_template(template: &str, params: &[Ident]) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+        if rest[start..].starts_with('}') {
+            return err_fmt!("unmatched `}}` in name template `{}`", template);
+        }
+
+        let end = match rest[start..].find('}') {
+            Some(offset) => start + offset,
+            None => return err_fmt!("unmatched `{{` in name template `{}`", template),
+        };
+        let placeholder = &rest[start + 1..end];
+        let param = match params.iter().find(|param| *param == placeholder) {
+            Some(param) => param.clone(),
+            None => return err_fmt!(
+                "`{{{}}}` in name template `{}` isn't a type parameter", placeholder, template
+            ),
+        };
+
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].into()));
+        }
+
+        segments.push(Segment::Param(param));
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.into()));
+    }
+
+    if segments.is_empty() {
+        return err_msg("collection name must not be empty");
+    }
+
+    Ok(segments)
+}
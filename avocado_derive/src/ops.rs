@@ -0,0 +1,624 @@
+//! Parsing the attributes of parameter structs and generating the members
+//! of `#[derive(Query)]`, `#[derive(Update)]` and `#[derive(Delete)]` impls.
+
+use proc_macro2::{ TokenStream, TokenTree };
+use quote::ToTokens;
+use syn::{ Attribute, Field, Ident, Type, Path, PathArguments };
+use syn::{ Meta, MetaList, NestedMeta, Lit };
+use crate::{
+    meta::value_as_str,
+    error::{ Error, Errors, Result, ResultExt },
+};
+
+/// The filter operators which can be specified in `#[filter(...)]` field
+/// attributes, along with the MongoDB operators they correspond to.
+const FILTER_OPERATORS: &[(&str, &str)] = &[
+    ("eq",  "$eq"),
+    ("ne",  "$ne"),
+    ("gt",  "$gt"),
+    ("gte", "$gte"),
+    ("lt",  "$lt"),
+    ("lte", "$lte"),
+    ("in",  "$in"),
+    ("nin", "$nin"),
+];
+
+/// The update operators which can be specified in `#[update(...)]` field
+/// attributes, along with the MongoDB operators they correspond to.
+const UPDATE_OPERATORS: &[(&str, &str)] = &[
+    ("set",        "$set"),
+    ("inc",        "$inc"),
+    ("mul",        "$mul"),
+    ("min",        "$min"),
+    ("max",        "$max"),
+    ("push",       "$push"),
+    ("add_to_set", "$addToSet"),
+    ("pull",       "$pull"),
+];
+
+/// The integer types some values of which can't be represented in BSON, whose
+/// largest integer type is `i64`.
+const NON_BSON_INTEGERS: &[&str] = &["u64", "usize", "u128", "i128"];
+
+/// The operation trait being derived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    /// `#[derive(Query)]`
+    Query,
+    /// `#[derive(Update)]`
+    Update,
+    /// `#[derive(Delete)]`
+    Delete,
+}
+
+impl OpKind {
+    /// The name of the trait in the `avocado::ops` module.
+    pub fn trait_name(self) -> &'static str {
+        match self {
+            OpKind::Query => "Query",
+            OpKind::Update => "Update",
+            OpKind::Delete => "Delete",
+        }
+    }
+
+    /// The name of the type-level attribute configuring the impl.
+    fn attr_name(self) -> &'static str {
+        match self {
+            OpKind::Query => "query",
+            OpKind::Update => "update",
+            OpKind::Delete => "delete",
+        }
+    }
+
+    /// The keys accepted by the type-level attribute.
+    fn keys(self) -> &'static [&'static str] {
+        match self {
+            OpKind::Query => &["doc", "output", "options", "transform", "include_deleted"],
+            OpKind::Update | OpKind::Delete => &["doc", "options"],
+        }
+    }
+}
+
+/// The settings of an operation impl, as specified by the type-level
+/// attribute, e.g. `#[query(doc = "User", output = "UserSummary")]`.
+#[derive(Debug, Clone, Default)]
+pub struct OpOptions {
+    /// The `Doc` type the operation acts on. Always present after parsing.
+    doc: Option<Type>,
+    /// The `Output` type of a query, if not the `Doc` type itself.
+    output: Option<Type>,
+    /// The function called with `&self` for implementing `options()`.
+    options: Option<Path>,
+    /// The function called with the raw document for implementing `transform()`.
+    transform: Option<Path>,
+    /// Whether a query takes soft-deleted documents into account.
+    include_deleted: bool,
+}
+
+impl OpOptions {
+    /// Parses the type-level attribute belonging to the derived trait.
+    /// The `doc` key is mandatory.
+    pub fn from_attributes(kind: OpKind, attrs: &[Attribute]) -> Result<Self> {
+        let attr_name = kind.attr_name();
+        let mut op_options = OpOptions::default();
+        let mut errors = Errors::new();
+
+        for attr in attrs {
+            let list = match attr.interpret_meta() {
+                Some(Meta::List(list)) => if list.ident == attr_name {
+                    list
+                } else {
+                    continue
+                },
+                Some(ref meta) if meta.name() == attr_name => {
+                    errors.push(Error::new(format!(
+                        "attribute must have form `#[{}(doc = \"...\", ...)]`", attr_name
+                    )).with_span_of(attr));
+                    continue
+                }
+                _ => continue,
+            };
+
+            for nested in &list.nested {
+                errors.record(op_options.add_nested_meta(kind, nested));
+            }
+        }
+
+        if op_options.doc.is_none() {
+            errors.push(Error::new(format!(
+                "`{}` requires the `Doc` type, as in `#[{}(doc = \"...\")]`",
+                kind.trait_name(),
+                attr_name,
+            )));
+        }
+
+        errors.finish()?;
+
+        Ok(op_options)
+    }
+
+    /// Sets a single option based on `key = "..."` or a bare `key`.
+    fn add_nested_meta(&mut self, kind: OpKind, nested: &NestedMeta) -> Result<()> {
+        let attr_name = kind.attr_name();
+        let meta = match *nested {
+            NestedMeta::Meta(ref meta) => meta,
+            NestedMeta::Literal(_) => return err_at!(
+                nested, "attribute must have form `#[{}(key = \"...\", ...)]`", attr_name
+            ),
+        };
+        let key = meta.name();
+
+        if !kind.keys().iter().any(|&name| key == name) {
+            return err_at!(key, "unknown `#[{}]` option `{}`", attr_name, key);
+        }
+
+        match *meta {
+            Meta::Word(_) if key == "include_deleted" => {
+                self.include_deleted = true;
+                Ok(())
+            }
+            Meta::NameValue(ref nv) if key != "include_deleted" => {
+                let value = value_as_str(nv)?;
+
+                match key.to_string().as_str() {
+                    "doc" => set_once(&mut self.doc, &key, parse_value(&value, &nv.lit)?),
+                    "output" => set_once(&mut self.output, &key, parse_value(&value, &nv.lit)?),
+                    "options" => set_once(&mut self.options, &key, parse_value(&value, &nv.lit)?),
+                    _ => set_once(&mut self.transform, &key, parse_value(&value, &nv.lit)?),
+                }
+            }
+            _ if key == "include_deleted" => err_at!(
+                meta, "attribute must have form `#[{}(include_deleted)]`", attr_name
+            ),
+            _ => err_at!(
+                meta, "attribute must have form `#[{}({} = \"...\")]`", attr_name, key
+            ),
+        }
+    }
+
+    /// The `Doc` type, for the trait in the header of the impl.
+    pub fn doc_type(&self) -> TokenStream {
+        let doc = &self.doc;
+        quote!(#doc)
+    }
+
+    /// Generates the members of the impl of the given trait, apart from
+    /// `filter()` and `update()`.
+    pub fn members(&self, kind: OpKind) -> TokenStream {
+        match kind {
+            OpKind::Query => self.query_members(),
+            OpKind::Update | OpKind::Delete => self.options_fn(
+                quote!(::avocado::prelude::WriteConcern)
+            ),
+        }
+    }
+
+    /// Generates `Output`, `transform()`, `after_load()`, `options()` and
+    /// `include_deleted()` of a `Query` impl. Unless the `Output` type is
+    /// overridden, `after_load()` forwards to `Doc::after_load()`.
+    fn query_members(&self) -> TokenStream {
+        let output = match self.output {
+            Some(ref output) => quote! {
+                type Output = #output;
+            },
+            None => {
+                let doc = &self.doc;
+
+                quote! {
+                    type Output = #doc;
+
+                    fn after_load(output: &mut Self::Output) -> ::avocado::error::Result<()> {
+                        ::avocado::doc::Doc::after_load(output)
+                    }
+                }
+            }
+        };
+        let transform = match self.transform {
+            Some(ref path) => quote! {
+                fn transform(
+                    raw: ::avocado::prelude::Document,
+                ) -> ::avocado::error::Result<::avocado::prelude::Bson> {
+                    #path(raw)
+                }
+            },
+            None => quote!(),
+        };
+        let options = self.options_fn(quote!(::avocado::prelude::FindOptions));
+        let include_deleted = if self.include_deleted {
+            quote! {
+                fn include_deleted(&self) -> bool {
+                    true
+                }
+            }
+        } else {
+            quote!()
+        };
+
+        quote! {
+            #output
+            #transform
+            #options
+            #include_deleted
+        }
+    }
+
+    /// Generates the `options()` method if a function was specified for it.
+    fn options_fn(&self, return_ty: TokenStream) -> TokenStream {
+        match self.options {
+            Some(ref path) => quote! {
+                fn options(&self) -> #return_ty {
+                    #path(self)
+                }
+            },
+            None => quote!(),
+        }
+    }
+}
+
+/// Stores `value` in `slot`, unless the option was already specified.
+fn set_once<T>(slot: &mut Option<T>, key: &Ident, value: T) -> Result<()> {
+    if slot.is_some() {
+        return err_at!(key, "duplicate option `{}`", key);
+    }
+
+    *slot = Some(value);
+    Ok(())
+}
+
+/// Parses the string value of an option as a type or a path.
+fn parse_value<T: syn::synom::Synom>(value: &str, lit: &Lit) -> Result<T> {
+    syn::parse_str(value).map_err(Error::from).spanned(lit)
+}
+
+/// A single clause of the generated filter or update document, corresponding
+/// to a field of the parameter struct.
+#[derive(Debug, Clone)]
+struct Clause {
+    /// The identifier of the field in the parameter struct.
+    ident: Ident,
+    /// The key in the `Doc` type the clause refers to.
+    key: String,
+    /// The MongoDB operator, e.g. `$gte` or `$set`.
+    operator: &'static str,
+    /// Whether the field is an `Option`, the clause being omitted if `None`.
+    optional: bool,
+}
+
+impl Clause {
+    /// Generates the statement inserting the value of the field into the
+    /// document named `target` under the key `key`.
+    fn insert_into(&self, target: &Ident, key: &str) -> TokenStream {
+        let ident = &self.ident;
+        let field_name = ident.to_string();
+
+        if self.optional {
+            quote! {
+                if let ::std::option::Option::Some(ref value) = self.#ident {
+                    #target.insert(#key, ::avocado::ops::field_operand(#field_name, value));
+                }
+            }
+        } else {
+            quote! {
+                #target.insert(#key, ::avocado::ops::field_operand(#field_name, &self.#ident));
+            }
+        }
+    }
+}
+
+/// The clauses of the filter and of the update document of an operation.
+#[derive(Debug, Clone, Default)]
+pub struct Clauses {
+    /// Clauses of the filter, from fields without an `#[update]` attribute.
+    filter: Vec<Clause>,
+    /// Clauses of the update, from fields with an `#[update]` attribute.
+    update: Vec<Clause>,
+}
+
+impl Clauses {
+    /// Collects the clauses from the fields of the parameter struct, given
+    /// along with their serialized names. An `Update` must have at least one
+    /// field with an `#[update(...)]` attribute.
+    pub fn from_fields(kind: OpKind, fields: &[(Field, String)]) -> Result<Self> {
+        let mut clauses = Clauses::default();
+        let mut errors = Errors::new();
+
+        for &(ref field, ref name) in fields {
+            errors.record(clauses.add_field(field, name).spanned(field));
+        }
+
+        errors.record(check_duplicates(&clauses.filter, "filter"));
+        errors.record(check_duplicates(&clauses.update, "update"));
+
+        if kind == OpKind::Update && clauses.update.is_empty() {
+            errors.push(Error::new(
+                "an `Update` must have at least one field with an `#[update(...)]` attribute"
+            ));
+        }
+
+        errors.finish()?;
+
+        Ok(clauses)
+    }
+
+    /// Adds the clause corresponding to a single field, if any. Fields without
+    /// a `#[filter]` or `#[update]` attribute are compared for equality.
+    fn add_field(&mut self, field: &Field, name: &str) -> Result<()> {
+        let ident = match field.ident {
+            Some(ref ident) => ident.clone(),
+            None => return Ok(()),
+        };
+        let optional = is_option(&field.ty);
+        let filter = operator_attr(&field.attrs, "filter")?;
+        let update = operator_attr(&field.attrs, "update")?;
+
+        if !filter.as_ref().map_or(false, is_skip) {
+            check_representable(&field.ty)?;
+        }
+
+        match (filter, update) {
+            (Some(_), Some(list)) => err_at!(
+                list, "a field can't have both a `#[filter]` and an `#[update]` attribute"
+            ),
+            (None, Some(list)) => {
+                let (operator, key) = operator_and_key(&list, UPDATE_OPERATORS, name)?;
+
+                self.update.push(Clause { ident, key, operator, optional });
+                Ok(())
+            }
+            (Some(list), None) => {
+                if is_skip(&list) {
+                    return Ok(());
+                }
+
+                let (operator, key) = operator_and_key(&list, FILTER_OPERATORS, name)?;
+
+                self.filter.push(Clause { ident, key, operator, optional });
+                Ok(())
+            }
+            (None, None) => {
+                let key = name.to_owned();
+
+                self.filter.push(Clause { ident, key, operator: "$eq", optional });
+                Ok(())
+            }
+        }
+    }
+
+    /// Generates the `filter()` method. Clauses referring to the same key
+    /// are combined into a single operator document, e.g. a lower and an
+    /// upper bound. A lone equality is expressed without the `$eq` operator.
+    pub fn filter_fn(&self) -> TokenStream {
+        let filter = Ident::new("filter", proc_macro2::Span::call_site());
+        let operators = Ident::new("operators", proc_macro2::Span::call_site());
+        let statements = group_by(&self.filter, |clause| &clause.key).into_iter().map(
+            |(key, group)| match group.as_slice() {
+                &[clause] if clause.operator == "$eq" => clause.insert_into(&filter, key),
+                _ => {
+                    let inserts = group.iter().map(
+                        |clause| clause.insert_into(&operators, clause.operator)
+                    );
+
+                    quote! {
+                        {
+                            let mut #operators = ::avocado::prelude::Document::new();
+                            #(#inserts)*
+                            if !#operators.is_empty() {
+                                #filter.insert(#key, #operators);
+                            }
+                        }
+                    }
+                }
+            }
+        );
+
+        quote! {
+            fn filter(&self) -> ::avocado::prelude::Document {
+                #[allow(unused_mut)]
+                let mut #filter = ::avocado::prelude::Document::new();
+                #(#statements)*
+                #filter
+            }
+        }
+    }
+
+    /// Generates the `update()` method, with one document per update operator.
+    /// Operators all of whose fields are `None` are omitted.
+    pub fn update_fn(&self) -> TokenStream {
+        let update = Ident::new("update", proc_macro2::Span::call_site());
+        let fields = Ident::new("fields", proc_macro2::Span::call_site());
+        let statements = group_by(&self.update, |clause| &clause.operator).into_iter().map(
+            |(operator, group)| {
+                let inserts = group.iter().map(|clause| clause.insert_into(&fields, &clause.key));
+
+                quote! {
+                    {
+                        let mut #fields = ::avocado::prelude::Document::new();
+                        #(#inserts)*
+                        if !#fields.is_empty() {
+                            #update.insert(#operator, #fields);
+                        }
+                    }
+                }
+            }
+        );
+
+        quote! {
+            fn update(&self) -> ::avocado::prelude::Document {
+                let mut #update = ::avocado::prelude::Document::new();
+                #(#statements)*
+                #update
+            }
+        }
+    }
+}
+
+/// Returns the list of the `#[filter(...)]` or `#[update(...)]` attribute
+/// of a field, if any.
+fn operator_attr(attrs: &[Attribute], attr_name: &str) -> Result<Option<MetaList>> {
+    let mut lists = attrs.iter().filter_map(|attr| match attr.interpret_meta() {
+        Some(meta) => if meta.name() == attr_name {
+            Some((attr, meta))
+        } else {
+            None
+        },
+        None => None,
+    });
+
+    let list = match lists.next() {
+        Some((_, Meta::List(list))) => list,
+        Some((attr, _)) => return err_at!(
+            attr, "attribute must have form `#[{}(operator = \"...\")]`", attr_name
+        ),
+        None => return Ok(None),
+    };
+
+    if let Some((attr, _)) = lists.next() {
+        return err_at!(attr, "duplicate `#[{}]` attribute", attr_name);
+    }
+
+    Ok(Some(list))
+}
+
+/// Returns `true` if the attribute is `#[filter(skip)]`.
+fn is_skip(list: &MetaList) -> bool {
+    match list.nested.iter().next() {
+        Some(&NestedMeta::Meta(Meta::Word(ref word))) => list.nested.len() == 1 && word == "skip",
+        _ => false,
+    }
+}
+
+/// Returns an error if the type of a field mentions an integer type which
+/// can't always be converted to BSON, since the generated `filter()` and
+/// `update()` methods have no way of reporting a failed conversion.
+fn check_representable(ty: &Type) -> Result<()> {
+    match non_bson_integer(ty.into_token_stream()) {
+        Some(ident) => err_at!(
+            ident,
+            "`{}` can't always be represented in BSON; use `i64` or a smaller type",
+            ident
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Finds the first of `NON_BSON_INTEGERS` in the tokens of a type, including
+/// its type arguments, tuple and array elements.
+fn non_bson_integer(tokens: TokenStream) -> Option<Ident> {
+    tokens.into_iter().find_map(|tree| match tree {
+        TokenTree::Ident(ident) => {
+            let name = ident.to_string();
+
+            if NON_BSON_INTEGERS.contains(&name.as_str()) {
+                Some(ident)
+            } else {
+                None
+            }
+        }
+        TokenTree::Group(group) => non_bson_integer(group.stream()),
+        TokenTree::Punct(_) | TokenTree::Literal(_) => None,
+    })
+}
+
+/// Parses `#[filter(operator = "key")]` or `#[filter(operator)]`, the latter
+/// meaning that the key is `name`, the serialized name of the field itself.
+fn operator_and_key(
+    list: &MetaList,
+    operators: &[(&str, &'static str)],
+    name: &str,
+) -> Result<(&'static str, String)> {
+    let attr_name = list.ident.to_string();
+    let mut nested_metas = list.nested.iter();
+
+    let (ident, key) = match (nested_metas.next(), nested_metas.next()) {
+        (Some(&NestedMeta::Meta(Meta::Word(ref word))), None) => (word, name.to_owned()),
+        (Some(&NestedMeta::Meta(Meta::NameValue(ref nv))), None) => (&nv.ident, value_as_str(nv)?),
+        (_, Some(extra)) => return err_at!(
+            extra, "`#[{}]` must specify exactly one operator", attr_name
+        ),
+        _ => return err_at!(
+            list, "attribute must have form `#[{}(operator = \"...\")]`", attr_name
+        ),
+    };
+
+    match operators.iter().find(|&&(operator_name, _)| ident == operator_name) {
+        Some(&(_, operator)) => Ok((operator, key)),
+        None => {
+            let names: Vec<_> = operators
+                .iter()
+                .map(|&(operator_name, _)| format!("`{}`", operator_name))
+                .collect();
+
+            err_at!(
+                ident,
+                "unknown `#[{}]` operator `{}`; expected one of {}",
+                attr_name,
+                ident,
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// Returns an error if a key is used more than once with the same operator
+/// in a filter, or more than once at all in an update, since MongoDB rejects
+/// conflicting updates of the same field.
+fn check_duplicates(clauses: &[Clause], attr_name: &str) -> Result<()> {
+    let mut errors = Errors::new();
+
+    for (index, clause) in clauses.iter().enumerate() {
+        let is_duplicate = clauses[..index].iter().any(|previous| {
+            previous.key == clause.key && (
+                attr_name == "update" || previous.operator == clause.operator
+            )
+        });
+
+        if is_duplicate {
+            let message = if attr_name == "update" {
+                format!("key `{}` is updated by more than one field", clause.key)
+            } else {
+                format!("operator `{}` is applied to key `{}` more than once", clause.operator, clause.key)
+            };
+
+            errors.push(Error::new(message).with_span_of(&clause.ident));
+        }
+    }
+
+    errors.finish()
+}
+
+/// Groups clauses by the given key, preserving the order of first occurrence.
+fn group_by<'a, K, F>(clauses: &'a [Clause], key_fn: F) -> Vec<(&'a K, Vec<&'a Clause>)>
+    where K: PartialEq + ?Sized + 'a,
+          F: Fn(&'a Clause) -> &'a K,
+{
+    let mut groups: Vec<(&K, Vec<&Clause>)> = Vec::new();
+
+    for clause in clauses {
+        let key = key_fn(clause);
+
+        match groups.iter_mut().find(|&&mut (group_key, _)| group_key == key) {
+            Some(&mut (_, ref mut group)) => group.push(clause),
+            None => groups.push((key, vec![clause])),
+        }
+    }
+
+    groups
+}
+
+/// Returns `true` if the type is syntactically an `Option<_>`. Type aliases
+/// of `Option` are therefore not recognized.
+pub fn is_option(ty: &Type) -> bool {
+    match *ty {
+        Type::Path(ref type_path) if type_path.qself.is_none() => {
+            type_path.path.segments.last().map_or(false, |pair| {
+                let segment = pair.value();
+
+                segment.ident == "Option" && match segment.arguments {
+                    PathArguments::AngleBracketed(_) => true,
+                    _ => false,
+                }
+            })
+        }
+        _ => false,
+    }
+}
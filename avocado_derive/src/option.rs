@@ -6,7 +6,10 @@ use proc_macro2::{ TokenStream, Span };
 use syn::{ Attribute, Ident, Path, PathSegment };
 use syn::{ Meta, NestedMeta, MetaNameValue, Lit };
 use quote::{ ToTokens, TokenStreamExt };
-use crate::error::{ Result, err_msg };
+use crate::{
+    meta::{ avocado_meta, value_as_u64 },
+    error::{ Error, Errors, Result, ResultExt },
+};
 
 /// This type can tokenize itself in a way that, when quoted inside
 /// an `impl Doc for T`, will expand to a bunch of option functions
@@ -89,35 +92,45 @@ impl DocOptions {
             })
             .next();
 
+        let mut errors = Errors::new();
+
         if let Some(metas) = metas {
             for meta in metas {
-                match meta {
-                    NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-                        ident,
-                        lit: Lit::Str(path_str),
-                        ..
-                    })) => {
-                        let path: Path = path_str.parse()?;
-                        let fn_name = ident.to_string();
-
-                        match options.0.get_mut(&fn_name) {
-                            Some(&mut (_, ref mut path_ptr)) => {
-                                *path_ptr = Some(path);
-                            }
-                            None => return err_fmt!(
-                                "no option method named `Doc::{}()`", fn_name
-                            )
-                        }
-                    },
-                    _ => return err_msg(
-                        "attribute must have form `#[options(fn_name = \"path\", ...)]`"
-                    )
-                }
+                errors.record(options.add_nested_meta(meta));
             }
         }
 
+        errors.finish()?;
+
         Ok(options)
     }
+
+    /// Sets the callee of a single option method based on `fn_name = "path"`.
+    fn add_nested_meta(&mut self, meta: NestedMeta) -> Result<()> {
+        match meta {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Str(path_str),
+                ..
+            })) => {
+                let path: Path = path_str.parse().map_err(Error::from).spanned(&path_str)?;
+                let fn_name = ident.to_string();
+
+                match self.0.get_mut(&fn_name) {
+                    Some(&mut (_, ref mut path_ptr)) => {
+                        *path_ptr = Some(path);
+                        Ok(())
+                    }
+                    None => err_at!(
+                        ident, "no option method named `Doc::{}()`", fn_name
+                    )
+                }
+            },
+            _ => err_at!(
+                meta, "attribute must have form `#[options(fn_name = \"path\", ...)]`"
+            )
+        }
+    }
 }
 
 impl ToTokens for DocOptions {
@@ -151,3 +164,83 @@ fn fn_to_tokens(fn_name: &str, type_path_components: &[&str], callee_path: &Path
         }
     });
 }
+
+/// Size limits of a capped collection, as specified by the
+/// `#[avocado(capped(size = ..., max = ...))]` attribute.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CappedSpec {
+    /// The maximal size of the collection in bytes.
+    size: u64,
+    /// The maximal number of documents in the collection.
+    max: Option<u64>,
+}
+
+impl CappedSpec {
+    /// Parses the `capped` option out of the `#[avocado(...)]` attribute.
+    pub fn from_attributes(attrs: &[Attribute]) -> Result<Option<Self>> {
+        let list = match avocado_meta(attrs, "capped") {
+            Some(Meta::List(list)) => list,
+            Some(meta) => return err_at!(
+                meta, "attribute must have form `#[avocado(capped(size = ..., max = ...))]`"
+            ),
+            None => return Ok(None),
+        };
+        let mut size = None;
+        let mut spec = CappedSpec::default();
+        let mut errors = Errors::new();
+
+        for nested in &list.nested {
+            match *nested {
+                NestedMeta::Meta(Meta::NameValue(MetaNameValue { ref ident, ref lit, .. })) => {
+                    let key = ident.to_string();
+                    let value = match value_as_u64(&key, lit).spanned(lit) {
+                        Ok(value) => value,
+                        Err(error) => {
+                            errors.push(error);
+                            continue;
+                        }
+                    };
+
+                    match key.as_str() {
+                        "size" => size = Some(value),
+                        "max" => spec.max = Some(value),
+                        _ => errors.record(err_at!(
+                            ident, "unknown capped collection option `{}`", key
+                        )),
+                    }
+                }
+                _ => errors.record(err_at!(
+                    nested, "`capped` must contain key-value pairs only, e.g. `size = 4096`"
+                )),
+            }
+        }
+
+        match size {
+            Some(value) => spec.size = value,
+            None => errors.record(err_at!(list, "capped collections require a `size`")),
+        }
+
+        errors.finish()?;
+
+        Ok(Some(spec))
+    }
+}
+
+impl ToTokens for CappedSpec {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let size = self.size;
+        let max = match self.max {
+            Some(max) => quote!(::std::option::Option::Some(#max)),
+            None => quote!(::std::option::Option::None),
+        };
+
+        tokens.append_all(quote! {
+            fn capped_options() -> ::std::option::Option<::avocado::doc::CappedOptions> {
+                ::std::option::Option::Some(::avocado::doc::CappedOptions {
+                    size: #size,
+                    max: #max,
+                })
+            }
+        });
+    }
+}
@@ -0,0 +1,151 @@
+//! Parsing `#[validate(...)]` field attributes and generating the
+//! corresponding checks for `#[derive(Validate)]`.
+
+use proc_macro2::{ TokenStream, Literal };
+use syn::{ Attribute, Ident, Type, Meta, NestedMeta, MetaNameValue, Lit, Expr };
+use crate::{
+    meta::{ value_as_u64, lit_value_as_str },
+    ops::is_option,
+    error::{ Error, Errors, Result, ResultExt, err_msg },
+};
+
+/// Generates the statements checking every rule specified for a field,
+/// recording violations in a local `ValidationErrors` named `errors`.
+/// `name` is the serialized name of the field, used for reporting. If the
+/// field is an `Option`, the rules are checked against the wrapped value,
+/// and `None` passes all of them.
+pub fn field_checks(ident: &Ident, ty: &Type, name: &str, attrs: &[Attribute]) -> Result<Vec<TokenStream>> {
+    let mut checks = Vec::new();
+    let mut errors = Errors::new();
+    let optional = is_option(ty);
+    let value = if optional {
+        quote!(value)
+    } else {
+        quote!(&self.#ident)
+    };
+
+    for attr in attrs {
+        let validate_list = match attr.interpret_meta() {
+            Some(Meta::List(meta_list)) => if meta_list.ident == "validate" {
+                meta_list
+            } else {
+                continue
+            },
+            Some(Meta::Word(ref word)) if word == "validate" => {
+                errors.push(
+                    Error::new("attribute must have form `#[validate(rule, ...)]`").with_span_of(attr)
+                );
+                continue
+            }
+            _ => continue,
+        };
+
+        for nested in &validate_list.nested {
+            match rule_check(&value, nested).spanned(nested) {
+                Ok(call) => checks.push(quote! {
+                    if let ::std::option::Option::Some(message) = #call {
+                        errors.add(#name, message);
+                    }
+                }),
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    errors.finish()?;
+
+    if optional && !checks.is_empty() {
+        Ok(vec![quote! {
+            if let ::std::option::Option::Some(ref value) = self.#ident {
+                #(#checks)*
+            }
+        }])
+    } else {
+        Ok(checks)
+    }
+}
+
+/// Generates the expression checking a single validation rule against
+/// `value`, a reference to the field, which evaluates to an `Option` of
+/// the violation message.
+fn rule_check(value: &TokenStream, nested: &NestedMeta) -> Result<TokenStream> {
+    let call = match *nested {
+        NestedMeta::Meta(Meta::Word(ref word)) if word == "non_empty" => quote! {
+            ::avocado::validation::check_non_empty(#value)
+        },
+        NestedMeta::Meta(Meta::NameValue(MetaNameValue { ident: ref key, ref lit, .. }))
+            if key == "regex" =>
+        {
+            let pattern = lit_value_as_str("regex", lit)?;
+            quote! {
+                ::avocado::validation::check_regex(#value, #pattern)?
+            }
+        }
+        NestedMeta::Meta(Meta::List(ref rule_list)) => {
+            let rule = rule_list.ident.to_string();
+            let mut min = quote!(::std::option::Option::None);
+            let mut max = quote!(::std::option::Option::None);
+
+            if rule != "length" && rule != "range" {
+                return err_at!(rule_list.ident, "unknown validation rule `{}`", rule);
+            }
+
+            for bound in &rule_list.nested {
+                let (key, lit) = match *bound {
+                    NestedMeta::Meta(Meta::NameValue(ref nv)) => (&nv.ident, &nv.lit),
+                    _ => return err_at!(
+                        bound, "`{}` must contain key-value pairs only, e.g. `min = 1`", rule
+                    ),
+                };
+                let key_str = key.to_string();
+                let value = match rule.as_str() {
+                    "length" => length_bound(&key_str, lit),
+                    _ => range_bound(&key_str, lit),
+                }.spanned(lit)?;
+                let option = quote!(::std::option::Option::Some(#value));
+
+                match key_str.as_str() {
+                    "min" => min = option,
+                    "max" => max = option,
+                    _ => return err_at!(key, "unknown bound `{}` for rule `{}`", key_str, rule),
+                }
+            }
+
+            match rule.as_str() {
+                "length" => quote! {
+                    ::avocado::validation::check_length(#value, #min, #max)
+                },
+                _ => quote! {
+                    ::avocado::validation::check_range(#value, #min, #max)
+                },
+            }
+        }
+        _ => return err_msg(
+            "validation rule must be one of `length(min = ..., max = ...)`, \
+             `range(min = ..., max = ...)`, `regex = \"...\"` or `non_empty`"
+        ),
+    };
+
+    Ok(call)
+}
+
+/// Returns an unsuffixed integer literal for a bound of a `length` rule,
+/// so that it is inferred to be a `usize`.
+fn length_bound(key: &str, lit: &Lit) -> Result<TokenStream> {
+    let value = Literal::u64_unsuffixed(value_as_u64(key, lit)?);
+    Ok(quote!(#value))
+}
+
+/// Returns the expression for a bound of a `range` rule. Numeric literals
+/// are used verbatim, while strings are parsed as an expression, since that
+/// is currently the only way to specify a negative number in an attribute.
+fn range_bound(key: &str, lit: &Lit) -> Result<TokenStream> {
+    match *lit {
+        Lit::Int(_) | Lit::Float(_) => Ok(quote!(#lit)),
+        Lit::Str(_) | Lit::ByteStr(_) => {
+            let expr: Expr = syn::parse_str(&lit_value_as_str(key, lit)?)?;
+            Ok(quote!(#expr))
+        }
+        _ => err_fmt!("value for key `{}` must be a number", key),
+    }
+}